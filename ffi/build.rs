@@ -0,0 +1,30 @@
+// 生成 sgflt.h 到 OUT_DIR，不修改源码目录
+// 提交的 include/sgflt.h 由测试 test_header_up_to_date 检查是否与生成结果一致
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let (Ok(dir), Ok(out)) = (
+        std::env::var("CARGO_MANIFEST_DIR"),
+        std::env::var("OUT_DIR"),
+    ) else {
+        println!("cargo:warning=CARGO_MANIFEST_DIR or OUT_DIR is not set, skip sgflt.h");
+        return;
+    };
+    let config = match cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("cargo:warning=load cbindgen.toml failed: {}", e);
+            return;
+        }
+    };
+    match cbindgen::Builder::new()
+        .with_crate(dir.as_str())
+        .with_config(config)
+        .generate()
+    {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/sgflt.h", out));
+        }
+        Err(e) => println!("cargo:warning=generate sgflt.h failed: {}", e),
+    }
+}
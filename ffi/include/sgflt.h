@@ -0,0 +1,125 @@
+#ifndef SGFLT_H
+#define SGFLT_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define SGFLT_OK 0
+
+/**
+ * Null pointer, or a string that is not valid UTF-8. Keys may be any bytes.
+ */
+#define SGFLT_ERR_INVALID_ARGUMENT 1
+
+/**
+ * `SgfitErr::ChunkFull`.
+ */
+#define SGFLT_ERR_CHUNK_FULL 2
+
+/**
+ * Backend or any other error; see `sgflt_last_error`.
+ */
+#define SGFLT_ERR_BACKEND 3
+
+#define SGFLT_ERR_PANIC 4
+
+/**
+ * `SgfitErr::QuotaExceeded`, the appid exceeded one of its quotas or rate limits.
+ */
+#define SGFLT_ERR_QUOTA_EXCEEDED 5
+
+/**
+ * Opaque pool handle.
+ */
+typedef struct SgfltPool SgfltPool;
+
+/**
+ * Pool configuration. `backend` accepts the same urls as
+ * `BloomExpandStrategy::build_from_url` (redis://, redis+cluster://, file://, memory://).
+ * `fixed_size` 0 keeps the default ladder; `fp_rate` and `timestamp_size` 0 keep the defaults.
+ */
+typedef struct SgfltConfig {
+  const char *appid;
+  const char *backend;
+  size_t fixed_size;
+  double fp_rate;
+  int64_t timestamp_size;
+} SgfltConfig;
+
+/**
+ * Byte-slice key.
+ */
+typedef struct SgfltKey {
+  const uint8_t *ptr;
+  size_t len;
+} SgfltKey;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Last error message of the calling thread, or NULL. Valid until the next call on this thread.
+ */
+const char *sgflt_last_error(void);
+
+/**
+ * Create a pool. On success `*out` must be released with `sgflt_pool_free`.
+ *
+ * # Safety
+ * `config` and `out` must be valid pointers; strings must be NUL terminated.
+ */
+int sgflt_pool_new(const struct SgfltConfig *config, struct SgfltPool **out);
+
+/**
+ * # Safety
+ * `pool` must come from `sgflt_pool_new` and must not be used afterwards.
+ */
+void sgflt_pool_free(struct SgfltPool *pool);
+
+/**
+ * # Safety
+ * `pool`, `group` and `out` must be valid; `key_ptr` must point to `key_len` bytes.
+ */
+int sgflt_contain(const struct SgfltPool *pool,
+                  const char *group,
+                  const uint8_t *key_ptr,
+                  size_t key_len,
+                  bool *out);
+
+/**
+ * # Safety
+ * `pool` and `group` must be valid; `key_ptr` must point to `key_len` bytes.
+ */
+int sgflt_insert(const struct SgfltPool *pool,
+                 const char *group,
+                 const uint8_t *key_ptr,
+                 size_t key_len);
+
+/**
+ * # Safety
+ * `keys_ptr` must point to `n` keys and `out` to `n` bools.
+ */
+int sgflt_batch_contain(const struct SgfltPool *pool,
+                        const char *group,
+                        const struct SgfltKey *keys_ptr,
+                        size_t n,
+                        bool *out);
+
+/**
+ * # Safety
+ * `keys_ptr` must point to `n` keys.
+ */
+int sgflt_batch_insert(const struct SgfltPool *pool,
+                       const char *group,
+                       const struct SgfltKey *keys_ptr,
+                       size_t n);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SGFLT_H */
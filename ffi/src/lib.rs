@@ -0,0 +1,351 @@
+//! C ABI for sgflt. See `include/sgflt.h` and `tests/harness.c`.
+use sgflt::{BlockingFiltersPool, BloomExpandStrategy, FiltersPool, SgfitErr};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+pub const SGFLT_OK: c_int = 0;
+/// Null pointer, or a string that is not valid UTF-8. Keys may be any bytes.
+pub const SGFLT_ERR_INVALID_ARGUMENT: c_int = 1;
+/// `SgfitErr::ChunkFull`.
+pub const SGFLT_ERR_CHUNK_FULL: c_int = 2;
+/// Backend or any other error; see `sgflt_last_error`.
+pub const SGFLT_ERR_BACKEND: c_int = 3;
+pub const SGFLT_ERR_PANIC: c_int = 4;
+/// `SgfitErr::QuotaExceeded`, the appid exceeded one of its quotas or rate limits.
+pub const SGFLT_ERR_QUOTA_EXCEEDED: c_int = 5;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+fn error_code(e: &anyhow::Error) -> c_int {
+    match e.downcast_ref::<SgfitErr>() {
+        Some(SgfitErr::ChunkFull(_)) => SGFLT_ERR_CHUNK_FULL,
+        Some(SgfitErr::QuotaExceeded(_, _)) => SGFLT_ERR_QUOTA_EXCEEDED,
+        None => SGFLT_ERR_BACKEND,
+    }
+}
+
+// 捕获panic，防止unwind穿过C边界
+fn call<F: FnOnce() -> Result<(), c_int>>(f: F) -> c_int {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(Ok(())) => SGFLT_OK,
+        Ok(Err(code)) => code,
+        Err(_) => {
+            set_last_error("sgflt: panic".into());
+            SGFLT_ERR_PANIC
+        }
+    }
+}
+
+fn check<T>(res: anyhow::Result<T>) -> Result<T, c_int> {
+    res.map_err(|e| {
+        let code = error_code(&e);
+        set_last_error(e.to_string());
+        code
+    })
+}
+
+fn invalid(msg: &str) -> c_int {
+    set_last_error(msg.to_string());
+    SGFLT_ERR_INVALID_ARGUMENT
+}
+
+unsafe fn c_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, c_int> {
+    if s.is_null() {
+        return Err(invalid(&format!("sgflt: {} is null", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| invalid(&format!("sgflt: {} is not utf8", name)))
+}
+
+// key按字节传入，可以是任意字节，utf8的key与rust侧传入String的hash相同
+unsafe fn key(ptr: *const u8, len: usize) -> Result<Vec<u8>, c_int> {
+    if ptr.is_null() && len > 0 {
+        return Err(invalid("sgflt: key is null"));
+    }
+    let bytes = if len == 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    };
+    Ok(bytes.to_vec())
+}
+
+unsafe fn keys(list: *const SgfltKey, n: usize) -> Result<Vec<Vec<u8>>, c_int> {
+    if list.is_null() && n > 0 {
+        return Err(invalid("sgflt: keys is null"));
+    }
+    let mut vec = Vec::with_capacity(n);
+    for i in 0..n {
+        let k = &*list.add(i);
+        vec.push(key(k.ptr, k.len)?);
+    }
+    Ok(vec)
+}
+
+/// Opaque pool handle.
+pub struct SgfltPool {
+    pool: BlockingFiltersPool,
+}
+
+/// Byte-slice key.
+#[repr(C)]
+pub struct SgfltKey {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+/// Pool configuration. `backend` accepts the same urls as
+/// `BloomExpandStrategy::build_from_url` (redis://, redis+cluster://, file://, memory://).
+/// `fixed_size` 0 keeps the default ladder; `fp_rate` and `timestamp_size` 0 keep the defaults.
+#[repr(C)]
+pub struct SgfltConfig {
+    pub appid: *const c_char,
+    pub backend: *const c_char,
+    pub fixed_size: usize,
+    pub fp_rate: f64,
+    pub timestamp_size: i64,
+}
+
+/// Last error message of the calling thread, or NULL. Valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn sgflt_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|x| x.as_ptr())
+            .unwrap_or(ptr::null())
+    })
+}
+
+/// Create a pool. On success `*out` must be released with `sgflt_pool_free`.
+///
+/// # Safety
+/// `config` and `out` must be valid pointers; strings must be NUL terminated.
+#[no_mangle]
+pub unsafe extern "C" fn sgflt_pool_new(
+    config: *const SgfltConfig,
+    out: *mut *mut SgfltPool,
+) -> c_int {
+    call(|| {
+        if config.is_null() || out.is_null() {
+            return Err(invalid("sgflt: config or out is null"));
+        }
+        let config = &*config;
+        let appid = c_str(config.appid, "appid")?;
+        let backend = c_str(config.backend, "backend")?;
+        let mut strategy = check(BloomExpandStrategy::build_from_url(appid, backend))?;
+        if config.fixed_size > 0 {
+            strategy = strategy.set_strategy_fixed(config.fixed_size);
+        }
+        if config.fp_rate > 0.0 {
+            strategy = strategy.set_fp_rate(config.fp_rate);
+        }
+        if config.timestamp_size > 0 {
+            strategy = strategy.set_timestamp_size(config.timestamp_size);
+        }
+        let runtime = check(
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_all()
+                .build()
+                .map_err(anyhow::Error::from),
+        )?;
+        let pool = BlockingFiltersPool::with_runtime(FiltersPool::from(strategy), runtime);
+        *out = Box::into_raw(Box::new(SgfltPool { pool }));
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `pool` must come from `sgflt_pool_new` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sgflt_pool_free(pool: *mut SgfltPool) {
+    if !pool.is_null() {
+        drop(Box::from_raw(pool));
+    }
+}
+
+/// # Safety
+/// `pool`, `group` and `out` must be valid; `key_ptr` must point to `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sgflt_contain(
+    pool: *const SgfltPool,
+    group: *const c_char,
+    key_ptr: *const u8,
+    key_len: usize,
+    out: *mut bool,
+) -> c_int {
+    call(|| {
+        if pool.is_null() || out.is_null() {
+            return Err(invalid("sgflt: pool or out is null"));
+        }
+        let group = c_str(group, "group")?;
+        let key = key(key_ptr, key_len)?;
+        *out = check((*pool).pool.contain_bytes(group, key))?;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `pool` and `group` must be valid; `key_ptr` must point to `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sgflt_insert(
+    pool: *const SgfltPool,
+    group: *const c_char,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> c_int {
+    call(|| {
+        if pool.is_null() {
+            return Err(invalid("sgflt: pool is null"));
+        }
+        let group = c_str(group, "group")?;
+        let key = key(key_ptr, key_len)?;
+        check((*pool).pool.insert_bytes(group, key))
+    })
+}
+
+/// # Safety
+/// `keys_ptr` must point to `n` keys and `out` to `n` bools.
+#[no_mangle]
+pub unsafe extern "C" fn sgflt_batch_contain(
+    pool: *const SgfltPool,
+    group: *const c_char,
+    keys_ptr: *const SgfltKey,
+    n: usize,
+    out: *mut bool,
+) -> c_int {
+    call(|| {
+        if pool.is_null() || (out.is_null() && n > 0) {
+            return Err(invalid("sgflt: pool or out is null"));
+        }
+        let group = c_str(group, "group")?;
+        let keys = keys(keys_ptr, n)?;
+        let result = check((*pool).pool.batch_contain_bytes(group, keys))?;
+        for (i, b) in result.into_iter().enumerate() {
+            *out.add(i) = b;
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `keys_ptr` must point to `n` keys.
+#[no_mangle]
+pub unsafe extern "C" fn sgflt_batch_insert(
+    pool: *const SgfltPool,
+    group: *const c_char,
+    keys_ptr: *const SgfltKey,
+    n: usize,
+) -> c_int {
+    call(|| {
+        if pool.is_null() {
+            return Err(invalid("sgflt: pool is null"));
+        }
+        let group = c_str(group, "group")?;
+        let keys = keys(keys_ptr, n)?;
+        check((*pool).pool.batch_insert_bytes(group, keys))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use sgflt::QuotaKind;
+
+    #[test]
+    fn test_ffi_memory() {
+        let appid = CString::new("biz02").unwrap();
+        let backend = CString::new("memory://").unwrap();
+        let group = CString::new("user001").unwrap();
+        let config = SgfltConfig {
+            appid: appid.as_ptr(),
+            backend: backend.as_ptr(),
+            fixed_size: 10,
+            fp_rate: 0.0,
+            timestamp_size: 0,
+        };
+        unsafe {
+            let mut pool = ptr::null_mut();
+            assert_eq!(sgflt_pool_new(&config, &mut pool), SGFLT_OK);
+            let data = (0..25).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+            let list = data
+                .iter()
+                .map(|x| SgfltKey {
+                    ptr: x.as_ptr(),
+                    len: x.len(),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                sgflt_batch_insert(pool, group.as_ptr(), list.as_ptr(), list.len()),
+                SGFLT_OK
+            );
+            let mut out = vec![false; list.len()];
+            assert_eq!(
+                sgflt_batch_contain(
+                    pool,
+                    group.as_ptr(),
+                    list.as_ptr(),
+                    list.len(),
+                    out.as_mut_ptr()
+                ),
+                SGFLT_OK
+            );
+            assert!(out.into_iter().all(|x| x));
+
+            // 非utf8的key按原始字节处理
+            let binary = [0xffu8, 0xfe];
+            let mut exist = true;
+            assert_eq!(
+                sgflt_contain(
+                    pool,
+                    group.as_ptr(),
+                    binary.as_ptr(),
+                    binary.len(),
+                    &mut exist
+                ),
+                SGFLT_OK
+            );
+            assert!(!exist);
+            assert_eq!(
+                sgflt_insert(pool, group.as_ptr(), binary.as_ptr(), binary.len()),
+                SGFLT_OK
+            );
+            assert_eq!(
+                sgflt_contain(
+                    pool,
+                    group.as_ptr(),
+                    binary.as_ptr(),
+                    binary.len(),
+                    &mut exist
+                ),
+                SGFLT_OK
+            );
+            assert!(exist);
+            assert_eq!(
+                sgflt_insert(pool, ptr::null(), binary.as_ptr(), 0),
+                SGFLT_ERR_INVALID_ARGUMENT
+            );
+            assert!(!sgflt_last_error().is_null());
+            sgflt_pool_free(pool);
+        }
+    }
+
+    #[test]
+    fn test_error_code() {
+        let e = anyhow::Error::new(SgfitErr::new_chunk_full(10));
+        assert_eq!(error_code(&e), SGFLT_ERR_CHUNK_FULL);
+        let e = anyhow::Error::new(SgfitErr::new_quota_exceeded(QuotaKind::Groups, 1));
+        assert_eq!(error_code(&e), SGFLT_ERR_QUOTA_EXCEEDED);
+        assert_eq!(error_code(&anyhow::anyhow!("io")), SGFLT_ERR_BACKEND);
+    }
+}
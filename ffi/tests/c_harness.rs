@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+// 用系统C编译器编译 tests/harness.c 并链接 libsgflt_ffi 运行
+#[test]
+fn test_c_harness() {
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let deps = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let lib_dir = deps.parent().unwrap().to_path_buf();
+    let out = deps.join("sgflt_c_harness");
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .arg(manifest.join("tests/harness.c"))
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg("-L")
+        .arg(lib_dir.as_path())
+        .arg("-lsgflt_ffi")
+        .arg("-o")
+        .arg(out.as_path())
+        .status()
+        .expect("C compiler not found, set CC");
+    assert!(status.success());
+    let output = Command::new(out.as_path())
+        .env("LD_LIBRARY_PATH", lib_dir.as_path())
+        .env("DYLD_LIBRARY_PATH", lib_dir.as_path())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "ok");
+}
+
+// 提交的头文件需要与build.rs生成的一致，修改导出函数后用
+// SGFLT_UPDATE_HEADER=1 cargo test -p sgflt-ffi --test c_harness 更新 include/sgflt.h
+#[test]
+fn test_header_up_to_date() {
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let generated = PathBuf::from(env!("OUT_DIR")).join("sgflt.h");
+    let generated = std::fs::read_to_string(generated.as_path())
+        .expect("sgflt.h was not generated, see the build script warnings");
+    let committed = manifest.join("include/sgflt.h");
+    if std::env::var_os("SGFLT_UPDATE_HEADER").is_some() {
+        std::fs::write(committed.as_path(), generated.as_str()).unwrap();
+        return;
+    }
+    let current = std::fs::read_to_string(committed.as_path()).unwrap();
+    assert!(
+        current == generated,
+        "include/sgflt.h is out of date, run with SGFLT_UPDATE_HEADER=1 to update it"
+    );
+}
@@ -0,0 +1,59 @@
+/* C test harness for libsgflt_ffi, run by tests/c_harness.rs against the memory backend. */
+#include <stdio.h>
+#include <string.h>
+#include "sgflt.h"
+
+#define CHECK(expr)                                                               \
+    do {                                                                          \
+        if (!(expr)) {                                                            \
+            const char *err = sgflt_last_error();                                 \
+            fprintf(stderr, "%s:%d: %s failed (%s)\n", __FILE__, __LINE__, #expr, \
+                    err ? err : "");                                              \
+            return 1;                                                             \
+        }                                                                         \
+    } while (0)
+
+int main(void) {
+    SgfltConfig config = {"biz_c", "memory://", 10, 0.001, 0};
+    SgfltPool *pool = NULL;
+    CHECK(sgflt_pool_new(&config, &pool) == SGFLT_OK);
+
+    char data[30][16];
+    SgfltKey keys[30];
+    for (int i = 0; i < 30; i++) {
+        snprintf(data[i], sizeof(data[i]), "key_%d", i);
+        keys[i].ptr = (const uint8_t *)data[i];
+        keys[i].len = strlen(data[i]);
+    }
+    CHECK(sgflt_batch_insert(pool, "user001", keys, 25) == SGFLT_OK);
+
+    bool out[30];
+    CHECK(sgflt_batch_contain(pool, "user001", keys, 30, out) == SGFLT_OK);
+    for (int i = 0; i < 25; i++) {
+        CHECK(out[i]);
+    }
+
+    bool exist = true;
+    const uint8_t binary[] = {'k', 0, 'x'};
+    CHECK(sgflt_contain(pool, "user001", binary, sizeof(binary), &exist) == SGFLT_OK);
+    CHECK(!exist);
+    CHECK(sgflt_insert(pool, "user001", binary, sizeof(binary)) == SGFLT_OK);
+    CHECK(sgflt_contain(pool, "user001", binary, sizeof(binary), &exist) == SGFLT_OK);
+    CHECK(exist);
+
+    const uint8_t raw[] = {0xff, 0xfe};
+    CHECK(sgflt_insert(pool, "user001", raw, sizeof(raw)) == SGFLT_OK);
+    CHECK(sgflt_contain(pool, "user001", raw, sizeof(raw), &exist) == SGFLT_OK);
+    CHECK(exist);
+    CHECK(sgflt_insert(pool, NULL, binary, sizeof(binary)) == SGFLT_ERR_INVALID_ARGUMENT);
+    CHECK(sgflt_last_error() != NULL);
+
+    SgfltConfig bad = {"biz_c", "unknown://", 0, 0, 0};
+    SgfltPool *none = NULL;
+    CHECK(sgflt_pool_new(&bad, &none) == SGFLT_ERR_BACKEND);
+    CHECK(none == NULL);
+
+    sgflt_pool_free(pool);
+    printf("ok\n");
+    return 0;
+}
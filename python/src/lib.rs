@@ -0,0 +1,323 @@
+//! Python bindings for sgflt. Build with `maturin build` in this directory.
+use pyo3::create_exception;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use sgflt::{BloomExpandStrategy, DefaultPoolImpl, FiltersPool, GroupStats, ProbeOrder, SgfitErr};
+use std::sync::Arc;
+
+create_exception!(sgflt, ChunkFullError, PyRuntimeError);
+create_exception!(sgflt, QuotaExceededError, PyRuntimeError);
+
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    match e.downcast_ref::<SgfitErr>() {
+        Some(SgfitErr::ChunkFull(_)) => ChunkFullError::new_err(e.to_string()),
+        Some(SgfitErr::QuotaExceeded(_, _)) => QuotaExceededError::new_err(e.to_string()),
+        None => PyRuntimeError::new_err(e.to_string()),
+    }
+}
+
+// key可以是str或任意bytes，str与内容相同的bytes命中同一个key
+#[derive(FromPyObject)]
+enum Key {
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl Key {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Key::Str(s) => s.into_bytes(),
+            Key::Bytes(b) => b,
+        }
+    }
+}
+
+fn keys(list: Vec<Key>) -> Vec<Vec<u8>> {
+    list.into_iter().map(Key::into_bytes).collect()
+}
+
+// 转成python dict
+struct Stats(GroupStats);
+
+impl<'py> IntoPyObject<'py> for Stats {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> Result<Self::Output, Self::Error> {
+        let text =
+            serde_json::to_string(&self.0).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        py.import("json")?.call_method1("loads", (text,))
+    }
+}
+
+/// Chunk expansion strategy and storage backend.
+///
+/// Setters return the strategy itself so they can be chained. The strategy is
+/// consumed when it is passed to `FiltersPool`.
+#[pyclass(name = "BloomExpandStrategy", module = "sgflt")]
+struct PyBloomExpandStrategy {
+    inner: Option<BloomExpandStrategy>,
+}
+
+impl PyBloomExpandStrategy {
+    fn update(
+        &mut self,
+        f: impl FnOnce(BloomExpandStrategy) -> BloomExpandStrategy,
+    ) -> PyResult<()> {
+        let strategy = self.take()?;
+        self.inner = Some(f(strategy));
+        Ok(())
+    }
+    fn take(&mut self) -> PyResult<BloomExpandStrategy> {
+        self.inner
+            .take()
+            .ok_or_else(|| PyRuntimeError::new_err("sgflt: strategy already used by a pool"))
+    }
+}
+
+#[pymethods]
+impl PyBloomExpandStrategy {
+    /// Build from a backend url: redis://, redis+cluster://, file://, memory://.
+    #[new]
+    #[pyo3(signature = (appid, url = "memory://"))]
+    fn new(appid: &str, url: &str) -> PyResult<Self> {
+        let inner = BloomExpandStrategy::build_from_url(appid, url).map_err(to_py_err)?;
+        Ok(Self { inner: Some(inner) })
+    }
+    #[staticmethod]
+    fn from_memory(appid: &str) -> Self {
+        Self {
+            inner: Some(BloomExpandStrategy::build_from_memory(appid)),
+        }
+    }
+    #[staticmethod]
+    fn from_file(appid: &str, path: std::path::PathBuf) -> PyResult<Self> {
+        let inner = BloomExpandStrategy::build_from_file(appid, path).map_err(to_py_err)?;
+        Ok(Self { inner: Some(inner) })
+    }
+
+    fn set_strategy_fixed(mut slf: PyRefMut<'_, Self>, n: usize) -> PyResult<PyRefMut<'_, Self>> {
+        slf.update(|s| s.set_strategy_fixed(n))?;
+        Ok(slf)
+    }
+    fn set_strategy_ladder(
+        mut slf: PyRefMut<'_, Self>,
+        table: Vec<usize>,
+    ) -> PyResult<PyRefMut<'_, Self>> {
+        slf.update(|s| s.set_strategy_ladder(table))?;
+        Ok(slf)
+    }
+    fn set_fp_rate(mut slf: PyRefMut<'_, Self>, rate: f64) -> PyResult<PyRefMut<'_, Self>> {
+        slf.update(|s| s.set_fp_rate(rate))?;
+        Ok(slf)
+    }
+    fn set_timestamp_size(mut slf: PyRefMut<'_, Self>, size: i64) -> PyResult<PyRefMut<'_, Self>> {
+        slf.update(|s| s.set_timestamp_size(size))?;
+        Ok(slf)
+    }
+    fn set_shards(mut slf: PyRefMut<'_, Self>, shards: usize) -> PyResult<PyRefMut<'_, Self>> {
+        slf.update(|s| s.set_shards(shards))?;
+        Ok(slf)
+    }
+}
+
+/// Filter pool. Sync methods release the GIL while waiting; `*_async` methods
+/// return awaitables for asyncio.
+#[pyclass(name = "FiltersPool", module = "sgflt", frozen)]
+struct PyFiltersPool {
+    pool: Arc<FiltersPool>,
+}
+
+fn runtime() -> &'static tokio::runtime::Runtime {
+    pyo3_async_runtimes::tokio::get_runtime()
+}
+
+#[pymethods]
+impl PyFiltersPool {
+    /// `probe_order` is "oldest_first" (default) or "newest_first".
+    #[new]
+    #[pyo3(signature = (strategy, parallelism = None, probe_order = None, fetch_window = None))]
+    fn new(
+        mut strategy: PyRefMut<'_, PyBloomExpandStrategy>,
+        parallelism: Option<usize>,
+        probe_order: Option<&str>,
+        fetch_window: Option<usize>,
+    ) -> PyResult<Self> {
+        let order = match probe_order {
+            None | Some("oldest_first") => ProbeOrder::OldestFirst,
+            Some("newest_first") => ProbeOrder::NewestFirst,
+            Some(s) => {
+                return Err(PyValueError::new_err(format!(
+                    "sgflt: unknown probe_order {}",
+                    s
+                )))
+            }
+        };
+        let mut pool = DefaultPoolImpl::new(strategy.take()?).set_probe_order(order);
+        if let Some(n) = parallelism {
+            pool = pool.set_parallelism(n);
+        }
+        if let Some(n) = fetch_window {
+            pool = pool.set_fetch_window(n);
+        }
+        Ok(Self {
+            pool: Arc::new(FiltersPool::new(pool)),
+        })
+    }
+
+    fn contain(&self, py: Python<'_>, group: &str, key: Key) -> PyResult<bool> {
+        let key = key.into_bytes();
+        py.allow_threads(|| runtime().block_on(self.pool.contain_bytes(group, key)))
+            .map_err(to_py_err)
+    }
+    fn insert(&self, py: Python<'_>, group: &str, key: Key) -> PyResult<()> {
+        let key = key.into_bytes();
+        py.allow_threads(|| runtime().block_on(self.pool.insert_bytes(group, key)))
+            .map_err(to_py_err)
+    }
+    fn batch_contain(&self, py: Python<'_>, group: &str, keys: Vec<Key>) -> PyResult<Vec<bool>> {
+        let keys = self::keys(keys);
+        py.allow_threads(|| runtime().block_on(self.pool.batch_contain_bytes(group, keys)))
+            .map_err(to_py_err)
+    }
+    fn batch_insert(&self, py: Python<'_>, group: &str, keys: Vec<Key>) -> PyResult<()> {
+        let keys = self::keys(keys);
+        py.allow_threads(|| runtime().block_on(self.pool.batch_insert_bytes(group, keys)))
+            .map_err(to_py_err)
+    }
+    /// Group statistics as a dict.
+    fn stats<'py>(&self, py: Python<'py>, group: &str) -> PyResult<Bound<'py, PyAny>> {
+        let stats = py
+            .allow_threads(|| runtime().block_on(self.pool.stats(group)))
+            .map_err(to_py_err)?;
+        Stats(stats).into_pyobject(py)
+    }
+
+    fn contain_async<'py>(
+        &self,
+        py: Python<'py>,
+        group: String,
+        key: Key,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let key = key.into_bytes();
+        let pool = self.pool.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            pool.contain_bytes(&group, key).await.map_err(to_py_err)
+        })
+    }
+    fn insert_async<'py>(
+        &self,
+        py: Python<'py>,
+        group: String,
+        key: Key,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let key = key.into_bytes();
+        let pool = self.pool.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            pool.insert_bytes(&group, key).await.map_err(to_py_err)
+        })
+    }
+    fn batch_contain_async<'py>(
+        &self,
+        py: Python<'py>,
+        group: String,
+        keys: Vec<Key>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let keys = self::keys(keys);
+        let pool = self.pool.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            pool.batch_contain_bytes(&group, keys)
+                .await
+                .map_err(to_py_err)
+        })
+    }
+    fn batch_insert_async<'py>(
+        &self,
+        py: Python<'py>,
+        group: String,
+        keys: Vec<Key>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let keys = self::keys(keys);
+        let pool = self.pool.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            pool.batch_insert_bytes(&group, keys)
+                .await
+                .map_err(to_py_err)
+        })
+    }
+    fn stats_async<'py>(&self, py: Python<'py>, group: String) -> PyResult<Bound<'py, PyAny>> {
+        let pool = self.pool.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            pool.stats(&group).await.map(Stats).map_err(to_py_err)
+        })
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "sgflt")]
+fn sgflt_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyBloomExpandStrategy>()?;
+    m.add_class::<PyFiltersPool>()?;
+    m.add("ChunkFullError", m.py().get_type::<ChunkFullError>())?;
+    m.add(
+        "QuotaExceededError",
+        m.py().get_type::<QuotaExceededError>(),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use std::ffi::CStr;
+    use std::sync::Once;
+
+    fn run(code: &CStr) {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            pyo3::append_to_inittab!(sgflt_py);
+            pyo3::prepare_freethreaded_python();
+        });
+        Python::with_gil(|py| py.run(code, None, None)).unwrap();
+    }
+
+    #[test]
+    fn test_python_sync() {
+        run(c"
+import sgflt
+strategy = sgflt.BloomExpandStrategy('biz02', 'memory://').set_strategy_fixed(10).set_shards(2)
+pool = sgflt.FiltersPool(strategy, probe_order='newest_first')
+keys = ['key_%d' % i for i in range(25)]
+pool.batch_insert('user001', keys)
+pool.insert('user001', b'key_bytes')
+assert all(pool.batch_contain('user001', keys + [b'key_bytes']))
+assert pool.contain('user001', 'key_bytes')
+assert not pool.contain('user001', 'none')
+stats = pool.stats('user001')
+assert stats['count'] == 26 and len(stats['chunks']) == 3
+pool.insert('user001', b'\\xff')
+assert pool.contain('user001', b'\\xff')
+try:
+    sgflt.FiltersPool(strategy)
+    raise AssertionError('expect RuntimeError')
+except RuntimeError:
+    pass
+");
+    }
+
+    #[test]
+    fn test_python_async() {
+        run(c"
+import asyncio, sgflt
+async def main():
+    pool = sgflt.FiltersPool(sgflt.BloomExpandStrategy.from_memory('biz02'))
+    await pool.batch_insert_async('user001', ['a', b'b'])
+    await pool.insert_async('user001', 'c')
+    assert await pool.batch_contain_async('user001', ['a', 'b', 'c', 'd']) == [True, True, True, False]
+    assert await pool.contain_async('user001', b'a')
+    assert (await pool.stats_async('user001'))['count'] == 3
+asyncio.run(main())
+");
+    }
+}
@@ -0,0 +1,52 @@
+import asyncio
+
+import pytest
+
+import sgflt
+
+
+def keys(n):
+    return ["key_%d" % i for i in range(n)]
+
+
+def test_memory():
+    pool = sgflt.FiltersPool(sgflt.BloomExpandStrategy.from_memory("biz02").set_strategy_fixed(10))
+    pool.batch_insert("user001", keys(25))
+    pool.insert("user001", b"key_bytes")
+    assert all(pool.batch_contain("user001", keys(25) + [b"key_bytes"]))
+    assert not pool.contain("user001", "none")
+    assert pool.stats("user001")["count"] == 26
+
+
+def test_file(tmp_path):
+    strategy = sgflt.BloomExpandStrategy("biz02", "file://%s" % tmp_path).set_strategy_fixed(10)
+    pool = sgflt.FiltersPool(strategy)
+    pool.batch_insert("user001", keys(15))
+
+    # 用相同的策略重新打开同一目录，数据仍然存在
+    strategy = sgflt.BloomExpandStrategy.from_file("biz02", str(tmp_path)).set_strategy_fixed(10)
+    pool = sgflt.FiltersPool(strategy)
+    assert all(pool.batch_contain("user001", keys(15)))
+    assert len(pool.stats("user001")["chunks"]) == 2
+
+
+def test_async():
+    async def main():
+        pool = sgflt.FiltersPool(sgflt.BloomExpandStrategy.from_memory("biz02"))
+        await pool.batch_insert_async("user001", ["a", b"b"])
+        assert await pool.batch_contain_async("user001", ["a", "b", "c"]) == [True, True, False]
+        assert await pool.contain_async("user001", b"a")
+
+    asyncio.run(main())
+
+
+def test_errors():
+    strategy = sgflt.BloomExpandStrategy.from_memory("biz02")
+    pool = sgflt.FiltersPool(strategy)
+    # 非utf8的bytes key原样参与哈希
+    pool.insert("user001", b"\xff")
+    assert pool.contain("user001", b"\xff")
+    with pytest.raises(RuntimeError):
+        sgflt.FiltersPool(strategy)
+    with pytest.raises(ValueError):
+        sgflt.FiltersPool(sgflt.BloomExpandStrategy.from_memory("biz02"), probe_order="random")
@@ -0,0 +1,321 @@
+use crate::http::AppError;
+use axum::http::{header, HeaderMap, StatusCode};
+use jsonwebtoken::{DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+
+// 所有appid
+pub const ANY_APPID: &str = "*";
+
+// read: contain、stats；read_write: 额外允许insert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Read,
+    ReadWrite,
+}
+
+// 通过认证的调用方
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub appids: Vec<String>,
+    pub role: Role,
+}
+
+impl Principal {
+    // 未开启认证时使用，允许所有操作
+    pub fn anonymous() -> Self {
+        Self {
+            name: "anonymous".into(),
+            appids: vec![ANY_APPID.into()],
+            role: Role::ReadWrite,
+        }
+    }
+    pub fn allow_appid(&self, appid: &str) -> bool {
+        self.appids.iter().any(|a| a == ANY_APPID || a == appid)
+    }
+    // 涉及所有租户的数据(例如/metrics)，只允许appids中有"*"的调用方访问
+    pub fn authorize_all(&self) -> Result<(), AppError> {
+        if !self.appids.iter().any(|a| a == ANY_APPID) {
+            return Err(forbidden(format!(
+                "{} can not access all appids",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+    pub fn authorize(&self, appid: &str, role: Role) -> Result<(), AppError> {
+        if !self.allow_appid(appid) {
+            return Err(forbidden(format!(
+                "{} can not access appid[{}]",
+                self.name, appid
+            )));
+        }
+        if self.role < role {
+            return Err(forbidden(format!("{} is read only", self.name)));
+        }
+        Ok(())
+    }
+}
+
+// api key只保存sha256，生成方式：echo -n $KEY | sha256sum
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    pub key_sha256: String,
+    pub appids: Vec<String>,
+    pub role: Role,
+}
+
+// HS256签名的jwt，claims中需要包含 sub exp appids role
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtConfig {
+    pub secret: String,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtClaims {
+    pub sub: String,
+    pub exp: u64,
+    pub appids: Vec<String>,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    pub jwt: Option<JwtConfig>,
+}
+
+impl AuthConfig {
+    // 根据扩展名选择格式：.toml .yaml .yml .json
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => Ok(toml::from_str(&text)?),
+            Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(&text)?),
+            Some("json") => Ok(serde_json::from_str(&text)?),
+            _ => Err(anyhow::anyhow!(
+                "unknown auth config format[{}]",
+                path.display()
+            )),
+        }
+    }
+}
+
+// 本地校验 Authorization: Bearer <api key | jwt>
+pub struct Authenticator {
+    keys: HashMap<String, Principal>,
+    jwt: Option<(DecodingKey, Validation)>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> anyhow::Result<Self> {
+        let mut keys = HashMap::new();
+        for k in config.api_keys {
+            let digest = k.key_sha256.to_lowercase();
+            if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(anyhow::anyhow!(
+                    "api key[{}] key_sha256 is not a sha256 hex",
+                    k.name
+                ));
+            }
+            let principal = Principal {
+                name: k.name,
+                appids: k.appids,
+                role: k.role,
+            };
+            if keys.insert(digest, principal).is_some() {
+                return Err(anyhow::anyhow!("duplicate api key"));
+            }
+        }
+        let jwt = config.jwt.map(|j| {
+            let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
+            // 配置了iss/aud时claims中必须包含，否则缺失的claim会被跳过校验
+            if let Some(iss) = j.issuer {
+                validation.set_issuer(&[iss]);
+                validation.required_spec_claims.insert("iss".into());
+            }
+            match j.audience {
+                Some(aud) => {
+                    validation.set_audience(&[aud]);
+                    validation.required_spec_claims.insert("aud".into());
+                }
+                None => validation.validate_aud = false,
+            }
+            (DecodingKey::from_secret(j.secret.as_bytes()), validation)
+        });
+        Ok(Self { keys, jwt })
+    }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::new(AuthConfig::from_file(path)?)
+    }
+
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AppError> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim())
+            .ok_or_else(|| unauthorized("missing bearer token"))?;
+        // jwt由三段组成，其余按api key处理
+        if token.matches('.').count() == 2 {
+            return self.verify_jwt(token);
+        }
+        self.keys
+            .get(sha256_hex(token).as_str())
+            .cloned()
+            .ok_or_else(|| unauthorized("invalid api key"))
+    }
+
+    fn verify_jwt(&self, token: &str) -> Result<Principal, AppError> {
+        let (key, validation) = self
+            .jwt
+            .as_ref()
+            .ok_or_else(|| unauthorized("jwt is not enabled"))?;
+        let data = jsonwebtoken::decode::<JwtClaims>(token, key, validation)
+            .map_err(|e| unauthorized(format!("invalid jwt: {}", e)))?;
+        Ok(Principal {
+            name: data.claims.sub,
+            appids: data.claims.appids,
+            role: data.claims.role,
+        })
+    }
+}
+
+pub fn sha256_hex(s: &str) -> String {
+    Sha256::digest(s.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn unauthorized<S: Into<String>>(msg: S) -> AppError {
+    AppError::new(StatusCode::UNAUTHORIZED, anyhow::anyhow!(msg.into()))
+}
+
+fn forbidden(msg: String) -> AppError {
+    AppError::new(StatusCode::FORBIDDEN, anyhow::anyhow!(msg))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::auth::{
+        sha256_hex, ApiKeyConfig, AuthConfig, Authenticator, JwtClaims, JwtConfig, Role,
+    };
+    use axum::http::{header, HeaderMap};
+
+    fn headers(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    fn jwt(claims: &JwtClaims, secret: &str) -> String {
+        let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+        jsonwebtoken::encode(&jsonwebtoken::Header::default(), claims, &key).unwrap()
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let auth = Authenticator::new(AuthConfig {
+            api_keys: vec![ApiKeyConfig {
+                name: "biz02-reader".into(),
+                key_sha256: sha256_hex("secret-key"),
+                appids: vec!["biz02".into()],
+                role: Role::Read,
+            }],
+            jwt: Some(JwtConfig {
+                secret: "jwt-secret".into(),
+                issuer: Some("sgflt".into()),
+                audience: None,
+            }),
+        })
+        .unwrap();
+
+        let p = auth.authenticate(&headers("secret-key")).unwrap();
+        assert_eq!(p.name, "biz02-reader");
+        assert!(p.authorize("biz02", Role::Read).is_ok());
+        assert!(p.authorize("biz02", Role::ReadWrite).is_err());
+        assert!(p.authorize("biz03", Role::Read).is_err());
+        assert!(p.authorize_all().is_err());
+        assert!(auth.authenticate(&headers("wrong-key")).is_err());
+        assert!(auth.authenticate(&HeaderMap::new()).is_err());
+
+        let exp = expire_at();
+        let mut claims = JwtClaims {
+            sub: "job".into(),
+            exp,
+            appids: vec!["*".into()],
+            role: Role::ReadWrite,
+        };
+        // issuer不匹配
+        assert!(auth
+            .authenticate(&headers(&jwt(&claims, "jwt-secret")))
+            .is_err());
+        #[derive(serde::Serialize)]
+        struct WithIss<'a> {
+            #[serde(flatten)]
+            claims: &'a JwtClaims,
+            iss: &'a str,
+        }
+        let token = |claims: &JwtClaims, secret: &str| {
+            let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+            let body = WithIss {
+                claims,
+                iss: "sgflt",
+            };
+            jsonwebtoken::encode(&jsonwebtoken::Header::default(), &body, &key).unwrap()
+        };
+        let p = auth
+            .authenticate(&headers(&token(&claims, "jwt-secret")))
+            .unwrap();
+        assert!(p.authorize("biz09", Role::ReadWrite).is_ok());
+        assert!(p.authorize_all().is_ok());
+        assert!(auth
+            .authenticate(&headers(&token(&claims, "other-secret")))
+            .is_err());
+        claims.exp = 1;
+        assert!(auth
+            .authenticate(&headers(&token(&claims, "jwt-secret")))
+            .is_err());
+    }
+
+    fn expire_at() -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        now.as_secs() + 3600
+    }
+
+    #[test]
+    fn test_auth_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.toml");
+        let text = format!(
+            "[[api_keys]]\nname = \"w\"\nkey_sha256 = \"{}\"\nappids = [\"biz02\"]\nrole = \"read_write\"\n\n[jwt]\nsecret = \"s\"",
+            sha256_hex("k")
+        );
+        std::fs::write(&path, text).unwrap();
+        let auth = Authenticator::from_file(&path).unwrap();
+        let p = auth.authenticate(&headers("k")).unwrap();
+        assert_eq!(p.role, Role::ReadWrite);
+
+        std::fs::write(
+            &path,
+            "[[api_keys]]\nname = \"w\"\nkey_sha256 = \"k\"\nappids = []\nrole = \"read\"",
+        )
+        .unwrap();
+        assert!(Authenticator::from_file(&path).is_err());
+    }
+}
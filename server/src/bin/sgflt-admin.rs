@@ -0,0 +1,177 @@
+use clap::{Parser, Subcommand};
+use sgflt::{BloomExpandStrategy, FilterGroup, FilterKind, FiltersPool, StrategyConfig};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+
+// 运维工具：查看和管理某个后端中的过滤器group
+#[derive(Parser, Debug)]
+#[command(name = "sgflt-admin", about = "Inspect and manage filter groups")]
+struct Args {
+    // 后端url，例如 redis://:pass@127.0.0.1/ 或 file:///data/sgflt
+    #[arg(long, env = "SGFLT_BACKEND", required_unless_present = "config")]
+    backend: Option<String>,
+    // 服务端的配置文件，指定时忽略其他后端和chunk参数
+    #[arg(long, env = "SGFLT_CONFIG")]
+    config: Option<String>,
+    // 固定扩容大小，优先于ladder
+    #[arg(long)]
+    fixed: Option<usize>,
+    // 梯度扩容表，需要和服务端配置一致
+    #[arg(long, value_delimiter = ',', default_value = "100,1000,5000")]
+    ladder: Vec<usize>,
+    #[arg(long, default_value_t = 0.001)]
+    fp_rate: f64,
+    #[arg(long, default_value_t = 3600)]
+    timestamp_size: i64,
+    // 新chunk的分片数，已有chunk按登记的分片数读取，没有登记的旧chunk也按这个值
+    #[arg(long, default_value_t = 1)]
+    shards: usize,
+    // 新chunk的过滤器类型 basic|blocked，与shards相同，已有chunk按登记的类型读取
+    #[arg(long, default_value = "basic")]
+    filter: FilterKind,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    // 列出appid下所有group
+    ListGroups {
+        appid: String,
+    },
+    // 查看group下每个chunk的状态
+    Show {
+        appid: String,
+        group: String,
+    },
+    Contain {
+        appid: String,
+        group: String,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    Insert {
+        appid: String,
+        group: String,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    // 删除group的所有chunk
+    Drop {
+        appid: String,
+        group: String,
+    },
+    // 导出快照，不指定group时导出整个appid
+    Export {
+        appid: String,
+        file: String,
+        #[arg(long)]
+        group: Vec<String>,
+    },
+    Import {
+        appid: String,
+        file: String,
+    },
+}
+
+impl Args {
+    fn strategy(&self, appid: &str) -> anyhow::Result<BloomExpandStrategy> {
+        if let Some(path) = self.config.as_ref() {
+            return StrategyConfig::from_file(path)?.build(appid);
+        }
+        let backend = self.backend.as_deref().unwrap_or_default();
+        let strategy = BloomExpandStrategy::build_from_url(appid, backend)?
+            .set_fp_rate(self.fp_rate)
+            .set_timestamp_size(self.timestamp_size)
+            .set_shards(self.shards)
+            .set_filter(self.filter);
+        let strategy = match self.fixed {
+            Some(n) => strategy.set_strategy_fixed(n),
+            None => strategy.set_strategy_ladder(self.ladder.clone()),
+        };
+        Ok(strategy)
+    }
+}
+
+async fn show(strategy: BloomExpandStrategy, group: &str) -> anyhow::Result<()> {
+    let group_key = strategy.key_layout().group_key(strategy.appid(), group);
+    let stats = FiltersPool::from(strategy).stats(group).await?;
+    println!("group: {}", group_key);
+    println!(
+        "{:<48} {:>10} {:>10} {:>12} {:>4} {:>12} {:>8} {:>10}",
+        "chunk", "count", "capacity", "m", "k", "bits", "fill", "fp"
+    );
+    for chunk in stats.chunks.iter() {
+        println!(
+            "{:<48} {:>10} {:>10} {:>12} {:>4} {:>12} {:>7.2}% {:>10.6}",
+            chunk.code,
+            chunk.count,
+            chunk.capacity,
+            chunk.m,
+            chunk.k,
+            chunk.bits,
+            chunk.fill_ratio * 100.0,
+            chunk.fp_rate
+        );
+    }
+    println!(
+        "chunks: {}  count: {}  capacity: {}  group fp: {:.6}",
+        stats.chunks.len(),
+        stats.count,
+        stats.capacity,
+        stats.fp_rate
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    match &args.command {
+        Command::ListGroups { appid } => {
+            for group in args.strategy(appid)?.groups().await? {
+                println!("{}", group);
+            }
+        }
+        Command::Show { appid, group } => {
+            show(args.strategy(appid)?, group.as_str()).await?;
+        }
+        Command::Contain { appid, group, keys } => {
+            // 只读查询，不存在的group不会因为查询创建chunk
+            let fg = FilterGroup::new(group.clone(), Arc::new(args.strategy(appid)?));
+            fg.reload().await?;
+            let result = fg
+                .batch_contain(keys.iter().map(|k| k.clone().into_bytes()).collect())
+                .await?;
+            for (key, exist) in keys.iter().zip(result) {
+                println!("{}\t{}", key, exist);
+            }
+        }
+        Command::Insert { appid, group, keys } => {
+            let pool = FiltersPool::from(args.strategy(appid)?);
+            pool.batch_insert(group.as_str(), keys.clone()).await?;
+            println!("inserted {} keys into {}", keys.len(), group);
+        }
+        Command::Drop { appid, group } => {
+            let n = args.strategy(appid)?.drop_group(group.as_str()).await?;
+            println!("dropped {} chunks of {}", n, group);
+        }
+        Command::Export { appid, file, group } => {
+            let strategy = args.strategy(appid)?;
+            let mut w = BufWriter::new(File::create(file)?);
+            let n = if group.is_empty() {
+                strategy.export_app(&mut w).await?
+            } else {
+                strategy.export_groups(group.as_slice(), &mut w).await?
+            };
+            println!("exported {} chunks to {}", n, file);
+        }
+        Command::Import { appid, file } => {
+            let strategy = args.strategy(appid)?;
+            let n = strategy.import(BufReader::new(File::open(file)?)).await?;
+            println!("imported {} chunks from {}", n, file);
+        }
+    }
+    Ok(())
+}
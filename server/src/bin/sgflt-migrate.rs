@@ -0,0 +1,86 @@
+use clap::Parser;
+use sgflt::{BloomExpandStrategy, FilterKind, Migration, StrategyConfig};
+
+// 将一个appid的数据从旧后端补齐到新后端
+// 服务端在迁移期间通过配置 migrate_to 双写，补齐完成后使用 --switch-over 写入切换标记
+// 服务端读取到标记后切换到新后端读取，之后可以把 backend 改为新后端并去掉 migrate_to
+#[derive(Parser, Debug)]
+#[command(
+    name = "sgflt-migrate",
+    about = "Backfill filters from one backend to another"
+)]
+struct Args {
+    #[arg(long)]
+    appid: String,
+    // 旧后端url，例如 redis://:pass@127.0.0.1/
+    #[arg(long)]
+    from: String,
+    // 新后端url，例如 redis+cluster://host1:6379,host2:6379 或 file:///data/sgflt
+    #[arg(long)]
+    to: String,
+    // 指定需要迁移的group，不指定时扫描appid下所有group
+    #[arg(long)]
+    group: Vec<String>,
+    // 补齐完成后写入切换标记
+    #[arg(long)]
+    switch_over: bool,
+    // 旧后端中没有登记分片数的chunk按这个值复制，不指定时取配置文件中appid的shards
+    #[arg(long)]
+    shards: Option<usize>,
+    // 旧后端中没有登记过滤器类型的chunk在新后端登记的类型 basic|blocked，不指定时取配置文件
+    #[arg(long)]
+    filter: Option<FilterKind>,
+    // 服务端的配置文件
+    #[arg(long, env = "SGFLT_CONFIG")]
+    config: Option<String>,
+}
+
+impl Args {
+    // 命令行参数优先，其次是配置文件中appid的配置
+    fn fallback(&self) -> anyhow::Result<(usize, FilterKind)> {
+        let (shards, filter) = match self.config.as_ref() {
+            Some(path) => {
+                let config = StrategyConfig::from_file(path)?.resolve(self.appid.as_str())?;
+                (config.shards, config.filter)
+            }
+            None => (1, FilterKind::default()),
+        };
+        Ok((self.shards.unwrap_or(shards), self.filter.unwrap_or(filter)))
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let (shards, filter) = args.fallback()?;
+    let old = BloomExpandStrategy::build_from_url(args.appid.as_str(), args.from.as_str())?;
+    let new = BloomExpandStrategy::build_from_url(args.appid.as_str(), args.to.as_str())?;
+    let migration = Migration::new(
+        old.filter_info(),
+        old.bitmap(),
+        new.filter_info(),
+        new.bitmap(),
+    )
+    .set_appid(args.appid.as_str())
+    .set_shards(shards)
+    .set_filter(filter);
+    let layout = old.key_layout();
+    let report = if args.group.is_empty() {
+        migration
+            .backfill(layout.as_ref(), args.appid.as_str())
+            .await?
+    } else {
+        migration
+            .backfill_groups(layout.as_ref(), args.appid.as_str(), args.group.as_slice())
+            .await?
+    };
+    println!(
+        "backfill finished: appid[{}] groups[{}] chunks[{}] bits[{}] count[{}]",
+        args.appid, report.groups, report.chunks, report.bits, report.count
+    );
+    if args.switch_over {
+        migration.switch_over().await?;
+        println!("switch over: appid[{}]", args.appid);
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use crate::auth::{Authenticator, Principal, Role};
+use crate::telemetry;
+use axum::extract::{Path, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{middleware, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use sgflt::{GroupStats, SgfitErr, Tenant, TenantRegistry};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct KeysRequest {
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContainResponse {
+    pub result: Vec<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InsertResponse {
+    pub inserted: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppsResponse {
+    pub apps: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    error: anyhow::Error,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, error: anyhow::Error) -> Self {
+        Self { status, error }
+    }
+}
+
+// 超出配额返回429，其他错误返回500
+impl<E: Into<anyhow::Error>> From<E> for AppError {
+    fn from(value: E) -> Self {
+        let error = value.into();
+        let status = match error.downcast_ref::<SgfitErr>() {
+            Some(SgfitErr::QuotaExceeded(_, _)) => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, error)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.error.to_string() });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    registry: Arc<TenantRegistry>,
+    // /v1/groups/... 这类不带appid的路由使用的默认appid
+    default_appid: Option<String>,
+    // 为None时不校验身份
+    auth: Option<Arc<Authenticator>>,
+}
+
+impl AppState {
+    pub fn new(registry: Arc<TenantRegistry>, default_appid: Option<String>) -> Self {
+        Self {
+            registry,
+            default_appid,
+            auth: None,
+        }
+    }
+    pub fn set_auth(mut self, auth: Authenticator) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    // 路径中有appid时使用路径中的，否则使用默认appid
+    // 先校验调用方对该appid的权限，避免通过404探测其他租户
+    fn tenant(
+        &self,
+        principal: &Principal,
+        params: &HashMap<String, String>,
+        role: Role,
+    ) -> Result<Arc<Tenant>, AppError> {
+        let appid = params
+            .get("appid")
+            .or(self.default_appid.as_ref())
+            .ok_or_else(|| {
+                AppError::new(StatusCode::NOT_FOUND, anyhow::anyhow!("appid is required"))
+            })?;
+        principal.authorize(appid, role)?;
+        self.registry.get(appid).ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_FOUND,
+                anyhow::anyhow!("unknown appid[{}]", appid),
+            )
+        })
+    }
+}
+
+pub fn router(state: AppState) -> Router {
+    // 开启认证时 /metrics 需要能访问所有appid的token，不需要token时通过 metrics_router 单独监听
+    let api = Router::new()
+        .route("/metrics", get(authorized_metrics))
+        .route("/v1/apps", get(apps))
+        .route("/v1/apps/{appid}/groups/{group}/contain", post(contain))
+        .route("/v1/apps/{appid}/groups/{group}/insert", post(insert))
+        .route("/v1/apps/{appid}/groups/{group}/stats", get(stats))
+        .route("/v1/groups/{group}/contain", post(contain))
+        .route("/v1/groups/{group}/insert", post(insert))
+        .route("/v1/groups/{group}/stats", get(stats))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authenticate));
+    Router::new()
+        .merge(api)
+        .layer(middleware::from_fn(telemetry::trace_context))
+        .with_state(state)
+}
+
+// 只有 /metrics，不做认证，需要监听在内网地址
+pub fn metrics_router() -> Router {
+    Router::new().route("/metrics", get(metrics))
+}
+
+// 校验Authorization头，将调用方放入request extensions
+async fn authenticate(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let principal = match state.auth.as_ref() {
+        Some(auth) => auth.authenticate(req.headers())?,
+        None => Principal::anonymous(),
+    };
+    req.extensions_mut().insert(principal);
+    Ok(next.run(req).await)
+}
+
+async fn metrics() -> Result<impl IntoResponse, AppError> {
+    let text = sgflt::metrics_text()?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text))
+}
+
+// 指标中带有所有租户的appid标签，只限于权限覆盖所有appid的调用方
+async fn authorized_metrics(
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, AppError> {
+    principal.authorize_all()?;
+    metrics().await
+}
+
+// 只返回调用方有权限的appid
+async fn apps(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Json<AppsResponse> {
+    let apps = state
+        .registry
+        .appids()
+        .into_iter()
+        .filter(|appid| principal.allow_appid(appid))
+        .collect();
+    Json(AppsResponse { apps })
+}
+
+async fn contain(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+    Json(req): Json<KeysRequest>,
+) -> Result<Json<ContainResponse>, AppError> {
+    let tenant = state.tenant(&principal, &params, Role::Read)?;
+    let result = tenant
+        .pool()
+        .batch_contain(&params["group"], req.keys)
+        .await?;
+    Ok(Json(ContainResponse { result }))
+}
+
+async fn insert(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+    Json(req): Json<KeysRequest>,
+) -> Result<Json<InsertResponse>, AppError> {
+    let tenant = state.tenant(&principal, &params, Role::ReadWrite)?;
+    let inserted = req.keys.len();
+    tenant
+        .pool()
+        .batch_insert(&params["group"], req.keys)
+        .await?;
+    Ok(Json(InsertResponse { inserted }))
+}
+
+async fn stats(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<GroupStats>, AppError> {
+    let tenant = state.tenant(&principal, &params, Role::Read)?;
+    Ok(Json(tenant.pool().stats(&params["group"]).await?))
+}
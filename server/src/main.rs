@@ -1,3 +1,315 @@
-fn main() {
-    println!("Hello, world!");
+mod auth;
+mod http;
+mod telemetry;
+
+use clap::Parser;
+use sgflt::{AppConfig, StrategyConfig, StrategyKind, TenantRegistry, DEFAULT_ENV_PREFIX};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// 去重服务，通过http提供contain/insert接口，/metrics 暴露prometheus指标
+#[derive(Parser, Debug, Clone)]
+#[command(name = "sgflt-server", about = "Duplicate value filtering service")]
+struct Args {
+    #[arg(long, env = "SGFLT_LISTEN", default_value = "0.0.0.0:8080")]
+    listen: String,
+    // 默认appid，/v1/groups/... 路由使用；不使用配置文件时必填
+    #[arg(long, env = "SGFLT_APPID", required_unless_present = "config")]
+    appid: Option<String>,
+    // 后端url，例如 redis://:pass@127.0.0.1/ 或 file:///data/sgflt
+    #[arg(long, env = "SGFLT_BACKEND", required_unless_present = "config")]
+    backend: Option<String>,
+    // 策略配置文件(toml/yaml/json)，apps下的每个appid都会被加载，设置后忽略backend、fixed、ladder等参数
+    // SGFLT_FP_RATE、SGFLT_STRATEGY__{appid} 等环境变量会覆盖文件中的值
+    #[arg(long, env = "SGFLT_CONFIG")]
+    config: Option<String>,
+    // 重新加载配置文件的间隔，单位s，0表示不重新加载
+    #[arg(long, env = "SGFLT_RELOAD_INTERVAL", default_value_t = 10)]
+    reload_interval: u64,
+    #[arg(long)]
+    fixed: Option<usize>,
+    #[arg(long, value_delimiter = ',', default_value = "100,1000,5000")]
+    ladder: Vec<usize>,
+    #[arg(long, default_value_t = 0.001)]
+    fp_rate: f64,
+    #[arg(long, default_value_t = 3600)]
+    timestamp_size: i64,
+    // 迁移目标后端url，设置后双写，sgflt-migrate --switch-over 之后切换读取
+    #[arg(long, env = "SGFLT_MIGRATE_TO")]
+    migrate_to: Option<String>,
+    // otlp/http地址，例如 http://127.0.0.1:4318/v1/traces，不设置时不导出span
+    #[arg(long, env = "SGFLT_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+    // 认证配置文件(toml/yaml/json)，包含api key和jwt，不设置时不校验身份
+    #[arg(long, env = "SGFLT_AUTH")]
+    auth: Option<String>,
+    // 单独监听 /metrics 的地址，例如 127.0.0.1:9090，该端口不做认证
+    #[arg(long, env = "SGFLT_METRICS_LISTEN")]
+    metrics_listen: Option<String>,
+}
+
+impl Args {
+    // 读取配置文件，没有配置文件时由命令行参数构建只有一个appid的配置
+    fn config(&self) -> anyhow::Result<StrategyConfig> {
+        if let Some(path) = self.config.as_ref() {
+            return StrategyConfig::from_file(path)?
+                .apply_env(DEFAULT_ENV_PREFIX, std::env::vars());
+        }
+        let strategy = match self.fixed {
+            Some(size) => StrategyKind::Fixed { size },
+            None => StrategyKind::Ladder {
+                sizes: self.ladder.clone(),
+            },
+        };
+        let app = AppConfig {
+            backend: self.backend.clone(),
+            strategy: Some(strategy),
+            fp_rate: Some(self.fp_rate),
+            timestamp_size: Some(self.timestamp_size),
+            retention: None,
+            shards: None,
+            filter: None,
+            quota: None,
+            migrate_to: self.migrate_to.clone(),
+        };
+        let appid = self.appid.clone().unwrap_or_default();
+        Ok(StrategyConfig {
+            default: AppConfig::default(),
+            apps: BTreeMap::from([(appid, app)]),
+        })
+    }
+
+    fn registry(&self) -> anyhow::Result<Arc<TenantRegistry>> {
+        let registry = TenantRegistry::new(self.config()?)?;
+        if let Some(appid) = self.appid.as_ref() {
+            if registry.get(appid).is_none() {
+                return Err(anyhow::anyhow!("appid[{}] not found in config", appid));
+            }
+        }
+        Ok(Arc::new(registry))
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let provider = telemetry::init(args.otlp_endpoint.as_deref())?;
+    let registry = args.registry()?;
+    if args.config.is_some() && args.reload_interval > 0 {
+        let interval = Duration::from_secs(args.reload_interval);
+        let loader = args.clone();
+        registry.clone().watch(interval, move || loader.config());
+    }
+    let mut state = http::AppState::new(registry, args.appid.clone());
+    if let Some(path) = args.auth.as_ref() {
+        state = state.set_auth(auth::Authenticator::from_file(path)?);
+    }
+    if let Some(addr) = args.metrics_listen.as_ref() {
+        let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+        tracing::info!("sgflt metrics listen on {}", addr);
+        tokio::spawn(async move { axum::serve(listener, http::metrics_router()).await });
+    }
+    let listener = tokio::net::TcpListener::bind(args.listen.as_str()).await?;
+    tracing::info!("sgflt server listen on {}", args.listen);
+    axum::serve(listener, http::router(state)).await?;
+    provider.shutdown()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::auth::{sha256_hex, ApiKeyConfig, AuthConfig, Authenticator, Role};
+    use crate::http::{metrics_router, router, AppState, AppsResponse, ContainResponse};
+    use crate::Args;
+    use clap::Parser;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_args_config() {
+        let args = Args::parse_from([
+            "sgflt-server",
+            "--appid",
+            "biz02",
+            "--backend",
+            "memory://",
+            "--fixed",
+            "10",
+        ]);
+        let registry = args.registry().unwrap();
+        assert_eq!(registry.appids(), vec!["biz02"]);
+        assert_eq!(
+            registry.get("biz02").unwrap().config().strategy.to_string(),
+            "fixed:10"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sgflt.toml");
+        let text = "backend = \"memory://\"\n[apps.biz02]\n[apps.biz03]\nstrategy = { kind = \"fixed\", size = 10 }";
+        std::fs::write(&path, text).unwrap();
+        let path = path.to_str().unwrap();
+        let args = Args::parse_from(["sgflt-server", "--config", path]);
+        assert_eq!(args.registry().unwrap().appids(), vec!["biz02", "biz03"]);
+        let args = Args::parse_from(["sgflt-server", "--appid", "biz04", "--config", path]);
+        assert!(args.registry().is_err());
+
+        std::fs::write(path, "fp_rate = 0.001").unwrap();
+        let args = Args::parse_from(["sgflt-server", "--config", path]);
+        assert!(args.registry().is_err());
+        assert!(Args::try_parse_from(["sgflt-server", "--appid", "biz02"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = format!(
+            "strategy = {{ kind = \"fixed\", size = 10 }}\n[apps.biz_http]\nbackend = \"file://{}\"\n[apps.biz_mem]\nbackend = \"memory://\"",
+            dir.path().display()
+        );
+        let registry = Arc::new(
+            sgflt::TenantRegistry::new(sgflt::StrategyConfig::from_toml(&text).unwrap()).unwrap(),
+        );
+        let state = AppState::new(registry, Some("biz_http".into()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+
+        let body = r#"{"keys":["a","b"]}"#;
+        let (status, _) = request(addr, "POST", "/v1/groups/user001/insert", body).await;
+        assert_eq!(status, 200);
+        let body = r#"{"keys":["a","b","c"]}"#;
+        let (status, resp) = request(addr, "POST", "/v1/groups/user001/contain", body).await;
+        assert_eq!(status, 200);
+        let resp: ContainResponse = serde_json::from_str(resp.as_str()).unwrap();
+        assert_eq!(resp.result, vec![true, true, false]);
+
+        // 不同appid的数据互相隔离
+        let path = "/v1/apps/biz_mem/groups/user001/contain";
+        let (status, resp) = request(addr, "POST", path, body).await;
+        assert_eq!(status, 200);
+        let resp: ContainResponse = serde_json::from_str(resp.as_str()).unwrap();
+        assert_eq!(resp.result, vec![false, false, false]);
+        let path = "/v1/apps/biz_http/groups/user001/contain";
+        let (_, resp) = request(addr, "POST", path, body).await;
+        let resp: ContainResponse = serde_json::from_str(resp.as_str()).unwrap();
+        assert_eq!(resp.result, vec![true, true, false]);
+        let path = "/v1/apps/biz_none/groups/user001/contain";
+        let (status, _) = request(addr, "POST", path, body).await;
+        assert_eq!(status, 404);
+
+        let (status, resp) = request(addr, "GET", "/v1/apps", "").await;
+        assert_eq!(status, 200);
+        let resp: AppsResponse = serde_json::from_str(resp.as_str()).unwrap();
+        assert_eq!(resp.apps, vec!["biz_http", "biz_mem"]);
+
+        let (status, resp) = request(addr, "GET", "/metrics", "").await;
+        assert_eq!(status, 200);
+        assert!(resp.contains(r#"sgflt_pool_requests_total{appid="biz_http",backend="file",operation="batch_insert",status="ok"} 1"#));
+        assert!(resp.contains(
+            r#"sgflt_group_events_total{appid="biz_http",backend="file",event="extend"}"#
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_http_auth() {
+        let text = "backend = \"memory://\"\n[apps.biz02]\n[apps.biz03]";
+        let registry = Arc::new(
+            sgflt::TenantRegistry::new(sgflt::StrategyConfig::from_toml(text).unwrap()).unwrap(),
+        );
+        let key = |name: &str, appid: &str, role| ApiKeyConfig {
+            name: name.into(),
+            key_sha256: sha256_hex(name),
+            appids: vec![appid.into()],
+            role,
+        };
+        let auth = Authenticator::new(AuthConfig {
+            api_keys: vec![
+                key("biz02-writer", "biz02", Role::ReadWrite),
+                key("biz03-reader", "biz03", Role::Read),
+                key("ops", "*", Role::Read),
+            ],
+            jwt: None,
+        })
+        .unwrap();
+        let state = AppState::new(registry, None).set_auth(auth);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+
+        let body = r#"{"keys":["a"]}"#;
+        let path = "/v1/apps/biz02/groups/user001/insert";
+        let (status, _) = request(addr, "POST", path, body).await;
+        assert_eq!(status, 401);
+        let (status, _) = send(addr, "POST", path, body, Some("wrong")).await;
+        assert_eq!(status, 401);
+        let (status, _) = send(addr, "POST", path, body, Some("biz02-writer")).await;
+        assert_eq!(status, 200);
+        // 跨租户访问和只读key写入
+        let (status, _) = send(addr, "POST", path, body, Some("biz03-reader")).await;
+        assert_eq!(status, 403);
+        let path = "/v1/apps/biz03/groups/user001/insert";
+        let (status, _) = send(addr, "POST", path, body, Some("biz03-reader")).await;
+        assert_eq!(status, 403);
+        let path = "/v1/apps/biz03/groups/user001/contain";
+        let (status, _) = send(addr, "POST", path, body, Some("biz03-reader")).await;
+        assert_eq!(status, 200);
+        // 无权限的appid返回403而不是404
+        let path = "/v1/apps/biz_none/groups/user001/contain";
+        let (status, _) = send(addr, "POST", path, body, Some("biz03-reader")).await;
+        assert_eq!(status, 403);
+
+        let (_, resp) = send(addr, "GET", "/v1/apps", "", Some("biz03-reader")).await;
+        let resp: AppsResponse = serde_json::from_str(resp.as_str()).unwrap();
+        assert_eq!(resp.apps, vec!["biz03"]);
+        // 开启认证后 /metrics 需要所有appid的权限，单独监听的metrics_router不需要
+        let (status, _) = request(addr, "GET", "/metrics", "").await;
+        assert_eq!(status, 401);
+        let (status, _) = send(addr, "GET", "/metrics", "", Some("biz03-reader")).await;
+        assert_eq!(status, 403);
+        let (status, _) = send(addr, "GET", "/metrics", "", Some("ops")).await;
+        assert_eq!(status, 200);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, metrics_router()).await });
+        let (status, _) = request(addr, "GET", "/metrics", "").await;
+        assert_eq!(status, 200);
+    }
+
+    async fn request(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> (u16, String) {
+        send(addr, method, path, body, None).await
+    }
+
+    // 测试用的最简http客户端，token不为空时带上 Authorization: Bearer
+    async fn send(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+        token: Option<&str>,
+    ) -> (u16, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let auth = token
+            .map(|t| format!("Authorization: Bearer {}\r\n", t))
+            .unwrap_or_default();
+        let req = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            auth,
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).await.unwrap();
+        let status = buf[9..12].parse().unwrap();
+        let body = buf.split_once("\r\n\r\n").map(|x| x.1).unwrap_or("");
+        (status, body.to_string())
+    }
 }
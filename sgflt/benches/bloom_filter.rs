@@ -0,0 +1,110 @@
+// BasicBloomFilter 与 BlockedBloomFilter 的对比，只使用进程内后端
+// cargo bench -p sgflt --bench bloom_filter
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sgflt::{
+    BasicBloomFilter, Bitmap, BitmapFile, BitmapMemory, BlockedBloomFilter, FilterInfoMemory,
+    FiltersInfo, SingleKeyFilter,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+// 100万容量的chunk，bitmap约1.8MB，超过L2缓存
+const CAPACITY: usize = 1_000_000;
+const FP_RATE: f64 = 0.001;
+const KEYS: usize = 1000;
+
+fn chunk(kind: &str, bitmap: Arc<dyn Bitmap>) -> Arc<dyn SingleKeyFilter> {
+    let info: Arc<dyn FiltersInfo> = Arc::new(FilterInfoMemory::new());
+    match kind {
+        "basic" => Arc::new(BasicBloomFilter::new(
+            "g", "c0", info, bitmap, CAPACITY, FP_RATE,
+        )),
+        _ => Arc::new(BlockedBloomFilter::new(
+            "g", "c0", info, bitmap, CAPACITY, FP_RATE,
+        )),
+    }
+}
+
+fn keys(prefix: &str) -> Vec<String> {
+    (0..KEYS).map(|i| format!("{}_{}", prefix, i)).collect()
+}
+
+// 填充一半容量后返回
+fn filled(
+    rt: &tokio::runtime::Runtime,
+    kind: &str,
+    bitmap: Arc<dyn Bitmap>,
+) -> Arc<dyn SingleKeyFilter> {
+    let chunk = chunk(kind, bitmap);
+    rt.block_on(async {
+        let mut total = HashMap::new();
+        let mut growth = HashMap::new();
+        let mut buf: HashMap<String, HashSet<usize>> = HashMap::new();
+        for i in 0..CAPACITY / 2 {
+            let bits = chunk
+                .pre_insert(format!("fill_{}", i).as_bytes(), &mut total, &mut growth)
+                .await
+                .unwrap();
+            buf.entry(chunk.code()).or_default().extend(bits);
+        }
+        chunk.commit_insert(&mut buf, &mut growth).await.unwrap();
+    });
+    chunk
+}
+
+fn bench_probe(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("probe");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for kind in ["basic", "blocked"] {
+        let chunk = filled(&rt, kind, Arc::new(BitmapMemory::new()));
+        // 已经拉取bitmap，只测进程内的hash和bit检查
+        let mut fetched = HashMap::new();
+        rt.block_on(chunk.pre_contain(b"", &mut fetched)).unwrap();
+        let keys = keys("probe");
+        group.bench_function(BenchmarkId::new("prefetched", kind), |b| {
+            b.iter(|| {
+                futures::executor::block_on(async {
+                    for key in keys.iter() {
+                        chunk
+                            .pre_contain(key.as_bytes(), &mut fetched)
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_backend(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("contain");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for kind in ["basic", "blocked"] {
+        let backends: [(&str, Arc<dyn Bitmap>); 2] = [
+            ("memory", Arc::new(BitmapMemory::new())),
+            (
+                "file",
+                Arc::new(BitmapFile::new(dir.path().join(kind)).unwrap()),
+            ),
+        ];
+        for (name, bitmap) in backends {
+            let chunk = filled(&rt, kind, bitmap);
+            let keys = keys("contain");
+            group.bench_function(BenchmarkId::new(kind, name), |b| {
+                b.to_async(&rt).iter(|| async {
+                    for key in keys.iter() {
+                        chunk.contain(key.as_bytes()).await.unwrap();
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_probe, bench_backend);
+criterion_main!(benches);
@@ -0,0 +1,259 @@
+// 基准测试：BasicBloomFilter的hash和下标计算、FilterGroup批量接口、各个Bitmap后端
+// 默认只跑进程内后端；PATH中有redis-server时通过 testing::RedisServer 在随机端口启动一个实例，
+// 也可以通过 SGFLT_BENCH_REDIS=redis://127.0.0.1:6379/ 指定已有的redis
+// cargo bench -p sgflt --bench suite
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput};
+use sgflt::{
+    BasicBloomFilter, Bitmap, BitmapFile, BitmapMemory, BitmapRedis, BloomExpandStrategy,
+    FilterExpandStrategy, FilterGroup, FilterInfoMemory, FiltersInfo, RedisServer, SingleKeyFilter,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const KEYS: usize = 1000;
+const FP_RATE: f64 = 0.001;
+
+fn keys(prefix: &str) -> Vec<String> {
+    (0..KEYS).map(|i| format!("{}_{}", prefix, i)).collect()
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().unwrap()
+}
+
+// 一次运行的环境，由main持有，结束时关闭本地启动的redis
+// 每次运行使用不同的appid(不含key的分隔符)，已有的redis中不会累积上次运行的数据
+struct BenchEnv {
+    _server: Option<RedisServer>,
+    redis_url: Option<String>,
+    appid: String,
+}
+
+impl BenchEnv {
+    fn new() -> Self {
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let (server, redis_url) = match std::env::var("SGFLT_BENCH_REDIS") {
+            Ok(url) => (None, Some(url)),
+            Err(_) => {
+                let server = RedisServer::try_start();
+                let url = server.as_ref().map(|s| s.url());
+                (server, url)
+            }
+        };
+        Self {
+            _server: server,
+            redis_url,
+            appid: format!("bench{}x{}", ts, std::process::id()),
+        }
+    }
+
+    fn redis_url(&self) -> Option<&str> {
+        self.redis_url.as_deref()
+    }
+
+    // 每个后端单独的key，避免相互影响
+    fn bitmaps(&self, dir: &std::path::Path) -> Vec<(&'static str, Arc<dyn Bitmap>)> {
+        let mut list: Vec<(&'static str, Arc<dyn Bitmap>)> = vec![
+            ("memory", Arc::new(BitmapMemory::new())),
+            ("file", Arc::new(BitmapFile::new(dir).unwrap())),
+        ];
+        if let Some(url) = self.redis_url() {
+            list.push((
+                "redis",
+                Arc::new(BitmapRedis::redis_single_node(url).unwrap()),
+            ));
+        }
+        list
+    }
+
+    fn strategies(&self, dir: &std::path::Path) -> Vec<(&'static str, BloomExpandStrategy)> {
+        let appid = self.appid.as_str();
+        let mut list = vec![
+            ("memory", BloomExpandStrategy::build_from_memory(appid)),
+            (
+                "file",
+                BloomExpandStrategy::build_from_file(appid, dir).unwrap(),
+            ),
+        ];
+        if let Some(url) = self.redis_url() {
+            list.push((
+                "redis",
+                BloomExpandStrategy::build_from_redis(appid, url).unwrap(),
+            ));
+        }
+        list
+    }
+}
+
+// 删除本次运行写入的group
+async fn drop_groups(strategy: &BloomExpandStrategy) {
+    for group in strategy.groups().await.unwrap() {
+        strategy.drop_group(group.as_str()).await.unwrap();
+    }
+}
+
+// pre_insert 只做hash和下标计算；pre_contain 在已拉取的bitmap上检查
+fn bench_hash(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("bloom_filter");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for capacity in [1_000usize, 1_000_000] {
+        let info: Arc<dyn FiltersInfo> = Arc::new(FilterInfoMemory::new());
+        let bitmap: Arc<dyn Bitmap> = Arc::new(BitmapMemory::new());
+        let chunk = BasicBloomFilter::new("g", "c0", info, bitmap, capacity, FP_RATE);
+        let keys = keys("hash");
+        let total = HashMap::from([("c0".to_string(), 0usize)]);
+        group.bench_function(BenchmarkId::new("pre_insert", capacity), |b| {
+            b.iter(|| {
+                futures::executor::block_on(async {
+                    let mut total = total.clone();
+                    let mut growth = HashMap::new();
+                    for key in keys.iter() {
+                        chunk
+                            .pre_insert(key.as_bytes(), &mut total, &mut growth)
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+        });
+
+        let mut buf: HashMap<String, HashSet<usize>> = HashMap::new();
+        let mut growth = HashMap::new();
+        rt.block_on(async {
+            let mut total = total.clone();
+            for key in keys.iter().step_by(2) {
+                let bits = chunk
+                    .pre_insert(key.as_bytes(), &mut total, &mut growth)
+                    .await
+                    .unwrap();
+                buf.entry("c0".to_string()).or_default().extend(bits);
+            }
+            chunk.commit_insert(&mut buf, &mut growth).await.unwrap();
+        });
+        let mut fetched = HashMap::new();
+        rt.block_on(chunk.pre_contain(b"", &mut fetched)).unwrap();
+        group.bench_function(BenchmarkId::new("pre_contain", capacity), |b| {
+            b.iter(|| {
+                futures::executor::block_on(async {
+                    for key in keys.iter() {
+                        chunk
+                            .pre_contain(key.as_bytes(), &mut fetched)
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+// 每次插入使用新的group，避免chunk写满后不断扩容
+fn bench_filter_group(c: &mut Criterion, env: &BenchEnv) {
+    let rt = runtime();
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("filter_group");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    group.sample_size(20);
+    let mut created = vec![];
+    for (backend, strategy) in env.strategies(dir.path()) {
+        let strategy = Arc::new(strategy.set_strategy_fixed(2000));
+        created.push(strategy.clone());
+        let strategy: Arc<dyn FilterExpandStrategy> = strategy;
+        let keys = keys("group")
+            .into_iter()
+            .map(String::into_bytes)
+            .collect::<Vec<_>>();
+        let seq = AtomicUsize::new(0);
+        group.bench_function(BenchmarkId::new("batch_insert", backend), |b| {
+            b.to_async(&rt).iter_batched(
+                || {
+                    let name = format!("insert_{}", seq.fetch_add(1, Ordering::Relaxed));
+                    (FilterGroup::new(name, strategy.clone()), keys.clone())
+                },
+                |(fg, keys)| async move { fg.batch_insert(keys).await.unwrap() },
+                BatchSize::SmallInput,
+            )
+        });
+
+        // 4个写满的chunk，一半的key已经存在
+        let fg = FilterGroup::new("contain".to_string(), strategy.clone());
+        rt.block_on(async {
+            for i in 0..4 {
+                let fill = (0..2000)
+                    .map(|j| format!("fill_{}_{}", i, j).into_bytes())
+                    .collect();
+                fg.batch_insert(fill).await.unwrap();
+            }
+            fg.batch_insert(keys.iter().step_by(2).cloned().collect())
+                .await
+                .unwrap();
+        });
+        group.bench_function(BenchmarkId::new("batch_contain", backend), |b| {
+            b.to_async(&rt)
+                .iter(|| async { fg.batch_contain(keys.clone()).await.unwrap() })
+        });
+    }
+    group.finish();
+    for strategy in created.iter() {
+        rt.block_on(drop_groups(strategy));
+    }
+}
+
+fn bench_bitmap(c: &mut Criterion, env: &BenchEnv) {
+    let rt = runtime();
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("bitmap");
+    // 约等于容量1万、fp 0.001的chunk
+    let m = 143_776usize;
+    let offsets = (0..KEYS * 10)
+        .map(|i| i.wrapping_mul(2_654_435_761) % m)
+        .collect::<Vec<_>>();
+    for (backend, bitmap) in env.bitmaps(dir.path()) {
+        let key = format!("{}_bitmap_{}", env.appid, backend);
+        rt.block_on(bitmap.mul_set(key.as_str(), offsets.iter().copied().collect()))
+            .unwrap();
+        group.throughput(Throughput::Elements(1));
+        group.bench_function(BenchmarkId::new("set", backend), |b| {
+            b.to_async(&rt)
+                .iter(|| async { bitmap.set(key.as_str(), 7, true).await.unwrap() })
+        });
+        group.bench_function(BenchmarkId::new("get_bits", backend), |b| {
+            b.to_async(&rt)
+                .iter(|| async { bitmap.get_bits(key.as_str(), &offsets[..10]).await.unwrap() })
+        });
+        group.bench_function(BenchmarkId::new("mul_get", backend), |b| {
+            b.to_async(&rt)
+                .iter(|| async { bitmap.mul_get(key.as_str()).await.unwrap() })
+        });
+        group.bench_function(BenchmarkId::new("count_ones", backend), |b| {
+            b.to_async(&rt)
+                .iter(|| async { bitmap.count_ones(key.as_str()).await.unwrap() })
+        });
+        group.throughput(Throughput::Elements(offsets.len() as u64));
+        let set = offsets.iter().copied().collect::<HashSet<_>>();
+        group.bench_function(BenchmarkId::new("mul_set", backend), |b| {
+            b.to_async(&rt).iter_batched(
+                || set.clone(),
+                |set| async { bitmap.mul_set(key.as_str(), set).await.unwrap() },
+                BatchSize::SmallInput,
+            )
+        });
+        rt.block_on(bitmap.del(key.as_str())).unwrap();
+    }
+    group.finish();
+}
+
+fn main() {
+    let env = BenchEnv::new();
+    let mut c = Criterion::default().configure_from_args();
+    bench_hash(&mut c);
+    bench_filter_group(&mut c, &env);
+    bench_bitmap(&mut c, &env);
+    c.final_summary();
+}
@@ -0,0 +1,491 @@
+use crate::error::SgfitErr;
+use crate::{
+    generate_hasher, hash_key, Bitmap, ChunkMeta, ChunkStats, FiltersInfo, SingleKeyFilter,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use std::sync::Arc;
+use tracing::Instrument;
+use wd_tools::PFErr;
+
+// 一个block的bit数，与64字节的cache line对齐
+pub const BLOCK_BITS: usize = 512;
+const BLOCK_WORDS: usize = BLOCK_BITS / 64;
+
+// 以seed为种子的splitmix64生成k个 [0, range) 内互不相同的位置，跳过重复的位置
+// 等差数列形式的双重hash在范围较小时相关性太强，共用步长的key大量重叠，实际误判率明显高于估算
+pub(crate) fn distinct_positions(seed: u64, k: usize, range: usize) -> Vec<usize> {
+    let k = k.min(range);
+    let mut state = seed;
+    let mut list = Vec::with_capacity(k);
+    while list.len() < k {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let i = ((z ^ (z >> 31)) % range as u64) as usize;
+        if !list.contains(&i) {
+            list.push(i);
+        }
+    }
+    list
+}
+
+// 不小于start且满足fits的最小值，fits随参数单调：先倍增找到上界再二分
+pub(crate) fn smallest_fit(start: usize, fits: impl Fn(usize) -> bool) -> usize {
+    let start = start.max(1);
+    if fits(start) {
+        return start;
+    }
+    let (mut lo, mut hi) = (start, start * 2);
+    while !fits(hi) {
+        (lo, hi) = (hi, hi * 2);
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if fits(mid) {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    hi
+}
+
+// key按hash分到parts个分区(block或分片)，每个分区bits个bit，k个位置在分区内互不相同
+// 分区中的key数服从均值 n/parts 的泊松分布，误判率为各分区误判率按分布加权
+// 分区中有i个key时每个bit被置位的概率为 1-(1-k/bits)^i
+pub(crate) fn partitioned_fp(parts: usize, bits: usize, items_count: usize, k: u32) -> f64 {
+    let lambda = items_count as f64 / parts.max(1) as f64;
+    if lambda <= 0.0 {
+        return 0.0;
+    }
+    let empty = (1.0 - k as f64 / bits as f64).max(0.0);
+    let spread = 12.0 * lambda.sqrt() + 16.0;
+    let start = (lambda - spread).max(0.0) as u64;
+    let end = (lambda + spread).ceil() as u64;
+    let mut ln_fact = ln_factorial(start);
+    let mut fp = 0.0;
+    for i in start..=end {
+        if i > start {
+            ln_fact += (i as f64).ln();
+        }
+        let pmf = (i as f64 * lambda.ln() - lambda - ln_fact).exp();
+        fp += pmf * (1.0 - empty.powf(i as f64)).powi(k as i32);
+    }
+    fp
+}
+
+// 较大的n使用Stirling级数
+fn ln_factorial(n: u64) -> f64 {
+    if n < 32 {
+        return (2..=n).map(|i| (i as f64).ln()).sum();
+    }
+    let n = n as f64;
+    n * n.ln() - n + 0.5 * (2.0 * core::f64::consts::PI * n).ln() + 1.0 / (12.0 * n)
+        - 1.0 / (360.0 * n * n * n)
+}
+
+// 分块布隆过滤器：h1选择block，k个bit都落在同一个512bit的block中
+// 查询只访问一个cache line，适合memory和file(mmap)后端；相同m下误判率略高于BasicBloomFilter
+// bit顺序与Bitmap一致(字节内高位在前)，block b 对应字节 [b*64, b*64+64)
+pub struct BlockedBloomFilter {
+    group: String,
+    code: String,
+    info: Arc<dyn FiltersInfo + 'static>,
+    bitmap: Arc<dyn Bitmap + 'static>,
+    blocks: usize,
+    optimal_k: u32,
+    items_count: usize,
+    fp_rate: f64,
+    hashes: [DefaultHasher; 2],
+}
+
+impl BlockedBloomFilter {
+    pub fn new<I: Into<String>>(
+        group: I,
+        code: I,
+        info: Arc<dyn FiltersInfo + 'static>,
+        bitmap: Arc<dyn Bitmap + 'static>,
+        items_count: usize,
+        fp_rate: f64,
+    ) -> Self {
+        let ln2_2 = core::f64::consts::LN_2 * core::f64::consts::LN_2;
+        let m = ((-(items_count as f64) * fp_rate.ln()) / ln2_2).ceil() as usize;
+        let optimal_k = ((-fp_rate.ln()) / core::f64::consts::LN_2).ceil() as u32;
+        let blocks = Self::blocks_for(
+            m.div_ceil(BLOCK_BITS).max(1),
+            items_count,
+            optimal_k,
+            fp_rate,
+        );
+
+        let group = group.into();
+        let code = code.into();
+        let hashes = [
+            generate_hasher(group.as_str()),
+            generate_hasher(code.as_str()),
+        ];
+        Self {
+            group,
+            code,
+            info,
+            bitmap,
+            blocks,
+            optimal_k,
+            items_count,
+            fp_rate,
+            hashes,
+        }
+    }
+
+    // 按标准布隆过滤器的m得到的block数误判率偏高，增加block数直到估算的误判率不超过目标
+    fn blocks_for(blocks: usize, items_count: usize, k: u32, fp_rate: f64) -> usize {
+        smallest_fit(blocks, |blocks| {
+            Self::estimate_fp(blocks, items_count, k) <= fp_rate
+        })
+    }
+    pub fn estimate_fp(blocks: usize, items_count: usize, k: u32) -> f64 {
+        partitioned_fp(blocks, BLOCK_BITS, items_count, k)
+    }
+
+    fn hash_kernel(&self, item: &[u8]) -> (u64, u64) {
+        let hasher1 = &mut self.hashes[0].clone();
+        let hasher2 = &mut self.hashes[1].clone();
+        hash_key(hasher1, item);
+        hash_key(hasher2, item);
+        (hasher1.finish(), hasher2.finish())
+    }
+    // block内的k个位置互不相同
+    fn block_bits(&self, h2: u64) -> Vec<usize> {
+        distinct_positions(h2, self.optimal_k as usize, BLOCK_BITS)
+    }
+    fn indexes(&self, item: &[u8]) -> Vec<usize> {
+        let (h1, h2) = self.hash_kernel(item);
+        let base = (h1 % self.blocks as u64) as usize * BLOCK_BITS;
+        self.block_bits(h2).into_iter().map(|i| base + i).collect()
+    }
+    // 按8个u64生成key在block中的掩码
+    fn block_mask(&self, h2: u64) -> [u64; BLOCK_WORDS] {
+        let mut mask = [0u64; BLOCK_WORDS];
+        for i in self.block_bits(h2) {
+            mask[i / 64] |= 0x8000_0000_0000_0000 >> (i % 64);
+        }
+        mask
+    }
+    // 在已经拉取的bitmap上按block比较掩码，逐lane的与运算可以被编译器向量化
+    fn contain_in(&self, item: &[u8], bitmap: &[u8]) -> bool {
+        let (h1, h2) = self.hash_kernel(item);
+        let start = (h1 % self.blocks as u64) as usize * (BLOCK_BITS / 8);
+        let mut block = [0u64; BLOCK_WORDS];
+        if let Some(bytes) = bitmap.get(start..) {
+            let bytes = &bytes[..bytes.len().min(BLOCK_BITS / 8)];
+            for (w, chunk) in block.iter_mut().zip(bytes.chunks(8)) {
+                let mut buf = [0u8; 8];
+                buf[..chunk.len()].copy_from_slice(chunk);
+                *w = u64::from_be_bytes(buf);
+            }
+        }
+        let mask = self.block_mask(h2);
+        block
+            .iter()
+            .zip(mask.iter())
+            .fold(true, |ok, (b, m)| ok & (b & m == *m))
+    }
+}
+
+#[async_trait::async_trait]
+impl SingleKeyFilter for BlockedBloomFilter {
+    fn code(&self) -> String {
+        self.code.clone()
+    }
+
+    fn meta(&self) -> ChunkMeta {
+        ChunkMeta {
+            code: self.code.clone(),
+            capacity: self.items_count,
+            m: self.blocks * BLOCK_BITS,
+            k: self.optimal_k,
+            fp_rate: self.fp_rate,
+        }
+    }
+
+    async fn stats(&self) -> anyhow::Result<ChunkStats> {
+        let count = self
+            .info
+            .count(self.group.as_str(), self.code.as_str())
+            .await?;
+        let bits = self.bitmap.count_ones(self.code.as_str()).await?;
+        Ok(ChunkStats::new(self.meta(), count, bits))
+    }
+
+    async fn is_full(&self) -> anyhow::Result<bool> {
+        Ok(self
+            .info
+            .count(self.group.as_str(), self.code.as_str())
+            .await?
+            >= self.items_count)
+    }
+
+    #[tracing::instrument(name = "chunk.insert", skip_all, fields(group = %self.group, code = %self.code))]
+    async fn insert(&self, item: &[u8]) -> anyhow::Result<()> {
+        if self.is_full().await? {
+            return anyhow::Error::new(SgfitErr::new_chunk_full(self.items_count)).err();
+        }
+        let index = self.indexes(item).into_iter().collect::<HashSet<_>>();
+        self.bitmap.mul_set(self.code.as_str(), index).await?;
+        if let Err(e) = self
+            .info
+            .add(self.group.as_str(), self.code.as_str(), 1)
+            .await
+        {
+            wd_log::log_field("error", e)
+                .field("code", self.code.as_str())
+                .warn("BlockedBloomFilter.insert add count failed")
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "chunk.contain", skip_all, fields(group = %self.group, code = %self.code))]
+    async fn contain(&self, item: &[u8]) -> anyhow::Result<bool> {
+        let index = self.indexes(item);
+        let bits = self
+            .bitmap
+            .get_bits(self.code.as_str(), index.as_slice())
+            .await?;
+        Ok(bits.into_iter().all(|x| x))
+    }
+
+    #[tracing::instrument(name = "chunk.probe", skip_all, fields(group = %self.group, code = %self.code))]
+    async fn fetch_bitmap(&self) -> anyhow::Result<Vec<u8>> {
+        self.bitmap.mul_get(self.code.as_str()).await
+    }
+
+    async fn store_bitmap(&self, bytes: Vec<u8>) -> anyhow::Result<()> {
+        self.bitmap.put_bytes(self.code.as_str(), bytes).await
+    }
+
+    async fn pre_insert(
+        &self,
+        item: &[u8],
+        total: &mut HashMap<String, usize>,
+        growth: &mut HashMap<String, usize>,
+    ) -> anyhow::Result<Vec<usize>> {
+        if !total.contains_key(self.code.as_str()) {
+            let current_total = self
+                .info
+                .count(self.group.as_str(), self.code.as_str())
+                .await?;
+            total.insert(self.code.clone(), current_total);
+        }
+        let count = total[self.code.as_str()] + growth.get(self.code.as_str()).unwrap_or(&0);
+        if count >= self.items_count {
+            return anyhow::Error::new(SgfitErr::new_chunk_full(self.items_count)).err();
+        }
+        *growth.entry(self.code.clone()).or_insert(0) += 1;
+        Ok(self.indexes(item))
+    }
+
+    async fn commit_insert(
+        &self,
+        buf: &mut HashMap<String, HashSet<usize>>,
+        growth: &mut HashMap<String, usize>,
+    ) -> anyhow::Result<()> {
+        let bits = buf.remove(self.code.as_str());
+        let count = growth.remove(self.code.as_str());
+        if bits.is_none() && count.is_none() {
+            return Ok(());
+        }
+        let span = tracing::info_span!(
+            "chunk.commit_insert",
+            group = %self.group,
+            code = %self.code,
+            bits = bits.as_ref().map(|x| x.len()).unwrap_or(0),
+            keys = count.unwrap_or(0)
+        );
+        async move {
+            if let Some(s) = bits {
+                self.bitmap.mul_set(self.code.as_str(), s).await?;
+            }
+            if let Some(i) = count {
+                self.info
+                    .add(self.group.as_str(), self.code.as_str(), i)
+                    .await?;
+            }
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn pre_contain(
+        &self,
+        item: &[u8],
+        buf: &mut HashMap<String, Vec<u8>>,
+    ) -> anyhow::Result<bool> {
+        if !buf.contains_key(self.code.as_str()) {
+            let bit = self.fetch_bitmap().await?;
+            buf.insert(self.code.clone(), bit);
+        }
+        Ok(self.contain_in(item, buf[self.code.as_str()].as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        BitmapMemory, BlockedBloomFilter, BloomExpandStrategy, FilterInfoMemory, FilterKind,
+        FiltersPool, SingleKeyFilter, BLOCK_BITS,
+    };
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_blocked_filter() {
+        let bitmap = BitmapMemory::new();
+        let chunk = BlockedBloomFilter::new(
+            "g",
+            "c0",
+            Arc::new(FilterInfoMemory::new()),
+            Arc::new(bitmap.clone()),
+            10000,
+            0.001,
+        );
+        let meta = chunk.meta();
+        assert_eq!(meta.m % BLOCK_BITS, 0);
+
+        let index = chunk.indexes(b"a");
+        assert_eq!(index.len(), meta.k as usize);
+        assert!(index
+            .iter()
+            .all(|i| i / BLOCK_BITS == index[0] / BLOCK_BITS));
+
+        let mut total = HashMap::new();
+        let mut growth = HashMap::new();
+        let mut buf: HashMap<String, HashSet<usize>> = HashMap::new();
+        for i in 0..5000 {
+            let bits = chunk
+                .pre_insert(format!("key_{}", i).as_bytes(), &mut total, &mut growth)
+                .await
+                .unwrap();
+            buf.entry("c0".to_string()).or_default().extend(bits);
+        }
+        chunk.commit_insert(&mut buf, &mut growth).await.unwrap();
+        chunk.insert(b"a").await.unwrap();
+        assert_eq!(chunk.stats().await.unwrap().count, 5001);
+
+        let mut fetched = HashMap::new();
+        for i in 0..5000 {
+            let key = format!("key_{}", i);
+            assert!(chunk
+                .pre_contain(key.as_bytes(), &mut fetched)
+                .await
+                .unwrap());
+        }
+        assert!(chunk.contain(b"a").await.unwrap());
+        let mut fp = 0;
+        for i in 0..10000 {
+            let key = format!("none_{}", i);
+            let hit = chunk
+                .pre_contain(key.as_bytes(), &mut fetched)
+                .await
+                .unwrap();
+            assert_eq!(hit, chunk.contain(key.as_bytes()).await.unwrap());
+            fp += hit as usize;
+        }
+        // 半满时误判率远低于目标
+        assert!(fp < 10, "fp = {}", fp);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_fp_rate() {
+        let standard = crate::BasicBloomFilter::new(
+            "g",
+            "c0",
+            Arc::new(FilterInfoMemory::new()),
+            Arc::new(BitmapMemory::new()),
+            20000,
+            0.01,
+        )
+        .meta();
+        let chunk = BlockedBloomFilter::new(
+            "g",
+            "c0",
+            Arc::new(FilterInfoMemory::new()),
+            Arc::new(BitmapMemory::new()),
+            20000,
+            0.01,
+        );
+        let meta = chunk.meta();
+        assert!(meta.m > standard.m);
+        assert!(BlockedBloomFilter::estimate_fp(meta.m / BLOCK_BITS, 20000, meta.k) <= 0.01);
+
+        // 装满之后实际误判率不超过目标加上抽样误差
+        let mut total = HashMap::new();
+        let mut growth = HashMap::new();
+        let mut buf: HashMap<String, HashSet<usize>> = HashMap::new();
+        for i in 0..20000 {
+            let bits = chunk
+                .pre_insert(format!("key_{}", i).as_bytes(), &mut total, &mut growth)
+                .await
+                .unwrap();
+            buf.entry("c0".to_string()).or_default().extend(bits);
+        }
+        chunk.commit_insert(&mut buf, &mut growth).await.unwrap();
+        let bitmap = chunk.fetch_bitmap().await.unwrap();
+        let probes = 100000;
+        let fp = (0..probes)
+            .filter(|i| chunk.contain_in(format!("none_{}", i).as_bytes(), bitmap.as_slice()))
+            .count() as f64
+            / probes as f64;
+        let sigma = (0.01 * 0.99 / probes as f64).sqrt();
+        assert!(fp <= 0.01 + 4.0 * sigma, "fp = {}", fp);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dir.path())
+            .unwrap()
+            .set_strategy_fixed(100)
+            .set_filter(FilterKind::Blocked);
+        let pool = FiltersPool::from(strategy);
+        let keys = (0..250).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys.clone()).await.unwrap();
+        pool.insert("user001", "a".into()).await.unwrap();
+        assert!(pool
+            .batch_contain("user001", keys.clone())
+            .await
+            .unwrap()
+            .into_iter()
+            .all(|x| x));
+        assert!(pool.contain("user001", "a".into()).await.unwrap());
+        let stats = pool.stats("user001").await.unwrap();
+        assert_eq!(stats.count, 251);
+        assert_eq!(stats.chunks.len(), 3);
+        assert_eq!(stats.chunks[0].m % BLOCK_BITS, 0);
+
+        // 过滤器类型和chunk一起保存，修改配置后已有的chunk仍按blocked读取
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dir.path())
+            .unwrap()
+            .set_strategy_fixed(100);
+        let pool = FiltersPool::from(strategy);
+        assert!(pool
+            .batch_contain("user001", keys.clone())
+            .await
+            .unwrap()
+            .into_iter()
+            .all(|x| x));
+        pool.insert("user001", "b".into()).await.unwrap();
+        let stats = pool.stats("user001").await.unwrap();
+        assert_eq!(stats.chunks.len(), 3);
+        assert_eq!(stats.chunks[2].m % BLOCK_BITS, 0);
+
+        let strategy = BloomExpandStrategy::build_from_memory("biz02")
+            .set_filter(FilterKind::Blocked)
+            .set_shards(2);
+        let pool = FiltersPool::from(strategy);
+        assert!(pool.insert("user001", "a".into()).await.is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{FiltersPool, GroupStats};
+use std::future::Future;
+use tokio::runtime::{Handle, Runtime};
+
+enum Executor {
+    // 自己持有的tokio运行时
+    Runtime(Runtime),
+    // 借用外部运行时，不能在该运行时的异步上下文中调用
+    Handle(Handle),
+    // 不依赖tokio，只适用于内存和本地文件这类同步后端
+    Local,
+}
+
+// FiltersPool的同步封装，供非async代码调用
+pub struct BlockingFiltersPool {
+    pool: FiltersPool,
+    executor: Executor,
+}
+
+impl BlockingFiltersPool {
+    // 创建一个专用的tokio运行时，redis等网络后端需要使用此方式
+    pub fn new(pool: FiltersPool) -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            pool,
+            executor: Executor::Runtime(runtime),
+        })
+    }
+    pub fn with_runtime(pool: FiltersPool, runtime: Runtime) -> Self {
+        Self {
+            pool,
+            executor: Executor::Runtime(runtime),
+        }
+    }
+    pub fn with_handle(pool: FiltersPool, handle: Handle) -> Self {
+        Self {
+            pool,
+            executor: Executor::Handle(handle),
+        }
+    }
+    // 不使用tokio，后端必须是同步实现(BitmapMemory、BitmapFile等)
+    pub fn without_runtime(pool: FiltersPool) -> Self {
+        Self {
+            pool,
+            executor: Executor::Local,
+        }
+    }
+
+    pub fn pool(&self) -> &FiltersPool {
+        &self.pool
+    }
+
+    fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        match &self.executor {
+            Executor::Runtime(rt) => rt.block_on(fut),
+            Executor::Handle(handle) => handle.block_on(fut),
+            Executor::Local => futures::executor::block_on(fut),
+        }
+    }
+
+    pub fn contain(&self, group: &str, key: String) -> anyhow::Result<bool> {
+        self.block_on(self.pool.contain(group, key))
+    }
+    pub fn insert(&self, group: &str, key: String) -> anyhow::Result<()> {
+        self.block_on(self.pool.insert(group, key))
+    }
+    pub fn batch_contain(&self, group: &str, keys: Vec<String>) -> anyhow::Result<Vec<bool>> {
+        self.block_on(self.pool.batch_contain(group, keys))
+    }
+    pub fn batch_insert(&self, group: &str, keys: Vec<String>) -> anyhow::Result<()> {
+        self.block_on(self.pool.batch_insert(group, keys))
+    }
+    pub fn contain_bytes(&self, group: &str, key: Vec<u8>) -> anyhow::Result<bool> {
+        self.block_on(self.pool.contain_bytes(group, key))
+    }
+    pub fn insert_bytes(&self, group: &str, key: Vec<u8>) -> anyhow::Result<()> {
+        self.block_on(self.pool.insert_bytes(group, key))
+    }
+    pub fn batch_contain_bytes(
+        &self,
+        group: &str,
+        keys: Vec<Vec<u8>>,
+    ) -> anyhow::Result<Vec<bool>> {
+        self.block_on(self.pool.batch_contain_bytes(group, keys))
+    }
+    pub fn batch_insert_bytes(&self, group: &str, keys: Vec<Vec<u8>>) -> anyhow::Result<()> {
+        self.block_on(self.pool.batch_insert_bytes(group, keys))
+    }
+    pub fn stats(&self, group: &str) -> anyhow::Result<GroupStats> {
+        self.block_on(self.pool.stats(group))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BlockingFiltersPool, BloomExpandStrategy, FiltersPool};
+
+    fn check(pool: &BlockingFiltersPool) {
+        let keys = (0..25).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys.clone()).unwrap();
+        pool.insert("user001", "key_single".into()).unwrap();
+        let result = pool.batch_contain("user001", keys).unwrap();
+        assert!(result.into_iter().all(|x| x));
+        assert!(pool.contain("user001", "key_single".into()).unwrap());
+        assert!(!pool.contain("user001", "none".into()).unwrap());
+        // utf8的key按字节和按String传入结果一致，非utf8的key同样可用
+        assert!(pool
+            .contain_bytes("user001", b"key_single".to_vec())
+            .unwrap());
+        let result = pool
+            .batch_contain_bytes("user001", vec![b"key_1".to_vec()])
+            .unwrap();
+        assert_eq!(result, vec![true]);
+        pool.insert_bytes("user001", vec![0xff, 0x00]).unwrap();
+        pool.batch_insert_bytes("user001", vec![vec![0xfe]])
+            .unwrap();
+        assert!(pool.contain_bytes("user001", vec![0xff, 0x00]).unwrap());
+        assert!(pool.contain_bytes("user001", vec![0xfe]).unwrap());
+        assert_eq!(pool.stats("user001").unwrap().count, 28);
+    }
+
+    #[test]
+    fn test_blocking_without_runtime() {
+        let strategy = BloomExpandStrategy::build_from_memory("biz02").set_strategy_fixed(10);
+        check(&BlockingFiltersPool::without_runtime(FiltersPool::from(
+            strategy,
+        )));
+
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dir.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        check(&BlockingFiltersPool::without_runtime(FiltersPool::from(
+            strategy,
+        )));
+    }
+
+    #[test]
+    fn test_blocking_with_runtime() {
+        let strategy = BloomExpandStrategy::build_from_url("biz02", "memory://")
+            .unwrap()
+            .set_strategy_fixed(10);
+        check(&BlockingFiltersPool::new(FiltersPool::from(strategy)).unwrap());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let strategy = BloomExpandStrategy::build_from_memory("biz02").set_strategy_fixed(10);
+        check(&BlockingFiltersPool::with_handle(
+            FiltersPool::from(strategy),
+            rt.handle().clone(),
+        ));
+    }
+}
@@ -1,9 +1,14 @@
-use crate::bloom_filter::BasicBloomFilter;
-use crate::util::assembly_prefix;
+use crate::bloom_filter::{shard_keys, BasicBloomFilter};
 use crate::{
-    Bitmap, BitmapRedis, FilterExpandStrategy, FilterInfoRedis, FiltersInfo, RedisClient,
-    SingleKeyFilter,
+    AppUsage, Bitmap, BitmapFile, BitmapMemory, BitmapRedis, BlockedBloomFilter, ChunkRegistration,
+    ChunkSpec, DelimitedKeyLayout, FilterExpandStrategy, FilterInfoFile, FilterInfoMemory,
+    FilterInfoRedis, FiltersInfo, KeyLayout, Quota, QuotaConfig, QuotaKind, RedisClient,
+    RedisConfig, RedisTlsConfig, SgfitErr, SingleKeyFilter, CHUNK_LAYOUT,
 };
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
 use wd_tools::{PFArc, PFErr, PFOk};
 
@@ -24,7 +29,7 @@ impl Default for Strategy {
 
 impl Strategy {
     pub fn chunk_size(&self, index: usize) -> anyhow::Result<usize> {
-        return match self {
+        match self {
             Strategy::Fixed(n) => Ok(*n),
             Strategy::Ladder(list) => {
                 if let Some(n) = list.get(index) {
@@ -39,7 +44,28 @@ impl Strategy {
                 }
             }
             Strategy::Function(function) => function(index).ok(),
-        };
+        }
+    }
+}
+
+// chunk使用的过滤器实现
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    #[default]
+    Basic,
+    // 512bit分块，适合memory和file后端，不支持分片
+    Blocked,
+}
+
+impl FromStr for FilterKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "basic" => Ok(FilterKind::Basic),
+            "blocked" => Ok(FilterKind::Blocked),
+            _ => anyhow::anyhow!("unknown filter kind[{}]", s).err(),
+        }
     }
 }
 
@@ -49,7 +75,12 @@ pub struct BloomExpandStrategy {
     strategy: Strategy,
     bitmap: Arc<dyn Bitmap + 'static>,
     fp_rate: f64,
-    timestamp_size: i64, //单位s
+    timestamp_size: i64,    //单位s
+    retention: Option<i64>, //单位s，超过保留时间的chunk加载时忽略
+    layout: Arc<dyn KeyLayout + 'static>,
+    quota: Arc<Quota>,
+    shards: usize, //每个chunk的bitmap拆分成的key数
+    filter: FilterKind,
 }
 
 impl BloomExpandStrategy {
@@ -64,6 +95,7 @@ impl BloomExpandStrategy {
         let strategy = Strategy::Ladder(vec![100, 1000, 5000]);
         let fp_rate = 0.001;
         let timestamp_size = 60 * 60;
+        let layout = DelimitedKeyLayout::default().arc();
         Ok(Self {
             appid,
             info,
@@ -71,8 +103,100 @@ impl BloomExpandStrategy {
             bitmap,
             fp_rate,
             timestamp_size,
+            retention: None,
+            layout,
+            quota: Arc::new(Quota::default()),
+            shards: 1,
+            filter: FilterKind::default(),
         })
     }
+    // 根据url选择后端：
+    // file:///data/sgflt
+    // memory://
+    // redis://:pass@host:6379/0 , rediss://...
+    // redis+cluster://:pass@host1:6379,host2:6379
+    // redis+sentinel://:pass@host1:26379,host2:26379/mymaster , rediss+sentinel://...
+    pub fn build_from_url<A: Into<String>>(appid: A, url: &str) -> anyhow::Result<Self> {
+        if let Some(path) = url.strip_prefix("file://") {
+            return Self::build_from_file(appid, path);
+        }
+        if url == "memory://" {
+            return Ok(Self::build_from_memory(appid));
+        }
+        let split_nodes = |rest: &str| {
+            let (auth, hosts) = match rest.rsplit_once('@') {
+                Some((a, h)) => (format!("{}@", a), h.to_string()),
+                None => (String::new(), rest.to_string()),
+            };
+            (auth, hosts)
+        };
+        if let Some(rest) = url.strip_prefix("redis+cluster://") {
+            let (auth, hosts) = split_nodes(rest.trim_end_matches('/'));
+            let nodes = hosts
+                .split(',')
+                .map(|h| format!("redis://{}{}/", auth, h))
+                .collect::<Vec<_>>();
+            return Self::build_from_redis(appid, RedisConfig::cluster(nodes));
+        }
+        let sentinel = url
+            .strip_prefix("redis+sentinel://")
+            .map(|x| ("redis", x))
+            .or_else(|| {
+                url.strip_prefix("rediss+sentinel://")
+                    .map(|x| ("rediss", x))
+            });
+        if let Some((scheme, rest)) = sentinel {
+            let (auth, hosts) = split_nodes(rest);
+            let (hosts, service) = match hosts.split_once('/') {
+                Some((h, s)) if !s.is_empty() => (h.to_string(), s.to_string()),
+                _ => return anyhow::anyhow!("sentinel url[{}] requires a service name", url).err(),
+            };
+            let nodes = hosts
+                .split(',')
+                .map(|h| format!("{}://{}{}/", scheme, auth, h))
+                .collect::<Vec<_>>();
+            let mut cfg = RedisConfig::sentinel(nodes, service);
+            // 哨兵和主从节点使用相同的认证信息和tls
+            if let Some((user, pass)) = auth.trim_end_matches('@').split_once(':') {
+                if !user.is_empty() {
+                    cfg = cfg.set_username(user);
+                }
+                cfg = cfg.set_password(pass);
+            }
+            if scheme == "rediss" {
+                cfg = cfg.set_tls(RedisTlsConfig::default());
+            }
+            return Self::build_from_redis(appid, cfg);
+        }
+        Self::build_from_redis(appid, url)
+    }
+    // 使用本地文件存储，不依赖redis
+    pub fn build_from_file<A: Into<String>, P: AsRef<Path>>(
+        appid: A,
+        dir: P,
+    ) -> anyhow::Result<Self> {
+        let info = FilterInfoFile::new(dir.as_ref())?;
+        let bitmap = BitmapFile::new(dir.as_ref())?;
+        Ok(Self::new(
+            appid.into(),
+            info,
+            Strategy::Ladder(vec![100, 1000, 5000]),
+            bitmap,
+            0.001,
+            60 * 60,
+        ))
+    }
+    // 使用进程内存存储，数据不持久化
+    pub fn build_from_memory<A: Into<String>>(appid: A) -> Self {
+        Self::new(
+            appid.into(),
+            FilterInfoMemory::new(),
+            Strategy::Ladder(vec![100, 1000, 5000]),
+            BitmapMemory::new(),
+            0.001,
+            60 * 60,
+        )
+    }
     pub fn new<I: FiltersInfo + 'static, B: Bitmap + 'static>(
         appid: String,
         info: I,
@@ -81,15 +205,31 @@ impl BloomExpandStrategy {
         fp_rate: f64,
         timestamp_size: i64,
     ) -> Self {
-        let info = info.arc();
-        let bitmap = bitmap.arc();
+        let mut this = Self::from_backend(appid, info.arc(), bitmap.arc());
+        this.strategy = strategy;
+        this.fp_rate = fp_rate;
+        this.timestamp_size = timestamp_size;
+        this
+    }
+    // 使用已有的后端，reload时在原来的存储上重建strategy
+    pub fn from_backend(
+        appid: String,
+        info: Arc<dyn FiltersInfo + 'static>,
+        bitmap: Arc<dyn Bitmap + 'static>,
+    ) -> Self {
+        let layout = DelimitedKeyLayout::default().arc();
         Self {
             appid,
             info,
-            strategy,
+            strategy: Strategy::Ladder(vec![100, 1000, 5000]),
             bitmap,
-            fp_rate,
-            timestamp_size,
+            fp_rate: 0.001,
+            timestamp_size: 60 * 60,
+            retention: None,
+            layout,
+            quota: Arc::new(Quota::default()),
+            shards: 1,
+            filter: FilterKind::default(),
         }
     }
     pub fn set_app_id(mut self, appid: String) -> Self {
@@ -127,34 +267,244 @@ impl BloomExpandStrategy {
         self.timestamp_size = size;
         self
     }
+    // chunk的保留时间，按照chunk key中的时间戳判断，None表示永久保留
+    // 需要先设置timestamp_size，保留时间不能小于一个时间分桶
+    // 过期的chunk不再读取，但记录和bitmap仍然保留并计入max_bitmap_bytes，drop_group时才删除
+    // chunk下标包含过期的chunk会一直增长，所以不能和阶梯扩容一起使用
+    pub fn set_retention(mut self, retention: Option<i64>) -> anyhow::Result<Self> {
+        if let Some(r) = retention {
+            if let Strategy::Ladder(_) = self.strategy {
+                return anyhow::anyhow!(
+                    "BloomExpandStrategy: retention can not be used with ladder strategy"
+                )
+                .err();
+            }
+            if r < self.timestamp_size {
+                return anyhow::anyhow!(
+                    "BloomExpandStrategy: retention[{}] must be >= timestamp_size[{}]",
+                    r,
+                    self.timestamp_size
+                )
+                .err();
+            }
+        }
+        self.retention = retention;
+        Ok(self)
+    }
+    pub fn set_quota(mut self, config: QuotaConfig) -> Self {
+        self.quota = Arc::new(Quota::new(config));
+        self
+    }
+    // 和其他strategy共用配额和限流状态，reload重建strategy时令牌桶不会重置
+    pub fn set_shared_quota(mut self, quota: Arc<Quota>) -> Self {
+        self.quota = quota;
+        self
+    }
+    // 大chunk的bitmap拆分到多个key，分散到redis集群的不同节点，单次查询仍只访问一个key
+    // 分片数和chunk一起保存，修改后只影响新chunk，已有的chunk仍按创建时的分片数读取
+    pub fn set_shards(mut self, shards: usize) -> Self {
+        self.shards = shards.max(1);
+        self
+    }
+    // 过滤器类型和chunk一起保存，修改后只影响新chunk，已有的chunk仍按创建时的实现读取
+    pub fn set_filter(mut self, filter: FilterKind) -> Self {
+        self.filter = filter;
+        self
+    }
+    pub fn set_key_layout(mut self, layout: impl KeyLayout + 'static) -> Self {
+        self.layout = Arc::new(layout);
+        self
+    }
+
+    pub fn appid(&self) -> &str {
+        self.appid.as_str()
+    }
+    pub fn filter_info(&self) -> Arc<dyn FiltersInfo> {
+        self.info.clone()
+    }
+    pub fn bitmap(&self) -> Arc<dyn Bitmap> {
+        self.bitmap.clone()
+    }
+    pub fn key_layout(&self) -> Arc<dyn KeyLayout> {
+        self.layout.clone()
+    }
+    pub fn quota(&self) -> QuotaConfig {
+        self.quota.config()
+    }
+    pub fn shared_quota(&self) -> Arc<Quota> {
+        self.quota.clone()
+    }
+    pub fn shards(&self) -> usize {
+        self.shards
+    }
+    pub fn filter(&self) -> FilterKind {
+        self.filter
+    }
+    // 列出当前appid下所有的group
+    pub async fn groups(&self) -> anyhow::Result<Vec<String>> {
+        self.info
+            .groups(self.layout.as_ref(), self.appid.as_str())
+            .await
+    }
+
+    // 删除group的所有chunk和记录，返回删除的chunk数
+    pub async fn drop_group(&self, group: &str) -> anyhow::Result<usize> {
+        let group = self.layout.group_key(self.appid.as_str(), group);
+        let items = self.info.list(group.as_str()).await?;
+        let specs = self.info.specs(group.as_str()).await?;
+        for (code, _) in items.iter() {
+            let spec = self.stored_spec(&specs, code.as_str());
+            for key in shard_keys(code.as_str(), spec.shards) {
+                self.bitmap.del(key.as_str()).await?;
+            }
+        }
+        self.info
+            .remove(self.usage_key().as_str(), group.as_str())
+            .await?;
+        Ok(items.len())
+    }
+
+    // 当前appid的group数和所有chunk的bitmap字节数，由FiltersInfo在登记chunk时累计
+    pub async fn usage(&self) -> anyhow::Result<AppUsage> {
+        self.info.usage(self.usage_key().as_str()).await
+    }
+    pub async fn bitmap_bytes(&self) -> anyhow::Result<u64> {
+        Ok(self.usage().await?.bytes)
+    }
+    // 用量计数的名字由key layout生成，和group key使用相同的前缀和命名空间
+    pub(crate) fn usage_key(&self) -> String {
+        self.layout.usage_key(self.appid.as_str())
+    }
+
+    // 创建chunk之前检查配额，chunks为group当前未过期的chunk数，bytes为新chunk的字节数
+    // new_group表示group还没有任何chunk(包括过期的)，尚未计入group数
+    async fn check_quota(&self, chunks: usize, new_group: bool, bytes: u64) -> anyhow::Result<()> {
+        let quota = self.quota.config();
+        let exceeded = |kind, limit| Err(SgfitErr::new_quota_exceeded(kind, limit).into());
+        if let Some(max) = quota.max_chunks_per_group {
+            if chunks >= max {
+                return exceeded(QuotaKind::ChunksPerGroup, max as u64);
+            }
+        }
+        if quota.max_groups.is_none() && quota.max_bitmap_bytes.is_none() {
+            return Ok(());
+        }
+        let usage = self.usage().await?;
+        if let Some(max) = quota.max_groups {
+            if new_group && usage.groups >= max {
+                return exceeded(QuotaKind::Groups, max as u64);
+            }
+        }
+        if let Some(max) = quota.max_bitmap_bytes {
+            if usage.bytes + bytes > max {
+                return exceeded(QuotaKind::BitmapBytes, max);
+            }
+        }
+        Ok(())
+    }
+
+    // 新chunk使用的构建参数
+    pub fn chunk_spec(&self) -> ChunkSpec {
+        ChunkSpec {
+            shards: self.shards,
+            filter: self.filter,
+            layout: CHUNK_LAYOUT,
+        }
+    }
+    // 已有chunk的构建参数，没有保存参数的旧chunk按当前配置和最初的下标计算方式
+    pub(crate) fn stored_spec(&self, specs: &HashMap<String, ChunkSpec>, code: &str) -> ChunkSpec {
+        specs.get(code).copied().unwrap_or(ChunkSpec {
+            layout: 0,
+            ..self.chunk_spec()
+        })
+    }
+
+    // 根据chunk下标和构建参数构建过滤器，group为完整的group key
+    pub(crate) fn build_chunk(
+        &self,
+        group: &str,
+        code: String,
+        index: usize,
+        spec: ChunkSpec,
+    ) -> anyhow::Result<Arc<dyn SingleKeyFilter>> {
+        let size = self.strategy.chunk_size(index)?;
+        let info = self.info.clone();
+        let bitmap = self.bitmap.clone();
+        let group = group.to_string();
+        let chunk: Arc<dyn SingleKeyFilter> = match spec.filter {
+            FilterKind::Basic => {
+                BasicBloomFilter::new(group, code, info, bitmap, size, self.fp_rate)
+                    .set_layout(spec.layout)
+                    .set_shards(spec.shards)
+                    .arc()
+            }
+            FilterKind::Blocked if spec.shards > 1 => {
+                return anyhow::anyhow!("blocked filter does not support shards").err()
+            }
+            FilterKind::Blocked => {
+                BlockedBloomFilter::new(group, code, info, bitmap, size, self.fp_rate).arc()
+            }
+        };
+        chunk.ok()
+    }
+    // 解析chunk的下标和时间戳，无法解析时使用默认下标
+    pub(crate) fn chunk_position(&self, code: &str, default: usize) -> (usize, i64) {
+        match self.layout.parse_chunk_key(code) {
+            Some(ck) => (ck.index, ck.timestamp),
+            None => (default, 0),
+        }
+    }
+
+    // 时间戳为0表示无法从key中解析，不做过期处理
+    fn expired(&self, ts: i64) -> bool {
+        match self.retention {
+            Some(r) if ts > 0 => ts + r < wd_tools::time::utc_timestamp(),
+            _ => false,
+        }
+    }
 
     fn next_chunk_key(&self, index: usize, group: &str) -> String {
         let ts = wd_tools::time::utc_timestamp();
         let ts = ts - ts % self.timestamp_size;
-        format!("{}_{}_{}", group, ts, index)
+        self.layout.chunk_key(group, ts, index)
     }
 }
 
 #[async_trait::async_trait]
 impl FilterExpandStrategy for BloomExpandStrategy {
+    fn appid(&self) -> &str {
+        self.appid.as_str()
+    }
+    fn backend(&self) -> &str {
+        self.bitmap.backend()
+    }
+    fn acquire(&self, n: usize) -> anyhow::Result<()> {
+        self.quota.acquire(n)
+    }
     async fn load_filter_group(
         &self,
         group: &str,
     ) -> anyhow::Result<Vec<Arc<dyn SingleKeyFilter>>> {
-        let group = assembly_prefix(self.appid.as_str(), group);
+        let group = self.layout.group_key(self.appid.as_str(), group);
         let items = self.info.list(group.as_str()).await?;
+        // chunk存在多扩容的情况，下标优先从key中解析，并按照下标排序
+        let mut items = items
+            .into_iter()
+            .enumerate()
+            .map(|(i, (k, _))| {
+                let (index, ts) = self.chunk_position(k.as_str(), i);
+                (index, ts, k)
+            })
+            .collect::<Vec<_>>();
+        items.sort_by_key(|a| (a.0, a.1));
+        let specs = self.info.specs(group.as_str()).await?;
         let mut list: Vec<Arc<dyn SingleKeyFilter>> = Vec::with_capacity(items.len());
-        // fixme: chunk存才多扩容的情况，i应该根据k拆解得出
-        for (i, (k, _)) in items.into_iter().enumerate() {
-            let bloom = BasicBloomFilter::new(
-                group.clone(),
-                k,
-                self.info.clone(),
-                self.bitmap.clone(),
-                self.strategy.chunk_size(i)?,
-                self.fp_rate,
-            );
-            list.push(bloom.arc());
+        for (index, ts, k) in items.into_iter() {
+            if self.expired(ts) {
+                continue;
+            }
+            let spec = self.stored_spec(&specs, k.as_str());
+            list.push(self.build_chunk(group.as_str(), k, index, spec)?);
         }
         list.ok()
     }
@@ -164,24 +514,48 @@ impl FilterExpandStrategy for BloomExpandStrategy {
         group: &str,
         index: isize,
     ) -> anyhow::Result<Arc<dyn SingleKeyFilter>> {
-        let group = assembly_prefix(self.appid.as_str(), group);
-        let current_list = self.info.list(group.as_str()).await?;
-        let index = if index <= 0 {
-            current_list.len()
-        } else {
-            index as usize
+        let group_key = self.layout.group_key(self.appid.as_str(), group);
+        let current_list = self.info.list(group_key.as_str()).await?;
+        // 调用方只看到了前index个未过期的chunk，下标取这些chunk和过期chunk中最大的下标加一
+        // 过期chunk被跳过后新chunk仍然排在最后；并发扩容时其他任务刚创建的chunk不计入，得到同一个chunk
+        let mut next = 0;
+        let mut live = vec![];
+        for (i, (k, _)) in current_list.iter().enumerate() {
+            let (position, ts) = self.chunk_position(k.as_str(), i);
+            if self.expired(ts) {
+                next = next.max(position + 1);
+            } else {
+                live.push(position);
+            }
+        }
+        live.sort();
+        let seen = index.max(0) as usize;
+        if let Some(last) = live[..seen.min(live.len())].last() {
+            next = next.max(last + 1);
+        }
+        let index = next.max(seen);
+        let key = self.next_chunk_key(index, group_key.as_str());
+        let spec = self.chunk_spec();
+        let bloom = self.build_chunk(group_key.as_str(), key, index, spec)?;
+        let bytes = bloom.meta().m.div_ceil(8) as u64;
+        self.check_quota(live.len(), current_list.is_empty(), bytes)
+            .await?;
+        let chunk = ChunkRegistration {
+            usage_key: self.usage_key(),
+            capacity: bloom.meta().capacity,
+            bytes,
+            spec,
         };
-        let size = self.strategy.chunk_size(index)?;
-        let key = self.next_chunk_key(index, group.as_str());
-        let bloom = BasicBloomFilter::new(
-            group,
-            key,
-            self.info.clone(),
-            self.bitmap.clone(),
-            size,
-            self.fp_rate,
-        );
-        let bloom: Arc<dyn SingleKeyFilter> = bloom.arc();
-        bloom.ok()
+        self.info
+            .register(group_key.as_str(), bloom.code().as_str(), &chunk)
+            .await?;
+        // 并发扩容时chunk可能已由配置不同的进程登记，按已保存的参数读取
+        let code = bloom.code();
+        match self.info.specs(group_key.as_str()).await?.get(&code) {
+            Some(stored) if *stored != spec => {
+                self.build_chunk(group_key.as_str(), code, index, *stored)
+            }
+            _ => bloom.ok(),
+        }
     }
 }
@@ -1,10 +1,27 @@
+use crate::blocked_bloom_filter::{distinct_positions, partitioned_fp, smallest_fit};
 use crate::error::SgfitErr;
-use crate::{generate_hasher, Bitmap, FiltersInfo, SingleKeyFilter};
-use std::collections::hash_map::{DefaultHasher};
+use crate::{
+    generate_hasher, hash_key, Bitmap, ChunkMeta, ChunkStats, FiltersInfo, SingleKeyFilter,
+};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
+use std::hash::Hasher;
 use std::sync::Arc;
-use wd_tools::{PFErr};
+use tracing::Instrument;
+use wd_tools::PFErr;
+
+// 分片bitmap的key：{code}#{shard}
+pub fn shard_key(code: &str, shard: usize) -> String {
+    format!("{}#{}", code, shard)
+}
+
+// chunk实际使用的bitmap key，不分片时就是chunk code
+pub fn shard_keys(code: &str, shards: usize) -> Vec<String> {
+    if shards <= 1 {
+        return vec![code.to_string()];
+    }
+    (0..shards).map(|i| shard_key(code, i)).collect()
+}
 
 pub struct BasicBloomFilter {
     group: String,
@@ -14,7 +31,12 @@ pub struct BasicBloomFilter {
     optimal_m: usize,
     optimal_k: u32,
     items_count: usize,
+    fp_rate: f64,
     hashes: [DefaultHasher; 2],
+    // 大于1时m个bit平均分到shards个key中，由h1选择分片，k个bit都落在同一个分片
+    shards: usize,
+    // 下标计算方式，见 CHUNK_LAYOUT；分片时总是生成互不相同的位置
+    layout: u32,
 }
 
 impl BasicBloomFilter {
@@ -45,8 +67,97 @@ impl BasicBloomFilter {
             info,
             optimal_m,
             optimal_k,
+            fp_rate,
             hashes,
+            shards: 1,
+            layout: 0,
+        }
+    }
+    pub fn set_shards(mut self, shards: usize) -> Self {
+        self.shards = shards.max(1);
+        self.resize();
+        self
+    }
+    pub fn set_layout(mut self, layout: u32) -> Self {
+        self.layout = layout;
+        self.resize();
+        self
+    }
+    fn legacy_index(&self) -> bool {
+        self.shards <= 1 && self.layout == 0
+    }
+    // 每个分片的bit数向上取整到8的倍数，分片拼接后的偏移与整体bitmap一致
+    // 分片时key在分片之间分布不均，增加分片的bit数直到估算的误判率不超过目标，与分块过滤器相同
+    fn resize(&mut self) {
+        let m = Self::bitmap_size(self.items_count, self.fp_rate);
+        if self.legacy_index() {
+            self.optimal_m = m;
+            return;
+        }
+        let bits = if self.shards > 1 {
+            smallest_fit(m.div_ceil(self.shards), |bits| {
+                partitioned_fp(self.shards, bits, self.items_count, self.optimal_k) <= self.fp_rate
+            })
+        } else {
+            m
+        };
+        self.optimal_m = bits.div_ceil(8) * 8 * self.shards;
+    }
+    pub fn shards(&self) -> usize {
+        self.shards
+    }
+    pub fn bitmap_keys(&self) -> Vec<String> {
+        shard_keys(self.code.as_str(), self.shards)
+    }
+
+    // 读取整个chunk的bitmap，分片按顺序拼接
+    pub async fn read_bitmap(&self) -> anyhow::Result<Vec<u8>> {
+        if self.shards <= 1 {
+            return self.bitmap.mul_get(self.code.as_str()).await;
+        }
+        let keys = self.bitmap_keys();
+        let list =
+            futures::future::try_join_all(keys.iter().map(|k| self.bitmap.mul_get(k.as_str())))
+                .await?;
+        let stride = self.stride() / 8;
+        let mut buf = Vec::with_capacity(stride * self.shards);
+        for mut shard in list {
+            shard.resize(stride, 0);
+            buf.extend(shard);
         }
+        Ok(buf)
+    }
+    // 按整体偏移写入，分片时拆分到各个分片key
+    pub async fn write_bitmap(&self, offsets: HashSet<usize>) -> anyhow::Result<()> {
+        if self.shards <= 1 {
+            return self.bitmap.mul_set(self.code.as_str(), offsets).await;
+        }
+        let mut split: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for i in offsets {
+            let (shard, offset) = self.locate(i);
+            split.entry(shard).or_default().insert(offset);
+        }
+        futures::future::try_join_all(split.into_iter().map(|(shard, offsets)| {
+            let key = shard_key(self.code.as_str(), shard);
+            async move { self.bitmap.mul_set(key.as_str(), offsets).await }
+        }))
+        .await?;
+        Ok(())
+    }
+    fn stride(&self) -> usize {
+        self.optimal_m / self.shards
+    }
+    // 整体偏移 -> (分片, 分片内偏移)
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let stride = self.stride();
+        (index / stride, index % stride)
+    }
+    // 分片时返回分片key，所有下标必须在同一个分片
+    fn storage_key(&self, index: usize) -> String {
+        if self.shards <= 1 {
+            return self.code.clone();
+        }
+        shard_key(self.code.as_str(), self.locate(index).0)
     }
     // pub async fn is_full(&self) -> anyhow::Result<bool> {
     //     Ok(self
@@ -55,24 +166,35 @@ impl BasicBloomFilter {
     //         .await?
     //         >= self.items_count)
     // }
-    fn hash_kernel<T: Hash + Send + Sync + ?Sized>(&self, item: &T) -> (u64, u64) {
+    fn hash_kernel(&self, item: &[u8]) -> (u64, u64) {
         let hasher1 = &mut self.hashes[0].clone();
         let hasher2 = &mut self.hashes[1].clone();
 
-        item.hash(hasher1);
-        item.hash(hasher2);
+        hash_key(hasher1, item);
+        hash_key(hasher2, item);
 
         let hash1 = hasher1.finish();
         let hash2 = hasher2.finish();
 
         (hash1, hash2)
     }
-    fn get_index(&self, h1: u64, h2: u64, k_i: u64) -> usize {
-        h1.wrapping_add((k_i).wrapping_mul(h2)) as usize % self.optimal_m
+    // key的k个下标，h1选择分片，分片内的位置互不相同；不分片时只有一个分片
+    fn indexes(&self, h1: u64, h2: u64) -> Vec<usize> {
+        let k = self.optimal_k as u64;
+        if self.legacy_index() {
+            return (0..k)
+                .map(|k_i| h1.wrapping_add(k_i.wrapping_mul(h2)) as usize % self.optimal_m)
+                .collect();
+        }
+        let stride = self.stride();
+        let base = (h1 % self.shards as u64) as usize * stride;
+        distinct_positions(h1.rotate_left(32) ^ h2, k as usize, stride)
+            .into_iter()
+            .map(|i| base + i)
+            .collect()
     }
-    async fn sync_mode_contain(&self, h1: u64, h2: u64, bitmap: &Vec<u8>) -> anyhow::Result<bool> {
-        for k_i in 0..self.optimal_k {
-            let index = self.get_index(h1, h2, k_i as u64);
+    async fn sync_mode_contain(&self, h1: u64, h2: u64, bitmap: &[u8]) -> anyhow::Result<bool> {
+        for index in self.indexes(h1, h2) {
             let i = index / 8;
             let offset = index % 8;
             if let Some(u) = bitmap.get(i) {
@@ -86,35 +208,30 @@ impl BasicBloomFilter {
         Ok(true)
     }
     async fn sync_mode_insert(&self, h1: u64, h2: u64) -> anyhow::Result<Vec<usize>> {
-        let mut result = vec![];
-        for k_i in 0..self.optimal_k {
-            let index = self.get_index(h1, h2, k_i as u64);
-            result.push(index);
-        }
-        Ok(result)
+        Ok(self.indexes(h1, h2))
     }
-    async fn raw_contain(&self, item: &str, bits: Option<&Vec<u8>>) -> anyhow::Result<bool> {
+    async fn raw_contain(&self, item: &[u8], bits: Option<&Vec<u8>>) -> anyhow::Result<bool> {
         let (h1, h2) = self.hash_kernel(item);
 
         if let Some(v) = bits {
             return self.sync_mode_contain(h1, h2, v).await;
-        } else {
-            for k_i in 0..self.optimal_k {
-                let index = self.get_index(h1, h2, k_i as u64);
-                if !self.bitmap.get(self.code.as_str(), index).await? {
-                    return Ok(false);
-                }
-            }
         }
-        Ok(true)
+        let index = self.indexes(h1, h2);
+        let key = self.storage_key(index[0]);
+        let index = index
+            .into_iter()
+            .map(|i| self.locate(i).1)
+            .collect::<Vec<_>>();
+        let bits = self.bitmap.get_bits(key.as_str(), index.as_slice()).await?;
+        Ok(bits.into_iter().all(|x| x))
     }
 
     fn bitmap_size(items_count: usize, fp_rate: f64) -> usize {
         let ln2_2 = core::f64::consts::LN_2 * core::f64::consts::LN_2;
-        ((-1.0f64 * items_count as f64 * fp_rate.ln()) / ln2_2).ceil() as usize
+        ((-(items_count as f64) * fp_rate.ln()) / ln2_2).ceil() as usize
     }
     fn optimal_k(fp_rate: f64) -> u32 {
-        ((-1.0f64 * fp_rate.ln()) / core::f64::consts::LN_2).ceil() as u32
+        ((-fp_rate.ln()) / core::f64::consts::LN_2).ceil() as u32
     }
 }
 
@@ -130,6 +247,28 @@ impl SingleKeyFilter for BasicBloomFilter {
         self.code.clone()
     }
 
+    fn meta(&self) -> ChunkMeta {
+        ChunkMeta {
+            code: self.code.clone(),
+            capacity: self.items_count,
+            m: self.optimal_m,
+            k: self.optimal_k,
+            fp_rate: self.fp_rate,
+        }
+    }
+
+    async fn stats(&self) -> anyhow::Result<ChunkStats> {
+        let count = self
+            .info
+            .count(self.group.as_str(), self.code.as_str())
+            .await?;
+        let mut bits = 0;
+        for key in self.bitmap_keys() {
+            bits += self.bitmap.count_ones(key.as_str()).await?;
+        }
+        Ok(ChunkStats::new(self.meta(), count, bits))
+    }
+
     async fn is_full(&self) -> anyhow::Result<bool> {
         Ok(self
             .info
@@ -138,7 +277,8 @@ impl SingleKeyFilter for BasicBloomFilter {
             >= self.items_count)
     }
 
-    async fn insert(&self, item: &str) -> anyhow::Result<()> {
+    #[tracing::instrument(name = "chunk.insert", skip_all, fields(group = %self.group, code = %self.code))]
+    async fn insert(&self, item: &[u8]) -> anyhow::Result<()> {
         //先判断是不是满了
         if self.is_full().await? {
             return anyhow::Error::new(SgfitErr::new_chunk_full(self.items_count)).err();
@@ -146,9 +286,11 @@ impl SingleKeyFilter for BasicBloomFilter {
 
         let (h1, h2) = self.hash_kernel(item);
 
-        for k_i in 0..self.optimal_k {
-            let index = self.get_index(h1, h2, k_i as u64);
-            self.bitmap.set(self.code.as_str(), index, true).await?;
+        for index in self.indexes(h1, h2) {
+            let key = self.storage_key(index);
+            self.bitmap
+                .set(key.as_str(), self.locate(index).1, true)
+                .await?;
         }
 
         //插入成功，添加一条记录
@@ -165,13 +307,37 @@ impl SingleKeyFilter for BasicBloomFilter {
         Ok(())
     }
 
-    async fn contain(&self, item: &str) -> anyhow::Result<bool> {
+    #[tracing::instrument(name = "chunk.contain", skip_all, fields(group = %self.group, code = %self.code))]
+    async fn contain(&self, item: &[u8]) -> anyhow::Result<bool> {
         self.raw_contain(item, None).await
     }
 
+    // 批量查询的预取和单个chunk的探测都经过这里，每次读取bitmap都有chunk.probe
+    #[tracing::instrument(name = "chunk.probe", skip_all, fields(group = %self.group, code = %self.code))]
+    async fn fetch_bitmap(&self) -> anyhow::Result<Vec<u8>> {
+        self.read_bitmap().await
+    }
+
+    async fn store_bitmap(&self, bytes: Vec<u8>) -> anyhow::Result<()> {
+        if self.shards <= 1 {
+            return self.bitmap.put_bytes(self.code.as_str(), bytes).await;
+        }
+        let stride = self.stride() / 8;
+        let keys = self.bitmap_keys();
+        futures::future::try_join_all(keys.iter().enumerate().map(|(i, key)| {
+            let shard = bytes
+                .get(i * stride..((i + 1) * stride).min(bytes.len()))
+                .unwrap_or_default()
+                .to_vec();
+            self.bitmap.put_bytes(key.as_str(), shard)
+        }))
+        .await?;
+        Ok(())
+    }
+
     async fn pre_insert(
         &self,
-        item: &str,
+        item: &[u8],
         total: &mut HashMap<String, usize>,
         growth: &mut HashMap<String, usize>,
     ) -> anyhow::Result<Vec<usize>> {
@@ -183,7 +349,7 @@ impl SingleKeyFilter for BasicBloomFilter {
                 .await?;
             total.insert(self.code.clone(), current_total);
         }
-        let mut count = total.get(self.code.as_str()).unwrap().clone();
+        let mut count = *total.get(self.code.as_str()).unwrap();
         if let Some(i) = growth.get(self.code.as_str()) {
             count += *i;
         }
@@ -208,27 +374,176 @@ impl SingleKeyFilter for BasicBloomFilter {
         buf: &mut HashMap<String, HashSet<usize>>,
         growth: &mut HashMap<String, usize>,
     ) -> anyhow::Result<()> {
-        if let Some(s) = buf.remove(self.code.as_str()) {
-            self.bitmap.mul_set(self.code.as_str(), s).await?;
+        let bits = buf.remove(self.code.as_str());
+        let count = growth.remove(self.code.as_str());
+        if bits.is_none() && count.is_none() {
+            return Ok(());
         }
-        if let Some(i) = growth.remove(self.code.as_str()) {
-            self.info
-                .add(self.group.as_str(), self.code.as_str(), i)
-                .await?;
+        let span = tracing::info_span!(
+            "chunk.commit_insert",
+            group = %self.group,
+            code = %self.code,
+            bits = bits.as_ref().map(|x| x.len()).unwrap_or(0),
+            keys = count.unwrap_or(0)
+        );
+        async move {
+            if let Some(s) = bits {
+                self.write_bitmap(s).await?;
+            }
+            if let Some(i) = count {
+                self.info
+                    .add(self.group.as_str(), self.code.as_str(), i)
+                    .await?;
+            }
+            Ok(())
         }
-        Ok(())
+        .instrument(span)
+        .await
     }
 
     async fn pre_contain(
         &self,
-        item: &str,
+        item: &[u8],
         buf: &mut HashMap<String, Vec<u8>>,
     ) -> anyhow::Result<bool> {
         if !buf.contains_key(self.code.as_str()) {
-            let bit = self.bitmap.mul_get(self.code.as_str()).await?;
+            // 每个chunk的bitmap只拉取一次
+            let bit = self.fetch_bitmap().await?;
             buf.insert(self.code.clone(), bit);
         }
         let bits = buf.get(self.code.as_str()).unwrap();
         self.raw_contain(item, Some(bits)).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::blocked_bloom_filter::partitioned_fp;
+    use crate::{
+        shard_key, BasicBloomFilter, Bitmap, BitmapMemory, BloomExpandStrategy, FilterInfoMemory,
+        FiltersPool, SingleKeyFilter, Strategy, CHUNK_LAYOUT,
+    };
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_sharded_filter() {
+        let bitmap = BitmapMemory::new();
+        let chunk = BasicBloomFilter::new(
+            "g",
+            "c0",
+            Arc::new(FilterInfoMemory::new()),
+            Arc::new(bitmap.clone()),
+            1000,
+            0.001,
+        )
+        .set_shards(4);
+        let meta = chunk.meta();
+        assert_eq!(meta.m % 32, 0);
+        assert!(meta.m >= BasicBloomFilter::bitmap_size(1000, 0.001));
+
+        // 单个key的k个bit都在同一个分片
+        chunk.insert(b"a").await.unwrap();
+        let mut ones = vec![];
+        for i in 0..4 {
+            let key = shard_key("c0", i);
+            ones.push(bitmap.count_ones(key.as_str()).await.unwrap());
+        }
+        assert_eq!(ones.iter().filter(|x| **x > 0).count(), 1);
+        assert_eq!(ones.iter().sum::<usize>(), meta.k as usize);
+        assert!(bitmap.mul_get("c0").await.unwrap().is_empty());
+
+        assert!(chunk.contain(b"a").await.unwrap());
+        assert!(!chunk.contain(b"b").await.unwrap());
+        assert_eq!(chunk.read_bitmap().await.unwrap().len(), meta.m / 8);
+        assert_eq!(chunk.stats().await.unwrap().bits, meta.k as usize);
+    }
+
+    #[test]
+    fn test_index_distinct() {
+        let new = |shards| {
+            BasicBloomFilter::new(
+                "g",
+                "c0",
+                Arc::new(FilterInfoMemory::new()),
+                Arc::new(BitmapMemory::new()),
+                100,
+                0.01,
+            )
+            .set_layout(CHUNK_LAYOUT)
+            .set_shards(shards)
+        };
+        for chunk in [new(1), new(8)] {
+            let stride = chunk.stride();
+            assert_eq!(stride % 8, 0);
+            assert!(chunk.meta().m >= BasicBloomFilter::bitmap_size(100, 0.01));
+            // 分片很小时k个位置仍然两两不同，且都在同一个分片
+            for i in 0..10000u32 {
+                let (h1, h2) = chunk.hash_kernel(&i.to_be_bytes());
+                let index = chunk.indexes(h1, h2).into_iter().collect::<HashSet<_>>();
+                assert_eq!(index.len(), chunk.optimal_k as usize);
+                let shard = index.iter().next().unwrap() / stride;
+                assert!(index.iter().all(|x| x / stride == shard));
+            }
+        }
+        // 分片中key分布不均，按估算的误判率增加分片的bit数
+        let chunk = new(8);
+        let bits = chunk.stride();
+        assert!(bits * 8 > BasicBloomFilter::bitmap_size(100, 0.01));
+        assert!(partitioned_fp(8, bits, 100, chunk.optimal_k) <= 0.01);
+    }
+
+    #[tokio::test]
+    async fn test_sharded_pool() {
+        let bitmap = BitmapMemory::new();
+        let info = FilterInfoMemory::new();
+        let strategy = |info: FilterInfoMemory, bitmap: BitmapMemory, shards: usize| {
+            BloomExpandStrategy::new(
+                "biz02".into(),
+                info,
+                Strategy::Fixed(100),
+                bitmap,
+                0.001,
+                3600,
+            )
+            .set_shards(shards)
+        };
+        let pool = FiltersPool::from(strategy(info.clone(), bitmap.clone(), 8));
+        let keys = (0..250).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys[..200].to_vec())
+            .await
+            .unwrap();
+        pool.insert("user001", keys[200].clone()).await.unwrap();
+        let result = pool.batch_contain("user001", keys.clone()).await.unwrap();
+        assert!(result[..201].iter().all(|x| *x));
+        assert!(result[201..].iter().filter(|x| **x).count() < 3);
+        for key in keys[..201].iter() {
+            assert!(pool.contain("user001", key.clone()).await.unwrap());
+        }
+        let stats = pool.stats("user001").await.unwrap();
+        assert_eq!(stats.count, 201);
+        assert_eq!(stats.chunks.len(), 3);
+
+        // 修改分片数后已有的chunk按登记的分片数读取
+        let pool = FiltersPool::from(strategy(info.clone(), bitmap.clone(), 2));
+        let result = pool.batch_contain("user001", keys.clone()).await.unwrap();
+        assert!(result[..201].iter().all(|x| *x));
+
+        // 快照按分片拼接导出并记录分片数，导入时重新拆分
+        let mut buf = vec![];
+        let source = strategy(info.clone(), bitmap.clone(), 1);
+        assert_eq!(source.export_app(&mut buf).await.unwrap(), 3);
+        let (info2, bitmap2) = (FilterInfoMemory::new(), BitmapMemory::new());
+        let target = strategy(info2.clone(), bitmap2.clone(), 1);
+        target.import(buf.as_slice()).await.unwrap();
+        let pool = FiltersPool::from(strategy(info2, bitmap2, 1));
+        let result = pool.batch_contain("user001", keys.clone()).await.unwrap();
+        assert!(result[..201].iter().all(|x| *x));
+
+        assert_eq!(source.drop_group("user001").await.unwrap(), 3);
+        for chunk in stats.chunks.iter() {
+            let key = shard_key(chunk.code.as_str(), 0);
+            assert!(bitmap.mul_get(key.as_str()).await.unwrap().is_empty());
+        }
+    }
+}
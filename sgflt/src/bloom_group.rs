@@ -1,14 +1,36 @@
-use crate::{error::SgfitErr, FilterExpandStrategy, SingleKeyFilter};
+use crate::metrics::record_group_event;
+use crate::{error::SgfitErr, FilterExpandStrategy, GroupStats, SingleKeyFilter};
+use futures::future::BoxFuture;
+use futures::StreamExt;
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use wd_tools::sync::Acl;
 use wd_tools::PFErr;
 
+pub const DEFAULT_PROBE_PARALLELISM: usize = 16;
+// batch_contain默认每轮只拉取一个chunk，所有key命中后不再读取其余chunk
+pub const DEFAULT_FETCH_WINDOW: usize = 1;
+
+// chunk code -> bitmap
+type ChunkBits = HashMap<String, Vec<u8>>;
+
+// chunk的探测顺序，近期数据重复较多时使用NewestFirst
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProbeOrder {
+    #[default]
+    OldestFirst,
+    NewestFirst,
+}
+
 pub struct FilterGroup {
     group: String,
     strategy: Arc<dyn FilterExpandStrategy>,
     list: Acl<Vec<Arc<dyn SingleKeyFilter>>>,
     try_max: usize,
+    parallelism: usize,
+    probe_order: ProbeOrder,
+    fetch_window: usize,
 }
 
 impl FilterGroup {
@@ -18,6 +40,9 @@ impl FilterGroup {
             strategy,
             list: Acl::new(vec![]),
             try_max: 5,
+            parallelism: DEFAULT_PROBE_PARALLELISM,
+            probe_order: ProbeOrder::default(),
+            fetch_window: DEFAULT_FETCH_WINDOW,
         }
     }
     pub async fn init_chunks_list(self) -> Self {
@@ -28,6 +53,20 @@ impl FilterGroup {
         self.try_max = max;
         self
     }
+    // 同时进行的chunk探测数上限
+    pub fn set_parallelism(mut self, n: usize) -> Self {
+        self.parallelism = n.max(1);
+        self
+    }
+    pub fn set_probe_order(mut self, order: ProbeOrder) -> Self {
+        self.probe_order = order;
+        self
+    }
+    // batch_contain每一轮拉取的chunk数，仍有key未命中时才拉取下一轮
+    pub fn set_fetch_window(mut self, n: usize) -> Self {
+        self.fetch_window = n.max(1);
+        self
+    }
 }
 
 impl Clone for FilterGroup {
@@ -37,83 +76,114 @@ impl Clone for FilterGroup {
             strategy,
             list,
             try_max,
+            parallelism,
+            probe_order,
+            fetch_window,
         } = self;
         Self {
             group: group.clone(),
             strategy: strategy.clone(),
             list: list.clone(),
-            try_max: try_max.clone(),
+            try_max: *try_max,
+            parallelism: *parallelism,
+            probe_order: *probe_order,
+            fetch_window: *fetch_window,
         }
     }
 }
 
 impl FilterGroup {
-    pub async fn contain(&self, keys: Vec<String>) -> anyhow::Result<Vec<bool>> {
-        let mut result = Vec::with_capacity(keys.len());
-
-        for i in keys.into_iter() {
-            let mut exist = false;
-            for skf in self.list.share().iter() {
-                if skf.contain(i.as_str()).await? {
-                    exist = true;
-                    break;
-                }
+    // 所有key和chunk的组合并发探测，某个key命中后跳过它尚未开始的探测
+    pub async fn contain(&self, keys: Vec<Vec<u8>>) -> anyhow::Result<Vec<bool>> {
+        let list = self.probe_list();
+        let found = keys
+            .iter()
+            .map(|_| AtomicBool::new(false))
+            .collect::<Vec<_>>();
+        let mut probes: Vec<BoxFuture<anyhow::Result<()>>> = vec![];
+        for (i, key) in keys.iter().enumerate() {
+            for skf in list.iter() {
+                let found = &found[i];
+                probes.push(Box::pin(async move {
+                    if found.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    if skf.contain(key.as_slice()).await? {
+                        found.store(true, Ordering::Relaxed);
+                    }
+                    Ok(())
+                }));
             }
-            result.push(exist);
         }
-        Ok(result)
+        let mut stream = futures::stream::iter(probes).buffer_unordered(self.parallelism);
+        while let Some(res) = stream.next().await {
+            res?;
+        }
+        drop(stream);
+        Ok(found.into_iter().map(|x| x.into_inner()).collect())
     }
-    pub async fn insert(&self, keys: Vec<String>) -> anyhow::Result<()> {
+    pub async fn insert(&self, keys: Vec<Vec<u8>>) -> anyhow::Result<()> {
         'lp: for i in keys.into_iter() {
             for _ in 0..self.try_max {
                 let chunk = self.get_last_chunk().await?;
-                if let Err(e) = chunk.insert(i.as_str()).await {
-                    if let Some(se) = e.downcast_ref::<SgfitErr>() {
-                        match se {
-                            SgfitErr::ChunkFull(_) => self.try_extend().await?,
-                        }
+                if let Err(e) = chunk.insert(i.as_slice()).await {
+                    // 如果错误是区块已满，则尝试扩容后重试
+                    if let Some(SgfitErr::ChunkFull(_)) = e.downcast_ref::<SgfitErr>() {
+                        self.record_event("chunk_full_retry");
+                        self.try_extend().await?;
+                        continue;
                     }
+                    return Err(e);
                 }
                 continue 'lp;
             }
+            self.record_event("try_max_exhausted");
             return anyhow::anyhow!("FilterGroup.insert failed,try_max[{}]", self.try_max).err();
         }
         Ok(())
     }
-    pub async fn batch_contain(&self, keys: Vec<String>) -> anyhow::Result<Vec<bool>> {
-        let mut result = Vec::with_capacity(keys.len());
-        let mut map = HashMap::new();
-        for i in keys.into_iter() {
-            let mut exist = false;
-            for skf in self.list.share().iter() {
-                if skf.pre_contain(i.as_str(), &mut map).await? {
-                    exist = true;
-                    break;
+    // 按探测顺序分轮拉取chunk，key命中后不再检查后续chunk，所有key都命中后提前结束
+    pub async fn batch_contain(&self, keys: Vec<Vec<u8>>) -> anyhow::Result<Vec<bool>> {
+        let list = self.probe_list();
+        let mut result = vec![false; keys.len()];
+        let mut pending = (0..keys.len()).collect::<Vec<_>>();
+        for wave in list.chunks(self.fetch_window) {
+            if pending.is_empty() {
+                break;
+            }
+            let mut map = self.prefetch(wave).await?;
+            let mut next = Vec::with_capacity(pending.len());
+            'key: for i in pending.into_iter() {
+                for skf in wave.iter() {
+                    if skf.pre_contain(keys[i].as_slice(), &mut map).await? {
+                        result[i] = true;
+                        continue 'key;
+                    }
                 }
+                next.push(i);
             }
-            result.push(exist);
+            pending = next;
         }
         Ok(result)
     }
-    pub async fn batch_insert(&self, keys: Vec<String>) -> anyhow::Result<()> {
+    pub async fn batch_insert(&self, keys: Vec<Vec<u8>>) -> anyhow::Result<()> {
         let mut map: HashMap<String, HashSet<usize>> = HashMap::new();
         let mut total: HashMap<String, usize> = HashMap::new();
         let mut growth: HashMap<String, usize> = HashMap::new();
         'lp: for i in keys.into_iter() {
             for _ in 0..self.try_max {
                 let chunk = self.get_last_chunk().await?;
-                let result = chunk.pre_insert(i.as_str(), &mut total, &mut growth).await;
+                let result = chunk
+                    .pre_insert(i.as_slice(), &mut total, &mut growth)
+                    .await;
                 let index_list = match result {
                     Ok(l) => l,
                     // 如果错误是区块已满，则尝试扩容
                     Err(e) => {
-                        if let Some(se) = e.downcast_ref::<SgfitErr>() {
-                            match se {
-                                SgfitErr::ChunkFull(_) => {
-                                    self.try_extend().await?;
-                                    continue;
-                                }
-                            }
+                        if let Some(SgfitErr::ChunkFull(_)) = e.downcast_ref::<SgfitErr>() {
+                            self.record_event("chunk_full_retry");
+                            self.try_extend().await?;
+                            continue;
                         }
                         return Err(e);
                     }
@@ -130,6 +200,7 @@ impl FilterGroup {
                 };
                 continue 'lp;
             }
+            self.record_event("try_max_exhausted");
             return anyhow::anyhow!("FilterGroup.batch_insert failed,try_max[{}]", self.try_max)
                 .err();
         }
@@ -140,6 +211,30 @@ impl FilterGroup {
         Ok(())
     }
 
+    fn probe_list(&self) -> Vec<Arc<dyn SingleKeyFilter>> {
+        let list = self.list.share();
+        match self.probe_order {
+            ProbeOrder::OldestFirst => list.to_vec(),
+            ProbeOrder::NewestFirst => list.iter().rev().cloned().collect(),
+        }
+    }
+    // 并发拉取一组chunk的bitmap
+    async fn prefetch(&self, list: &[Arc<dyn SingleKeyFilter>]) -> anyhow::Result<ChunkBits> {
+        let mut fetch: Vec<BoxFuture<anyhow::Result<ChunkBits>>> = vec![];
+        for skf in list.iter() {
+            fetch.push(Box::pin(async move {
+                let bytes = skf.fetch_bitmap().await?;
+                Ok(HashMap::from([(skf.code(), bytes)]))
+            }));
+        }
+        let mut stream = futures::stream::iter(fetch).buffer_unordered(self.parallelism);
+        let mut map = HashMap::new();
+        while let Some(res) = stream.next().await {
+            map.extend(res?);
+        }
+        Ok(map)
+    }
+
     async fn get_last_chunk(&self) -> anyhow::Result<Arc<dyn SingleKeyFilter>> {
         let list = self.list.share();
         if list.is_empty() {
@@ -152,6 +247,7 @@ impl FilterGroup {
         Ok(chunk)
     }
 
+    #[tracing::instrument(name = "group.try_extend", skip_all, fields(group = %self.group))]
     pub async fn try_extend(&self) -> anyhow::Result<()> {
         //先更新再扩容
         let current_list = self.list.share();
@@ -172,16 +268,44 @@ impl FilterGroup {
             .expand_chunk(self.group.as_str(), current_list.len() as isize)
             .await?;
         self.list.update(|x| {
-            let mut vec = (&*x).clone();
+            let mut vec = (*x).clone();
             vec.push(chunk);
             vec
         });
+        self.record_event("extend");
         Ok(())
     }
-    fn skfs_eq(
-        cl: &Arc<Vec<Arc<dyn SingleKeyFilter>>>,
-        ll: &Vec<Arc<dyn SingleKeyFilter>>,
-    ) -> bool {
+    // 只从后端加载chunk列表，不扩容，用于只读的查询
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let list = self.strategy.load_filter_group(self.group.as_str()).await?;
+        self.list.update(|_| list);
+        Ok(())
+    }
+    // 超出限流时记录quota_exceeded事件
+    pub fn acquire(&self, n: usize) -> anyhow::Result<()> {
+        self.strategy
+            .acquire(n)
+            .inspect_err(|_| self.record_event("quota_exceeded"))
+    }
+    pub fn appid(&self) -> &str {
+        self.strategy.appid()
+    }
+    pub fn backend(&self) -> &str {
+        self.strategy.backend()
+    }
+    fn record_event(&self, event: &str) {
+        record_group_event(self.appid(), self.backend(), event);
+    }
+    // 直接从后端加载chunk列表，不触发扩容
+    pub async fn stats(&self) -> anyhow::Result<GroupStats> {
+        let list = self.strategy.load_filter_group(self.group.as_str()).await?;
+        let mut chunks = Vec::with_capacity(list.len());
+        for i in list.iter() {
+            chunks.push(i.stats().await?);
+        }
+        Ok(GroupStats::new(self.group.clone(), chunks))
+    }
+    fn skfs_eq(cl: &Arc<Vec<Arc<dyn SingleKeyFilter>>>, ll: &[Arc<dyn SingleKeyFilter>]) -> bool {
         let ll_len = ll.len();
         if ll_len > cl.len() {
             return false;
@@ -194,3 +318,186 @@ impl FilterGroup {
         true
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        mock_backend, Bitmap, BitmapFile, BloomExpandStrategy, DefaultPoolImpl, FilterGroup,
+        FilterInfoFile, FiltersPool, ProbeOrder, Strategy, DEFAULT_PROBE_PARALLELISM,
+    };
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // 每个chunk只拉取一次bitmap，并且多个chunk同时在途
+    #[tokio::test]
+    async fn test_concurrent_contain() {
+        let (info, bitmap, faults) = mock_backend();
+        let strategy = || {
+            let (info, bitmap) = (info.clone(), bitmap.clone());
+            BloomExpandStrategy::new(
+                "biz02".into(),
+                info,
+                Strategy::Fixed(10),
+                bitmap,
+                0.01,
+                3600,
+            )
+        };
+        let pool = FiltersPool::new(
+            DefaultPoolImpl::new(strategy()).set_fetch_window(DEFAULT_PROBE_PARALLELISM),
+        );
+        let keys = (0..30).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys.clone()).await.unwrap();
+        let query = vec![keys[0].clone(), keys[15].clone(), keys[29].clone()];
+
+        faults.set_latency("bitmap.mul_get", Duration::from_millis(20));
+        faults.reset_calls();
+        let result = pool.batch_contain("user001", query.clone()).await.unwrap();
+        assert_eq!(result, vec![true, true, true]);
+        assert_eq!(faults.calls("bitmap.mul_get"), 3);
+        assert_eq!(
+            faults.calls("bitmap.get") + faults.calls("bitmap.get_bits"),
+            0
+        );
+        assert_eq!(faults.peak("bitmap.mul_get"), 3);
+
+        // 单key探测时每个key和chunk的组合一次往返，同时在途
+        faults.set_latency("bitmap.get_bits", Duration::from_millis(20));
+        let group = FilterGroup::new("user001".into(), Arc::new(strategy()))
+            .init_chunks_list()
+            .await;
+        faults.reset_calls();
+        let result = group
+            .contain(query.into_iter().map(String::into_bytes).collect())
+            .await
+            .unwrap();
+        assert_eq!(result, vec![true, true, true]);
+        assert_eq!(faults.calls("bitmap.get_bits"), 9);
+        assert_eq!(faults.peak("bitmap.get_bits"), 9);
+    }
+
+    struct CountingBitmap(BitmapFile, Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl Bitmap for CountingBitmap {
+        async fn set(&self, key: &str, offset: usize, value: bool) -> anyhow::Result<()> {
+            self.0.set(key, offset, value).await
+        }
+        async fn get(&self, key: &str, offset: usize) -> anyhow::Result<bool> {
+            self.0.get(key, offset).await
+        }
+        async fn mul_set(&self, key: &str, list: HashSet<usize>) -> anyhow::Result<()> {
+            self.0.mul_set(key, list).await
+        }
+        async fn mul_get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.mul_get(key).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let pool = |order: ProbeOrder| {
+            let strategy = BloomExpandStrategy::new(
+                "biz02".into(),
+                FilterInfoFile::new(dir.path()).unwrap(),
+                Strategy::Fixed(10),
+                CountingBitmap(BitmapFile::new(dir.path()).unwrap(), fetched.clone()),
+                0.001,
+                3600,
+            );
+            FiltersPool::new(
+                DefaultPoolImpl::new(strategy)
+                    .set_probe_order(order)
+                    .set_fetch_window(1),
+            )
+        };
+        let keys = (0..30).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool(ProbeOrder::OldestFirst)
+            .batch_insert("user001", keys.clone())
+            .await
+            .unwrap();
+
+        let cases = [
+            (ProbeOrder::NewestFirst, vec!["key_25", "key_28"], 1),
+            (ProbeOrder::OldestFirst, vec!["key_25", "key_28"], 3),
+            (ProbeOrder::NewestFirst, vec!["key_1", "key_28"], 3),
+            (ProbeOrder::OldestFirst, vec!["key_1", "key_8"], 1),
+            (ProbeOrder::NewestFirst, vec!["key_none"], 3),
+        ];
+        for (order, query, expect) in cases {
+            let query = query.into_iter().map(String::from).collect::<Vec<_>>();
+            let exist = query != vec!["key_none".to_string()];
+            fetched.store(0, Ordering::SeqCst);
+            let result = pool(order).batch_contain("user001", query).await.unwrap();
+            assert!(result.into_iter().all(|x| x == exist));
+            assert_eq!(fetched.load(Ordering::SeqCst), expect, "{:?}", order);
+        }
+
+        let result = pool(ProbeOrder::NewestFirst)
+            .batch_contain("user001", keys.clone())
+            .await
+            .unwrap();
+        assert!(result.into_iter().all(|x| x));
+        let result = pool(ProbeOrder::NewestFirst)
+            .contain("user001", "key_3".into())
+            .await
+            .unwrap();
+        assert!(result);
+    }
+
+    // 默认的拉取窗口下，所有key命中最新的chunk后不再读取其他chunk
+    #[tokio::test]
+    async fn test_default_fetch_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let strategy = BloomExpandStrategy::new(
+            "biz02".into(),
+            FilterInfoFile::new(dir.path()).unwrap(),
+            Strategy::Fixed(10),
+            CountingBitmap(BitmapFile::new(dir.path()).unwrap(), fetched.clone()),
+            0.001,
+            3600,
+        );
+        let pool = FiltersPool::new(
+            DefaultPoolImpl::new(strategy).set_probe_order(ProbeOrder::NewestFirst),
+        );
+        let keys = (0..30).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys).await.unwrap();
+
+        fetched.store(0, Ordering::SeqCst);
+        let query = vec!["key_21".to_string(), "key_29".to_string()];
+        let result = pool.batch_contain("user001", query).await.unwrap();
+        assert_eq!(result, vec![true, true]);
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+    }
+
+    // reload只加载已有chunk，查询不存在的group不会创建chunk
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = || {
+            BloomExpandStrategy::build_from_file("biz02", dir.path())
+                .unwrap()
+                .set_strategy_fixed(10)
+        };
+        let fg = FilterGroup::new("user001".into(), Arc::new(strategy()));
+        fg.reload().await.unwrap();
+        let result = fg.batch_contain(vec!["a".into()]).await.unwrap();
+        assert_eq!(result, vec![false]);
+        assert!(strategy().groups().await.unwrap().is_empty());
+
+        let pool = FiltersPool::from(strategy());
+        pool.insert("user001", "a".into()).await.unwrap();
+        fg.reload().await.unwrap();
+        assert_eq!(
+            fg.batch_contain(vec!["a".into()]).await.unwrap(),
+            vec![true]
+        );
+        assert_eq!(strategy().groups().await.unwrap(), vec!["user001"]);
+    }
+}
@@ -0,0 +1,627 @@
+use crate::{Bitmap, BloomExpandStrategy, FilterKind, FiltersInfo, Migration, QuotaConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use wd_tools::{PFErr, PFOk};
+
+pub const DEFAULT_FP_RATE: f64 = 0.001;
+pub const DEFAULT_TIMESTAMP_SIZE: i64 = 60 * 60;
+pub const DEFAULT_ENV_PREFIX: &str = "SGFLT_";
+
+// 可序列化的扩容策略，Strategy::Function无法通过配置表达
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StrategyKind {
+    Fixed { size: usize },
+    Ladder { sizes: Vec<usize> },
+}
+
+impl Default for StrategyKind {
+    fn default() -> Self {
+        StrategyKind::Ladder {
+            sizes: vec![100, 1000, 5000],
+        }
+    }
+}
+
+// 环境变量中的写法：fixed:1000 或 ladder:100,1000,5000
+impl FromStr for StrategyKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':').unwrap_or((s, ""));
+        match kind.trim() {
+            "fixed" => StrategyKind::Fixed {
+                size: value.trim().parse()?,
+            }
+            .ok(),
+            "ladder" => {
+                let sizes = value
+                    .split(',')
+                    .map(|x| x.trim().parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                StrategyKind::Ladder { sizes }.ok()
+            }
+            _ => anyhow::anyhow!("unknown strategy[{}], expect fixed:N or ladder:N,N,...", s).err(),
+        }
+    }
+}
+
+impl Display for StrategyKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StrategyKind::Fixed { size } => write!(f, "fixed:{}", size),
+            StrategyKind::Ladder { sizes } => {
+                let sizes = sizes.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                write!(f, "ladder:{}", sizes.join(","))
+            }
+        }
+    }
+}
+
+// 单个appid的配置，未设置的字段使用上一层的值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    // 后端url，与 BloomExpandStrategy::build_from_url 相同
+    pub backend: Option<String>,
+    pub strategy: Option<StrategyKind>,
+    pub fp_rate: Option<f64>,
+    // chunk key的时间分桶大小，单位s
+    pub timestamp_size: Option<i64>,
+    // chunk保留时间，单位s
+    pub retention: Option<i64>,
+    // 每个chunk的bitmap拆分成的key数，默认1
+    pub shards: Option<usize>,
+    // chunk的过滤器实现，basic或blocked
+    pub filter: Option<FilterKind>,
+    // 与上一层按字段合并
+    pub quota: Option<QuotaConfig>,
+    // 迁移目标后端url，设置后写入双写到backend和migrate_to，读取按切换标记选择
+    pub migrate_to: Option<String>,
+}
+
+impl AppConfig {
+    // other中设置的字段覆盖当前值
+    fn merge(&self, other: &AppConfig) -> AppConfig {
+        AppConfig {
+            backend: other.backend.clone().or_else(|| self.backend.clone()),
+            strategy: other.strategy.clone().or_else(|| self.strategy.clone()),
+            fp_rate: other.fp_rate.or(self.fp_rate),
+            timestamp_size: other.timestamp_size.or(self.timestamp_size),
+            retention: other.retention.or(self.retention),
+            shards: other.shards.or(self.shards),
+            filter: other.filter.or(self.filter),
+            quota: match (&self.quota, &other.quota) {
+                (Some(a), Some(b)) => Some(a.merge(b)),
+                (a, b) => b.clone().or_else(|| a.clone()),
+            },
+            migrate_to: other.migrate_to.clone().or_else(|| self.migrate_to.clone()),
+        }
+    }
+}
+
+// 合并默认值和覆盖之后，某个appid最终生效的配置
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResolvedConfig {
+    pub appid: String,
+    pub backend: String,
+    pub strategy: StrategyKind,
+    pub fp_rate: f64,
+    pub timestamp_size: i64,
+    pub retention: Option<i64>,
+    pub shards: usize,
+    pub filter: FilterKind,
+    pub quota: QuotaConfig,
+    pub migrate_to: Option<String>,
+}
+
+impl ResolvedConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let err = |msg: String| anyhow::anyhow!("appid[{}] {}", self.appid, msg).err();
+        if self.backend.is_empty() {
+            return err("backend is empty".into());
+        }
+        match &self.strategy {
+            StrategyKind::Fixed { size } if *size == 0 => {
+                return err("strategy fixed size must be > 0".into())
+            }
+            StrategyKind::Ladder { sizes } if sizes.is_empty() || sizes.contains(&0) => {
+                return err(format!(
+                    "strategy ladder {:?} must be non-empty and > 0",
+                    sizes
+                ))
+            }
+            _ => {}
+        }
+        if !(self.fp_rate > 0.0 && self.fp_rate < 1.0) {
+            return err(format!("fp_rate[{}] must be in (0,1)", self.fp_rate));
+        }
+        if self.timestamp_size <= 0 {
+            return err(format!(
+                "timestamp_size[{}] must be > 0",
+                self.timestamp_size
+            ));
+        }
+        if let Some(r) = self.retention {
+            if r < self.timestamp_size {
+                return err(format!(
+                    "retention[{}] must be >= timestamp_size[{}]",
+                    r, self.timestamp_size
+                ));
+            }
+            // chunk下标包含过期的chunk，阶梯用完后group将无法再扩容
+            if let StrategyKind::Ladder { .. } = self.strategy {
+                return err("retention can not be used with ladder strategy".into());
+            }
+        }
+        if self.shards == 0 {
+            return err("shards must be > 0".into());
+        }
+        if self.filter == FilterKind::Blocked && self.shards > 1 {
+            return err("blocked filter does not support shards".into());
+        }
+        if let Err(e) = self.quota.validate() {
+            return err(e.to_string());
+        }
+        match &self.migrate_to {
+            Some(to) if to.is_empty() => return err("migrate_to is empty".into()),
+            Some(to) if *to == self.backend => {
+                return err(format!("migrate_to[{}] is same as backend", to))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> anyhow::Result<BloomExpandStrategy> {
+        self.validate()?;
+        let strategy = BloomExpandStrategy::build_from_url(self.appid.as_str(), &self.backend)?;
+        let Some(to) = &self.migrate_to else {
+            return self.apply(strategy);
+        };
+        let target = BloomExpandStrategy::build_from_url(self.appid.as_str(), to)?;
+        let migration = Migration::new(
+            strategy.filter_info(),
+            strategy.bitmap(),
+            target.filter_info(),
+            target.bitmap(),
+        )
+        .set_appid(self.appid.as_str())
+        .set_shards(self.shards)
+        .set_filter(self.filter);
+        self.apply(
+            strategy
+                .set_filter_info(migration.filter_info())
+                .set_bitmap(migration.bitmap()),
+        )
+    }
+    // 在已有的后端上构建strategy，后端不变时reload沿用原来的存储
+    pub fn build_on(
+        &self,
+        info: Arc<dyn FiltersInfo>,
+        bitmap: Arc<dyn Bitmap>,
+    ) -> anyhow::Result<BloomExpandStrategy> {
+        self.validate()?;
+        self.apply(BloomExpandStrategy::from_backend(
+            self.appid.clone(),
+            info,
+            bitmap,
+        ))
+    }
+    // 把后端以外的配置应用到strategy上
+    fn apply(&self, strategy: BloomExpandStrategy) -> anyhow::Result<BloomExpandStrategy> {
+        // 先设置扩容方式，set_retention需要检查是否为阶梯扩容
+        let strategy = match &self.strategy {
+            StrategyKind::Fixed { size } => strategy.set_strategy_fixed(*size),
+            StrategyKind::Ladder { sizes } => strategy.set_strategy_ladder(sizes.clone()),
+        };
+        strategy
+            .set_fp_rate(self.fp_rate)
+            .set_timestamp_size(self.timestamp_size)
+            .set_retention(self.retention)?
+            .set_shards(self.shards)
+            .set_filter(self.filter)
+            .set_quota(self.quota.clone())
+            .ok()
+    }
+    // 后端地址或迁移设置相同时可以复用已连接的后端，迁移时分片数和过滤器类型也参与数据回填
+    pub fn same_backend(&self, other: &ResolvedConfig) -> bool {
+        self.backend == other.backend
+            && self.migrate_to == other.migrate_to
+            && (self.migrate_to.is_none()
+                || (self.shards == other.shards && self.filter == other.filter))
+    }
+}
+
+// 策略配置，顶层字段是所有appid的默认值，apps下按appid覆盖
+//
+// backend = "redis://127.0.0.1/"
+// fp_rate = 0.001
+// strategy = { kind = "ladder", sizes = [100, 1000, 5000] }
+//
+// [apps.biz02]
+// fp_rate = 0.0001
+// strategy = { kind = "fixed", size = 100000 }
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StrategyConfig {
+    #[serde(flatten)]
+    pub default: AppConfig,
+    #[serde(default)]
+    pub apps: BTreeMap<String, AppConfig>,
+}
+
+impl StrategyConfig {
+    pub fn from_toml(s: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+    pub fn from_yaml(s: &str) -> anyhow::Result<Self> {
+        Ok(serde_yaml::from_str(s)?)
+    }
+    // 根据扩展名选择格式：.toml .yaml .yml .json
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("yaml") | Some("yml") => Self::from_yaml(&text),
+            Some("json") => Ok(serde_json::from_str(&text)?),
+            _ => anyhow::anyhow!("unknown config format[{}]", path.display()).err(),
+        }
+    }
+    // 只从环境变量加载
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::default().apply_env(DEFAULT_ENV_PREFIX, std::env::vars())
+    }
+
+    // 用环境变量覆盖配置，{prefix}{FIELD} 为默认值，{prefix}{FIELD}__{appid} 为appid的值
+    // FIELD: BACKEND STRATEGY FP_RATE TIMESTAMP_SIZE RETENTION SHARDS FILTER MIGRATE_TO 以及配额
+    // MAX_GROUPS MAX_CHUNKS_PER_GROUP MAX_BITMAP_BYTES OPS_PER_SEC BURST，其他变量忽略
+    pub fn apply_env<I: IntoIterator<Item = (String, String)>>(
+        mut self,
+        prefix: &str,
+        vars: I,
+    ) -> anyhow::Result<Self> {
+        for (k, v) in vars {
+            let Some(name) = k.strip_prefix(prefix) else {
+                continue;
+            };
+            let (field, app) = match name.split_once("__") {
+                Some((f, a)) => (f, Some(a)),
+                None => (name, None),
+            };
+            const FIELDS: [&str; 13] = [
+                "BACKEND",
+                "STRATEGY",
+                "FP_RATE",
+                "TIMESTAMP_SIZE",
+                "RETENTION",
+                "SHARDS",
+                "FILTER",
+                "MIGRATE_TO",
+                "MAX_GROUPS",
+                "MAX_CHUNKS_PER_GROUP",
+                "MAX_BITMAP_BYTES",
+                "OPS_PER_SEC",
+                "BURST",
+            ];
+            if !FIELDS.contains(&field) {
+                continue;
+            }
+            let app = match app {
+                Some(a) => self.apps.entry(a.to_string()).or_default(),
+                None => &mut self.default,
+            };
+            let parse_err = |e: &dyn Display| anyhow::anyhow!("env[{}={}] {}", k, v, e);
+            match field {
+                "BACKEND" => app.backend = Some(v.clone()),
+                "STRATEGY" => app.strategy = Some(v.parse().map_err(|e| parse_err(&e))?),
+                "FP_RATE" => app.fp_rate = Some(v.parse().map_err(|e| parse_err(&e))?),
+                "TIMESTAMP_SIZE" => {
+                    app.timestamp_size = Some(v.parse().map_err(|e| parse_err(&e))?)
+                }
+                "RETENTION" => app.retention = Some(v.parse().map_err(|e| parse_err(&e))?),
+                "SHARDS" => app.shards = Some(v.parse().map_err(|e| parse_err(&e))?),
+                "FILTER" => app.filter = Some(v.parse().map_err(|e| parse_err(&e))?),
+                "MIGRATE_TO" => app.migrate_to = Some(v.clone()),
+                _ => {
+                    let quota = app.quota.get_or_insert_with(QuotaConfig::default);
+                    match field {
+                        "MAX_GROUPS" => {
+                            quota.max_groups = Some(v.parse().map_err(|e| parse_err(&e))?)
+                        }
+                        "MAX_CHUNKS_PER_GROUP" => {
+                            quota.max_chunks_per_group = Some(v.parse().map_err(|e| parse_err(&e))?)
+                        }
+                        "MAX_BITMAP_BYTES" => {
+                            quota.max_bitmap_bytes = Some(v.parse().map_err(|e| parse_err(&e))?)
+                        }
+                        "OPS_PER_SEC" => {
+                            quota.ops_per_sec = Some(v.parse().map_err(|e| parse_err(&e))?)
+                        }
+                        _ => quota.burst = Some(v.parse().map_err(|e| parse_err(&e))?),
+                    }
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    // 配置了覆盖项的appid
+    pub fn appids(&self) -> Vec<String> {
+        self.apps.keys().cloned().collect()
+    }
+
+    pub fn resolve(&self, appid: &str) -> anyhow::Result<ResolvedConfig> {
+        let app = match self.apps.get(appid) {
+            Some(app) => self.default.merge(app),
+            None => self.default.clone(),
+        };
+        let backend = match app.backend {
+            Some(b) => b,
+            None => return anyhow::anyhow!("appid[{}] has no backend", appid).err(),
+        };
+        ResolvedConfig {
+            appid: appid.to_string(),
+            backend,
+            strategy: app.strategy.unwrap_or_default(),
+            fp_rate: app.fp_rate.unwrap_or(DEFAULT_FP_RATE),
+            timestamp_size: app.timestamp_size.unwrap_or(DEFAULT_TIMESTAMP_SIZE),
+            retention: app.retention,
+            shards: app.shards.unwrap_or(1),
+            filter: app.filter.unwrap_or_default(),
+            quota: app.quota.unwrap_or_default(),
+            migrate_to: app.migrate_to,
+        }
+        .ok()
+    }
+
+    // 校验所有appid最终生效的配置，默认值没有backend时只校验apps
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.default.backend.is_some() || self.apps.is_empty() {
+            self.resolve("")
+                .and_then(|x| x.validate())
+                .map_err(|e| anyhow::anyhow!("default config: {}", e))?;
+        }
+        for appid in self.apps.keys() {
+            self.resolve(appid)?.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn build(&self, appid: &str) -> anyhow::Result<BloomExpandStrategy> {
+        self.resolve(appid)?.build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        BloomExpandStrategy, ChunkRegistration, DelimitedKeyLayout, FilterExpandStrategy,
+        FilterInfoFile, FilterKind, FiltersInfo, FiltersPool, StrategyConfig, StrategyKind,
+    };
+
+    const TOML: &str = r#"
+backend = "memory://"
+timestamp_size = 60
+strategy = { kind = "ladder", sizes = [100, 1000] }
+
+quota = { max_groups = 100, ops_per_sec = 1000.0 }
+
+[apps.biz02]
+fp_rate = 0.0001
+retention = 86400
+shards = 4
+strategy = { kind = "fixed", size = 10 }
+quota = { max_groups = 10, max_chunks_per_group = 5 }
+
+[apps.biz03]
+backend = "file:///tmp/sgflt"
+filter = "blocked"
+"#;
+
+    #[test]
+    fn test_config_formats() {
+        let cfg = StrategyConfig::from_toml(TOML).unwrap();
+        let yaml = serde_yaml::to_string(&cfg).unwrap();
+        assert_eq!(StrategyConfig::from_yaml(&yaml).unwrap(), cfg);
+        assert_eq!(cfg.appids(), vec!["biz02", "biz03"]);
+        cfg.validate().unwrap();
+
+        let biz02 = cfg.resolve("biz02").unwrap();
+        assert_eq!(biz02.backend, "memory://");
+        assert_eq!(biz02.strategy, StrategyKind::Fixed { size: 10 });
+        assert_eq!(biz02.fp_rate, 0.0001);
+        assert_eq!(biz02.timestamp_size, 60);
+        assert_eq!(biz02.retention, Some(86400));
+        assert_eq!(biz02.shards, 4);
+        assert_eq!(biz02.quota.max_groups, Some(10));
+        assert_eq!(biz02.quota.max_chunks_per_group, Some(5));
+        assert_eq!(biz02.quota.ops_per_sec, Some(1000.0));
+
+        let other = cfg.resolve("other").unwrap();
+        assert_eq!(other.fp_rate, 0.001);
+        assert_eq!(other.shards, 1);
+        assert_eq!(other.strategy.to_string(), "ladder:100,1000");
+        assert_eq!(cfg.resolve("biz03").unwrap().backend, "file:///tmp/sgflt");
+        assert_eq!(cfg.resolve("biz03").unwrap().filter, FilterKind::Blocked);
+        assert_eq!(other.filter, FilterKind::Basic);
+        assert_eq!(cfg.build("biz02").unwrap().appid(), "biz02");
+        assert_eq!(cfg.build("biz02").unwrap().shards(), 4);
+    }
+
+    #[test]
+    fn test_config_env() {
+        let vars = [
+            ("SGFLT_BACKEND", "memory://"),
+            ("SGFLT_STRATEGY", "fixed:1000"),
+            ("SGFLT_FP_RATE__biz02", "0.01"),
+            ("SGFLT_STRATEGY__biz02", "ladder:10, 20"),
+            ("OTHER_FP_RATE", "2"),
+            ("SGFLT_LISTEN__biz09", "0.0.0.0:8080"),
+            ("SGFLT_OPS_PER_SEC__biz02", "50"),
+            ("SGFLT_SHARDS__biz02", "8"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let cfg = StrategyConfig::from_toml(TOML)
+            .unwrap()
+            .apply_env("SGFLT_", vars)
+            .unwrap();
+        assert_eq!(cfg.appids(), vec!["biz02", "biz03"]);
+        let biz02 = cfg.resolve("biz02").unwrap();
+        assert_eq!(biz02.fp_rate, 0.01);
+        assert_eq!(
+            biz02.strategy,
+            StrategyKind::Ladder {
+                sizes: vec![10, 20]
+            }
+        );
+        assert_eq!(biz02.retention, Some(86400));
+        assert_eq!(biz02.quota.ops_per_sec, Some(50.0));
+        assert_eq!(biz02.shards, 8);
+        assert_eq!(biz02.quota.max_groups, Some(10));
+        assert_eq!(
+            cfg.resolve("other").unwrap().strategy,
+            StrategyKind::Fixed { size: 1000 }
+        );
+
+        let vars = [("SGFLT_FP_RATE", "abc".to_string())].map(|(k, v)| (k.to_string(), v));
+        assert!(StrategyConfig::default().apply_env("SGFLT_", vars).is_err());
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let invalid = [
+            "fp_rate = 0.001",
+            "backend = \"memory://\"\nfp_rate = 1.5",
+            "backend = \"memory://\"\ntimestamp_size = 0",
+            "backend = \"memory://\"\ntimestamp_size = 60\nretention = 10",
+            "backend = \"memory://\"\nstrategy = { kind = \"fixed\", size = 0 }",
+            "backend = \"memory://\"\nstrategy = { kind = \"ladder\", sizes = [] }",
+            "backend = \"memory://\"\n[apps.biz02]\nfp_rate = 0",
+            "backend = \"memory://\"\nquota = { max_groups = 0 }",
+            "backend = \"memory://\"\nshards = 0",
+            "backend = \"memory://\"\nshards = 2\nfilter = \"blocked\"",
+            "backend = \"memory://\"\nmigrate_to = \"\"",
+            "backend = \"memory://\"\nmigrate_to = \"memory://\"",
+        ];
+        for s in invalid {
+            let cfg = StrategyConfig::from_toml(s).unwrap();
+            assert!(cfg.validate().is_err(), "{}", s);
+        }
+        // 默认值没有backend，只要每个appid都配置了即可
+        let cfg = StrategyConfig::from_toml("[apps.biz02]\nbackend = \"memory://\"").unwrap();
+        cfg.validate().unwrap();
+        assert!(cfg.build("other").is_err());
+        assert!(StrategyConfig::from_toml("strategy = { kind = \"function\" }").is_err());
+        assert!("linear:1".parse::<StrategyKind>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retention() {
+        let cfg = StrategyConfig::from_toml(
+            "backend = \"memory://\"\ntimestamp_size = 60\nretention = 3600\nstrategy = { kind = \"fixed\", size = 100 }",
+        )
+        .unwrap();
+        let strategy = cfg.build("biz02").unwrap();
+        let layout = strategy.key_layout();
+        let group = layout.group_key("biz02", "user001");
+        let now = wd_tools::time::utc_timestamp();
+        let info = strategy.filter_info();
+        let chunk = ChunkRegistration {
+            usage_key: layout.usage_key("biz02"),
+            capacity: 100,
+            bytes: 180,
+            ..Default::default()
+        };
+        for (ts, index) in [(now - 7200, 0), (now, 1)] {
+            let code = layout.chunk_key(group.as_str(), ts, index);
+            info.register(group.as_str(), code.as_str(), &chunk)
+                .await
+                .unwrap();
+        }
+        let list = strategy.load_filter_group("user001").await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert!(list[0].code().ends_with("_1"));
+        // 过期的chunk不计入列表，但新chunk的下标仍然在所有已注册chunk之后
+        let chunk = strategy
+            .expand_chunk("user001", list.len() as isize)
+            .await
+            .unwrap();
+        assert!(chunk.code().ends_with("_2"));
+        let list = strategy.load_filter_group("user001").await.unwrap();
+        assert_eq!(list.last().unwrap().code(), chunk.code());
+        // 并发扩容时另一个任务仍按旧的列表长度扩容，得到同一个chunk
+        let again = strategy.expand_chunk("user001", 1).await.unwrap();
+        assert_eq!(again.code(), chunk.code());
+
+        let strategy = BloomExpandStrategy::build_from_memory("biz02").set_timestamp_size(60);
+        assert!(strategy.set_retention(Some(10)).is_err());
+        // 阶梯扩容不能设置保留时间
+        let strategy = BloomExpandStrategy::build_from_memory("biz02")
+            .set_timestamp_size(60)
+            .set_strategy_ladder(vec![100, 1000]);
+        assert!(strategy.set_retention(Some(3600)).is_err());
+        let cfg = StrategyConfig::from_toml(
+            "timestamp_size = 60\nretention = 3600\nstrategy = { kind = \"ladder\", sizes = [100] }",
+        )
+        .unwrap();
+        assert!(cfg.validate().is_err());
+
+        // 过期的chunk不计入max_chunks_per_group
+        let cfg = StrategyConfig::from_toml(
+            "backend = \"memory://\"\ntimestamp_size = 60\nretention = 3600\nstrategy = { kind = \"fixed\", size = 100 }\nquota = { max_chunks_per_group = 1 }",
+        )
+        .unwrap();
+        let strategy = cfg.build("biz02").unwrap();
+        let group = layout.group_key("biz02", "user002");
+        let code = layout.chunk_key(group.as_str(), now - 7200, 0);
+        let expired = ChunkRegistration {
+            usage_key: layout.usage_key("biz02"),
+            capacity: 100,
+            bytes: 180,
+            ..Default::default()
+        };
+        strategy
+            .filter_info()
+            .register(group.as_str(), code.as_str(), &expired)
+            .await
+            .unwrap();
+        let chunk = strategy.expand_chunk("user002", 0).await.unwrap();
+        assert!(chunk.code().ends_with("_1"));
+        assert!(strategy.expand_chunk("user002", 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_to() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        let url = |dir: &tempfile::TempDir| format!("file://{}", dir.path().display());
+        let text = format!(
+            "backend = \"{}\"\nstrategy = {{ kind = \"fixed\", size = 100 }}\n[apps.biz02]\nmigrate_to = \"{}\"",
+            url(&old),
+            url(&new)
+        );
+        let cfg = StrategyConfig::from_toml(text.as_str()).unwrap();
+        let biz02 = cfg.resolve("biz02").unwrap();
+        assert_eq!(biz02.migrate_to, Some(url(&new)));
+        assert_eq!(cfg.resolve("biz03").unwrap().migrate_to, None);
+
+        // 写入同时落到新旧两个后端
+        let pool = FiltersPool::from(biz02.build().unwrap());
+        pool.insert("user001", "a".into()).await.unwrap();
+        assert!(pool.contain("user001", "a".into()).await.unwrap());
+        let layout = DelimitedKeyLayout::default();
+        for dir in [&old, &new] {
+            let groups = FilterInfoFile::new(dir.path())
+                .unwrap()
+                .groups(&layout, "biz02")
+                .await
+                .unwrap();
+            assert_eq!(groups, vec!["user001".to_string()]);
+        }
+    }
+}
@@ -1,14 +1,20 @@
+use crate::QuotaKind;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
 pub enum SgfitErr {
     ChunkFull(usize),
+    // 超过appid的配额，第二个值为配置的上限
+    QuotaExceeded(QuotaKind, u64),
 }
 
 impl SgfitErr {
     pub fn new_chunk_full(cap: usize) -> Self {
         SgfitErr::ChunkFull(cap)
     }
+    pub fn new_quota_exceeded(kind: QuotaKind, limit: u64) -> Self {
+        SgfitErr::QuotaExceeded(kind, limit)
+    }
 }
 
 impl Debug for SgfitErr {
@@ -25,6 +31,9 @@ impl Display for SgfitErr {
             SgfitErr::ChunkFull(cap) => {
                 write!(f, "SgfitErr::ChunkFull[cap:{}]", cap)
             }
+            SgfitErr::QuotaExceeded(kind, limit) => {
+                write!(f, "SgfitErr::QuotaExceeded[{}:{}]", kind, limit)
+            }
         }
     }
 }
@@ -1,5 +1,8 @@
-use crate::bloom_group::FilterGroup;
-use crate::{FilterExpandStrategy, Pool};
+use crate::bloom_group::{
+    FilterGroup, ProbeOrder, DEFAULT_FETCH_WINDOW, DEFAULT_PROBE_PARALLELISM,
+};
+use crate::metrics::{observe_pool, record_contain};
+use crate::{FilterExpandStrategy, GroupStats, Pool};
 use std::sync::Arc;
 use wd_tools::{PFArc, PFBox};
 
@@ -20,39 +23,107 @@ impl FiltersPool {
 
 impl FiltersPool {
     pub async fn contain(&self, group: &str, key: String) -> anyhow::Result<bool> {
-        let fg = self.pool.get(group);
-        let _ = fg.try_extend().await;
-        let res = fg.contain(vec![key]).await?;
-        Ok(res[0])
+        self.contain_bytes(group, key.into_bytes()).await
     }
     pub async fn insert(&self, group: &str, keys: String) -> anyhow::Result<()> {
-        let fg = self.pool.get(group);
-        let _ = fg.try_extend().await;
-        fg.insert(vec![keys]).await
+        self.insert_bytes(group, keys.into_bytes()).await
     }
     pub async fn batch_contain(&self, group: &str, keys: Vec<String>) -> anyhow::Result<Vec<bool>> {
-        let fg = self.pool.get(group);
-        let _ = fg.try_extend().await;
-        fg.batch_contain(keys).await
+        self.batch_contain_bytes(group, into_bytes(keys)).await
     }
     pub async fn batch_insert(&self, group: &str, keys: Vec<String>) -> anyhow::Result<()> {
+        self.batch_insert_bytes(group, into_bytes(keys)).await
+    }
+    // 任意字节的key，utf8的key与String版本的结果一致
+    pub async fn contain_bytes(&self, group: &str, key: Vec<u8>) -> anyhow::Result<bool> {
+        let fg = self.pool.get(group);
+        observe_pool(fg.appid(), fg.backend(), "contain", group, 1, async {
+            fg.acquire(1)?;
+            fg.reload().await?;
+            let res = fg.contain(vec![key]).await?;
+            record_contain(fg.appid(), fg.backend(), res.as_slice());
+            Ok(res[0])
+        })
+        .await
+    }
+    pub async fn insert_bytes(&self, group: &str, key: Vec<u8>) -> anyhow::Result<()> {
         let fg = self.pool.get(group);
-        let _ = fg.try_extend().await;
-        fg.batch_insert(keys).await
+        observe_pool(fg.appid(), fg.backend(), "insert", group, 1, async {
+            fg.acquire(1)?;
+            let _ = fg.try_extend().await;
+            fg.insert(vec![key]).await
+        })
+        .await
+    }
+    pub async fn batch_contain_bytes(
+        &self,
+        group: &str,
+        keys: Vec<Vec<u8>>,
+    ) -> anyhow::Result<Vec<bool>> {
+        let fg = self.pool.get(group);
+        let n = keys.len();
+        observe_pool(fg.appid(), fg.backend(), "batch_contain", group, n, async {
+            fg.acquire(n)?;
+            fg.reload().await?;
+            let res = fg.batch_contain(keys).await?;
+            record_contain(fg.appid(), fg.backend(), res.as_slice());
+            Ok(res)
+        })
+        .await
+    }
+    pub async fn batch_insert_bytes(&self, group: &str, keys: Vec<Vec<u8>>) -> anyhow::Result<()> {
+        let fg = self.pool.get(group);
+        let n = keys.len();
+        observe_pool(fg.appid(), fg.backend(), "batch_insert", group, n, async {
+            fg.acquire(n)?;
+            let _ = fg.try_extend().await;
+            fg.batch_insert(keys).await
+        })
+        .await
+    }
+    pub async fn stats(&self, group: &str) -> anyhow::Result<GroupStats> {
+        self.pool.get(group).stats().await
     }
 }
 
+fn into_bytes(keys: Vec<String>) -> Vec<Vec<u8>> {
+    keys.into_iter().map(String::into_bytes).collect()
+}
+
 impl<T: FilterExpandStrategy + 'static> From<T> for FiltersPool {
     fn from(value: T) -> Self {
-        let strategy = Arc::new(value);
-        let pi = DefaultPoolImpl { strategy };
-        let pool = Box::new(pi);
-        FiltersPool { pool }
+        FiltersPool::new(DefaultPoolImpl::new(value))
     }
 }
 
 pub struct DefaultPoolImpl {
     strategy: Arc<dyn FilterExpandStrategy + 'static>,
+    parallelism: usize,
+    probe_order: ProbeOrder,
+    fetch_window: usize,
+}
+
+impl DefaultPoolImpl {
+    pub fn new<T: FilterExpandStrategy + 'static>(strategy: T) -> Self {
+        Self {
+            strategy: Arc::new(strategy),
+            parallelism: DEFAULT_PROBE_PARALLELISM,
+            probe_order: ProbeOrder::default(),
+            fetch_window: DEFAULT_FETCH_WINDOW,
+        }
+    }
+    pub fn set_parallelism(mut self, n: usize) -> Self {
+        self.parallelism = n;
+        self
+    }
+    pub fn set_probe_order(mut self, order: ProbeOrder) -> Self {
+        self.probe_order = order;
+        self
+    }
+    pub fn set_fetch_window(mut self, n: usize) -> Self {
+        self.fetch_window = n;
+        self
+    }
 }
 
 impl Pool<FilterGroup> for DefaultPoolImpl {
@@ -61,6 +132,10 @@ impl Pool<FilterGroup> for DefaultPoolImpl {
     }
 
     fn get(&self, group: &str) -> Arc<FilterGroup> {
-        FilterGroup::new(group.to_string(), self.strategy.clone()).arc()
+        FilterGroup::new(group.to_string(), self.strategy.clone())
+            .set_parallelism(self.parallelism)
+            .set_probe_order(self.probe_order)
+            .set_fetch_window(self.fetch_window)
+            .arc()
     }
 }
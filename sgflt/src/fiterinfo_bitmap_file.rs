@@ -0,0 +1,791 @@
+use crate::{AppUsage, Bitmap, ChunkRegistration, ChunkSpec, FiltersInfo, KeyLayout};
+use memmap2::{Mmap, MmapMut};
+use std::collections::{btree_map::Entry, BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use wd_tools::{PFOk, MD5};
+
+const BITMAP_DIR: &str = "bitmap";
+const INFO_DIR: &str = "info";
+const BITMAP_SUFFIX: &str = ".bit";
+const INFO_SUFFIX: &str = ".meta";
+const LOCK_SUFFIX: &str = ".lock";
+// 文件名过长时保存原始key的文件
+const KEY_SUFFIX: &str = ".key";
+// appid的用量文件，每行一个 group\tbytes
+const USAGE_SUFFIX: &str = ".usage";
+// group中chunk的构建参数，每行一个 chunk\tspec
+const SPEC_SUFFIX: &str = ".spec";
+// 文件名一般限制为255字节，超过时截断并追加key的md5，需要给后缀留出空间
+const MAX_NAME_LEN: usize = 200;
+const HASHED_PREFIX_LEN: usize = 160;
+// 转义后的文件名中不会出现 ~，用来标记截断的文件名
+const HASHED_MARK: char = '~';
+
+// 将任意key转换为安全的文件名，非 [A-Za-z0-9_-] 的字节使用百分号转义
+fn file_name(key: &str) -> String {
+    let mut buf = String::with_capacity(key.len());
+    for b in key.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'_' || b == b'-' {
+            buf.push(b as char);
+        } else {
+            buf.push_str(format!("%{:02X}", b).as_str());
+        }
+    }
+    if buf.len() > MAX_NAME_LEN {
+        let hash = key
+            .md5()
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect::<String>();
+        buf.truncate(HASHED_PREFIX_LEN);
+        buf.push(HASHED_MARK);
+        buf.push_str(hash.as_str());
+    }
+    buf
+}
+// appid用量文件使用的名字，和group key区分开
+fn usage_name(usage_key: &str) -> String {
+    format!("sgflt:usage:{}", usage_key)
+}
+// 截断的文件名无法还原，返回None
+fn key_from_file_name(name: &str) -> Option<String> {
+    if name.contains(HASHED_MARK) {
+        return None;
+    }
+    let src = name.as_bytes();
+    let mut bytes = Vec::with_capacity(src.len());
+    let mut i = 0;
+    while i < src.len() {
+        if src[i] == b'%' {
+            let hex = name.get(i + 1..i + 3)?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(src[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn create_dir(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+// 文件锁、mmap和fsync都会阻塞线程，在tokio的blocking线程池中执行
+// 没有tokio运行时(例如 BlockingFiltersPool::without_runtime)时直接在当前线程执行
+async fn blocking<T, F>(f: F) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+{
+    match tokio::runtime::Handle::try_current() {
+        Ok(_) => tokio::task::spawn_blocking(f).await?,
+        Err(_) => f(),
+    }
+}
+
+// 合并并发写入的fsync
+// 每次写入得到一个序号，落盘时一次处理所有待同步的文件，序号已被覆盖的写入直接返回
+#[derive(Default)]
+struct SyncBatch {
+    dirty: std::sync::Mutex<(u64, HashSet<PathBuf>)>,
+    synced: tokio::sync::Mutex<u64>,
+}
+
+impl SyncBatch {
+    async fn commit(&self, path: PathBuf) -> anyhow::Result<()> {
+        let seq = {
+            let mut dirty = self.dirty.lock().unwrap();
+            dirty.0 += 1;
+            dirty.1.insert(path);
+            dirty.0
+        };
+        let mut synced = self.synced.lock().await;
+        if *synced >= seq {
+            return Ok(());
+        }
+        let (last, paths) = {
+            let mut dirty = self.dirty.lock().unwrap();
+            (dirty.0, std::mem::take(&mut dirty.1))
+        };
+        let list = paths.clone();
+        let result = blocking(move || {
+            for path in list.iter() {
+                match File::open(path) {
+                    Ok(f) => f.sync_all()?,
+                    // 等待期间被删除的文件不需要同步
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(())
+        })
+        .await;
+        match result {
+            Ok(()) => {
+                *synced = last;
+                Ok(())
+            }
+            Err(e) => {
+                self.dirty.lock().unwrap().1.extend(paths);
+                Err(e)
+            }
+        }
+    }
+}
+
+// 本地文件实现的bitmap，每个chunk一个文件，通过mmap读写
+// 同一台机器上的多个进程通过文件锁共享，写入返回前落盘，并发写入的fsync会合并
+#[derive(Clone)]
+pub struct BitmapFile {
+    dir: Arc<PathBuf>,
+    sync: Arc<SyncBatch>,
+}
+
+impl BitmapFile {
+    pub fn new<P: AsRef<Path>>(root: P) -> anyhow::Result<Self> {
+        let dir = root.as_ref().join(BITMAP_DIR);
+        create_dir(dir.as_path())?;
+        Ok(Self {
+            dir: Arc::new(dir),
+            sync: Arc::default(),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}{}", file_name(key), BITMAP_SUFFIX))
+    }
+    fn open_write(&self, key: &str) -> anyhow::Result<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.path(key))?;
+        file.lock()?;
+        Ok(file)
+    }
+    fn open_read(&self, key: &str) -> anyhow::Result<Option<File>> {
+        let file = match File::open(self.path(key)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        file.lock_shared()?;
+        Ok(Some(file))
+    }
+    // 加锁状态下修改bitmap，文件长度不足时先扩容，修改完成后合并落盘
+    async fn modify<F>(&self, key: &str, max: usize, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut [u8]) + Send + 'static,
+    {
+        let this = self.clone();
+        let path = self.path(key);
+        let key = key.to_string();
+        blocking(move || {
+            let file = this.open_write(key.as_str())?;
+            let need = (max / 8 + 1) as u64;
+            if file.metadata()?.len() < need {
+                file.set_len(need)?;
+            }
+            let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+            f(&mut mmap);
+            file.unlock()?;
+            Ok(())
+        })
+        .await?;
+        // 共享映射的脏页由文件的fsync一起写回
+        self.sync.commit(path).await
+    }
+    async fn read<T, F>(&self, key: &str, default: T, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&[u8]) -> T + Send + 'static,
+    {
+        let this = self.clone();
+        let key = key.to_string();
+        blocking(move || {
+            let file = match this.open_read(key.as_str())? {
+                Some(f) => f,
+                None => return Ok(default),
+            };
+            if file.metadata()?.len() == 0 {
+                return Ok(default);
+            }
+            let mmap = unsafe { Mmap::map(&file)? };
+            let result = f(&mmap);
+            file.unlock()?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl Bitmap for BitmapFile {
+    async fn set(&self, key: &str, offset: usize, value: bool) -> anyhow::Result<()> {
+        self.modify(key, offset, move |buf| {
+            if value {
+                buf[offset / 8] |= 0x80 >> (offset % 8)
+            } else {
+                buf[offset / 8] &= !(0x80 >> (offset % 8))
+            }
+        })
+        .await
+    }
+
+    async fn get(&self, key: &str, offset: usize) -> anyhow::Result<bool> {
+        self.read(key, false, move |buf| {
+            buf.len() > offset / 8 && buf[offset / 8] & (0x80 >> (offset % 8)) != 0
+        })
+        .await
+    }
+
+    async fn mul_set(&self, key: &str, list: HashSet<usize>) -> anyhow::Result<()> {
+        let max = match list.iter().max() {
+            Some(m) => *m,
+            None => return Ok(()),
+        };
+        self.modify(key, max, |buf| {
+            for i in list {
+                buf[i / 8] |= 0x80 >> (i % 8)
+            }
+        })
+        .await
+    }
+
+    async fn mul_get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        self.read(key, vec![], |buf| buf.to_vec()).await
+    }
+
+    fn backend(&self) -> &'static str {
+        "file"
+    }
+
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.path(key);
+        blocking(move || match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        })
+        .await
+    }
+
+    async fn put_bytes(&self, key: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+        let this = self.clone();
+        let path = self.path(key);
+        let key = key.to_string();
+        blocking(move || {
+            let file = this.open_write(key.as_str())?;
+            file.set_len(bytes.len() as u64)?;
+            if !bytes.is_empty() {
+                let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+                mmap.copy_from_slice(bytes.as_slice());
+            }
+            file.unlock()?;
+            Ok(())
+        })
+        .await?;
+        self.sync.commit(path).await
+    }
+}
+
+// 本地文件实现的过滤器信息，每个group一个元数据文件，每行一个 chunk\tcount
+// 更新时先写临时文件再原子rename，保证进程崩溃时元数据完整，目录的fsync会合并
+// group key过长时文件名被截断，原始key保存在同名的 .key 文件中
+#[derive(Clone)]
+pub struct FilterInfoFile {
+    dir: Arc<PathBuf>,
+    sync: Arc<SyncBatch>,
+}
+
+impl FilterInfoFile {
+    pub fn new<P: AsRef<Path>>(root: P) -> anyhow::Result<Self> {
+        let dir = root.as_ref().join(INFO_DIR);
+        create_dir(dir.as_path())?;
+        Ok(Self {
+            dir: Arc::new(dir),
+            sync: Arc::default(),
+        })
+    }
+
+    fn path(&self, group: &str, suffix: &str) -> PathBuf {
+        self.dir.join(format!("{}{}", file_name(group), suffix))
+    }
+    // 元数据文件会被rename替换，所以锁加在单独的锁文件上
+    fn lock(&self, group: &str, exclusive: bool) -> anyhow::Result<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.path(group, LOCK_SUFFIX))?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(file)
+    }
+    fn load(&self, group: &str) -> anyhow::Result<BTreeMap<String, usize>> {
+        self.load_lines(group, INFO_SUFFIX)
+    }
+    fn load_lines<V>(&self, name: &str, suffix: &str) -> anyhow::Result<BTreeMap<String, V>>
+    where
+        V: FromStr,
+        V::Err: Into<anyhow::Error>,
+    {
+        let mut map = BTreeMap::new();
+        let mut file = match File::open(self.path(name, suffix)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(map),
+            Err(e) => return Err(e.into()),
+        };
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)?;
+        for line in buf.lines() {
+            if let Some((k, v)) = line.rsplit_once('\t') {
+                map.insert(k.to_string(), v.parse().map_err(Into::into)?);
+            }
+        }
+        Ok(map)
+    }
+    fn store(&self, group: &str, map: &BTreeMap<String, usize>) -> anyhow::Result<()> {
+        if file_name(group).contains(HASHED_MARK) {
+            let key = self.path(group, KEY_SUFFIX);
+            if !key.exists() {
+                let mut file = File::create(key.as_path())?;
+                file.write_all(group.as_bytes())?;
+                file.sync_all()?;
+            }
+        }
+        self.store_lines(group, INFO_SUFFIX, map)
+    }
+    fn store_lines<V: Display>(
+        &self,
+        name: &str,
+        suffix: &str,
+        map: &BTreeMap<String, V>,
+    ) -> anyhow::Result<()> {
+        let path = self.path(name, suffix);
+        let tmp = self.path(name, ".tmp");
+        let mut file = File::create(tmp.as_path())?;
+        for (k, v) in map.iter() {
+            writeln!(file, "{}\t{}", k, v)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(tmp.as_path(), path.as_path())?;
+        Ok(())
+    }
+    // 加锁修改appid的用量文件，调用方已持有group的锁
+    fn update_usage<F>(&self, usage_key: &str, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut BTreeMap<String, usize>),
+    {
+        let name = usage_name(usage_key);
+        let lock = self.lock(name.as_str(), true)?;
+        let mut map = self.load_lines(name.as_str(), USAGE_SUFFIX)?;
+        f(&mut map);
+        self.store_lines(name.as_str(), USAGE_SUFFIX, &map)?;
+        lock.unlock()?;
+        Ok(())
+    }
+    // 加锁读取元数据
+    async fn read<T, F>(&self, group: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(BTreeMap<String, usize>) -> T + Send + 'static,
+    {
+        let this = self.clone();
+        let group = group.to_string();
+        blocking(move || {
+            let lock = this.lock(group.as_str(), false)?;
+            let map = this.load(group.as_str())?;
+            lock.unlock()?;
+            Ok(f(map))
+        })
+        .await
+    }
+    // 加锁修改元数据，rename之后合并目录的fsync
+    async fn update<F>(&self, group: &str, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut BTreeMap<String, usize>) + Send + 'static,
+    {
+        let this = self.clone();
+        let name = group.to_string();
+        blocking(move || {
+            let lock = this.lock(name.as_str(), true)?;
+            let mut map = this.load(name.as_str())?;
+            f(&mut map);
+            this.store(name.as_str(), &map)?;
+            lock.unlock()?;
+            Ok(())
+        })
+        .await?;
+        self.sync.commit(self.dir.to_path_buf()).await
+    }
+}
+
+#[async_trait::async_trait]
+impl FiltersInfo for FilterInfoFile {
+    async fn list(&self, group: &str) -> anyhow::Result<Vec<(String, usize)>> {
+        self.read(group, |map| map.into_iter().collect::<Vec<_>>())
+            .await
+    }
+
+    async fn count(&self, group: &str, key: &str) -> anyhow::Result<usize> {
+        let key = key.to_string();
+        self.read(group, move |map| map.get(&key).copied().unwrap_or(0))
+            .await
+    }
+
+    async fn add(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        let key = key.to_string();
+        self.update(group, move |map| {
+            *map.entry(key).or_insert(0) += count;
+        })
+        .await
+    }
+
+    async fn set_count(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        let key = key.to_string();
+        self.update(group, move |map| {
+            map.insert(key, count);
+        })
+        .await
+    }
+
+    // 新chunk以0计数写入元数据，保证重新加载时能看到未写入过的chunk
+    async fn register(
+        &self,
+        group: &str,
+        key: &str,
+        chunk: &ChunkRegistration,
+    ) -> anyhow::Result<()> {
+        let this = self.clone();
+        let group = group.to_string();
+        let key = key.to_string();
+        let chunk = chunk.clone();
+        blocking(move || {
+            let lock = this.lock(group.as_str(), true)?;
+            let mut map = this.load(group.as_str())?;
+            // 已存在但没有参数的chunk(例如迁移期间双写计数时创建)补上参数
+            let mut specs = this.load_lines::<ChunkSpec>(group.as_str(), SPEC_SUFFIX)?;
+            if let Entry::Vacant(e) = specs.entry(key.clone()) {
+                e.insert(chunk.spec);
+                this.store_lines(group.as_str(), SPEC_SUFFIX, &specs)?;
+            }
+            if let Entry::Vacant(e) = map.entry(key) {
+                e.insert(0);
+                this.store(group.as_str(), &map)?;
+                this.update_usage(chunk.usage_key.as_str(), |usage| {
+                    *usage.entry(group.clone()).or_insert(0) += chunk.bytes as usize;
+                })?;
+            }
+            lock.unlock()?;
+            Ok(())
+        })
+        .await?;
+        self.sync.commit(self.dir.to_path_buf()).await
+    }
+
+    async fn specs(&self, group: &str) -> anyhow::Result<HashMap<String, ChunkSpec>> {
+        let this = self.clone();
+        let group = group.to_string();
+        blocking(move || {
+            let lock = this.lock(group.as_str(), false)?;
+            let specs = this.load_lines(group.as_str(), SPEC_SUFFIX)?;
+            lock.unlock()?;
+            Ok(specs.into_iter().collect())
+        })
+        .await
+    }
+
+    async fn usage(&self, usage_key: &str) -> anyhow::Result<AppUsage> {
+        let this = self.clone();
+        let name = usage_name(usage_key);
+        blocking(move || {
+            let lock = this.lock(name.as_str(), false)?;
+            let map = this.load_lines::<usize>(name.as_str(), USAGE_SUFFIX)?;
+            lock.unlock()?;
+            Ok(AppUsage {
+                groups: map.len(),
+                bytes: map.values().map(|x| *x as u64).sum(),
+            })
+        })
+        .await
+    }
+
+    async fn remove(&self, usage_key: &str, group: &str) -> anyhow::Result<()> {
+        let this = self.clone();
+        let usage_key = usage_key.to_string();
+        let group = group.to_string();
+        blocking(move || {
+            let lock = this.lock(group.as_str(), true)?;
+            for suffix in [INFO_SUFFIX, SPEC_SUFFIX, KEY_SUFFIX] {
+                match std::fs::remove_file(this.path(group.as_str(), suffix)) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            this.update_usage(usage_key.as_str(), |usage| {
+                usage.remove(group.as_str());
+            })?;
+            lock.unlock()?;
+            Ok(())
+        })
+        .await?;
+        self.sync.commit(self.dir.to_path_buf()).await
+    }
+
+    async fn groups(&self, layout: &dyn KeyLayout, appid: &str) -> anyhow::Result<Vec<String>> {
+        let dir = self.dir.clone();
+        let keys = blocking(move || {
+            let mut keys = vec![];
+            for entry in std::fs::read_dir(dir.as_path())? {
+                let name = entry?.file_name();
+                let name = match name.to_str().and_then(|x| x.strip_suffix(INFO_SUFFIX)) {
+                    Some(n) => n.to_string(),
+                    None => continue,
+                };
+                let key = match key_from_file_name(name.as_str()) {
+                    Some(k) => k,
+                    None if name.contains(HASHED_MARK) => {
+                        let path = dir.join(format!("{}{}", name, KEY_SUFFIX));
+                        match std::fs::read_to_string(path) {
+                            Ok(k) => k,
+                            Err(_) => continue,
+                        }
+                    }
+                    None => continue,
+                };
+                keys.push(key);
+            }
+            Ok(keys)
+        })
+        .await?;
+        let mut list = keys
+            .into_iter()
+            .filter_map(|k| layout.parse_group_key(k.as_str()))
+            .filter(|(a, _)| a == appid)
+            .map(|(_, g)| g)
+            .collect::<Vec<_>>();
+        list.sort();
+        list.ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fiterinfo_bitmap_file::{file_name, key_from_file_name};
+    use crate::{
+        AppUsage, Bitmap, BitmapFile, BloomExpandStrategy, ChunkRegistration, ChunkSpec,
+        DelimitedKeyLayout, FilterInfoFile, FilterKind, FiltersInfo, FiltersPool, KeyLayout,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_file_name() {
+        let key = "SFP_biz%5F02_user/001_1704798000_0";
+        let name = file_name(key);
+        assert!(!name.contains('/'));
+        assert_eq!(key_from_file_name(name.as_str()).unwrap(), key);
+
+        // 过长的key截断后追加md5，不同的key不会冲突
+        let long = format!("SFP_biz02_{}", "用户".repeat(50));
+        let name = file_name(long.as_str());
+        assert!(name.len() < 255 - 5);
+        assert_ne!(name, file_name(format!("{}1", long).as_str()));
+        assert!(key_from_file_name(name.as_str()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_bitmap_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let bitmap = BitmapFile::new(dir.path()).unwrap();
+        assert!(!bitmap.get("chunk", 9).await.unwrap());
+        bitmap
+            .mul_set("chunk", HashSet::from([9, 11]))
+            .await
+            .unwrap();
+        bitmap.set("chunk", 100, true).await.unwrap();
+        assert!(bitmap.get("chunk", 9).await.unwrap());
+        assert!(!bitmap.get("chunk", 10).await.unwrap());
+        assert!(bitmap.get("chunk", 100).await.unwrap());
+        bitmap.set("chunk", 100, false).await.unwrap();
+        assert!(!bitmap.get("chunk", 100).await.unwrap());
+
+        let buf = bitmap.mul_get("chunk").await.unwrap();
+        assert_eq!(buf.len(), 13);
+        assert_eq!(buf[1], 0b0101_0000);
+        assert!(bitmap.mul_get("none").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_filter_info_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let info = FilterInfoFile::new(dir.path()).unwrap();
+        info.add("SFP_biz02_user001", "SFP_biz02_user001_0_1", 3)
+            .await
+            .unwrap();
+        info.add("SFP_biz02_user001", "SFP_biz02_user001_0_0", 1)
+            .await
+            .unwrap();
+        info.add("SFP_biz02_user001", "SFP_biz02_user001_0_0", 2)
+            .await
+            .unwrap();
+        info.add("SFP_biz03_user001", "SFP_biz03_user001_0_0", 2)
+            .await
+            .unwrap();
+        let list = info.list("SFP_biz02_user001").await.unwrap();
+        assert_eq!(
+            list,
+            vec![
+                ("SFP_biz02_user001_0_0".to_string(), 3),
+                ("SFP_biz02_user001_0_1".to_string(), 3)
+            ]
+        );
+        let count = info
+            .count("SFP_biz02_user001", "SFP_biz02_user001_0_1")
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+        let groups = info
+            .groups(&DelimitedKeyLayout::default(), "biz02")
+            .await
+            .unwrap();
+        assert_eq!(groups, vec!["user001".to_string()]);
+
+        // register写入0计数的chunk，不覆盖已有计数，已存在的chunk不计入用量
+        let chunk = ChunkRegistration {
+            usage_key: "biz02".into(),
+            capacity: 10,
+            bytes: 18,
+            spec: ChunkSpec {
+                shards: 4,
+                filter: FilterKind::Basic,
+                layout: 1,
+            },
+        };
+        info.register("SFP_biz02_user001", "SFP_biz02_user001_0_2", &chunk)
+            .await
+            .unwrap();
+        info.register("SFP_biz02_user001", "SFP_biz02_user001_0_1", &chunk)
+            .await
+            .unwrap();
+        let list = info.list("SFP_biz02_user001").await.unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[1].1, 3);
+        assert_eq!(list[2].1, 0);
+        assert_eq!(
+            info.usage("biz02").await.unwrap(),
+            AppUsage {
+                groups: 1,
+                bytes: 18
+            }
+        );
+        // 已存在的chunk不计入用量，但会补上构建参数
+        let specs = info.specs("SFP_biz02_user001").await.unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(
+            specs.get("SFP_biz02_user001_0_2"),
+            Some(&ChunkSpec {
+                shards: 4,
+                filter: FilterKind::Basic,
+                layout: 1,
+            })
+        );
+
+        // 过长的group key通过 .key 文件还原
+        let long = "u".repeat(300);
+        let group = DelimitedKeyLayout::default().group_key("biz02", long.as_str());
+        info.add(group.as_str(), "chunk", 1).await.unwrap();
+        let groups = info
+            .groups(&DelimitedKeyLayout::default(), "biz02")
+            .await
+            .unwrap();
+        assert_eq!(groups, vec!["user001".to_string(), long]);
+        info.remove("biz02", group.as_str()).await.unwrap();
+        info.remove("biz02", "SFP_biz02_user001").await.unwrap();
+        assert_eq!(info.usage("biz02").await.unwrap(), AppUsage::default());
+        assert_eq!(info.list(group.as_str()).await.unwrap(), vec![]);
+        assert!(info.specs("SFP_biz02_user001").await.unwrap().is_empty());
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("info"))
+                .unwrap()
+                .filter(|x| {
+                    let name = x.as_ref().unwrap().file_name();
+                    name.to_str().unwrap().ends_with(".key")
+                })
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let bitmap = BitmapFile::new(dir.path()).unwrap();
+        let tasks = (0..64)
+            .map(|i| {
+                let bitmap = bitmap.clone();
+                tokio::spawn(async move { bitmap.set("chunk", i * 3, true).await })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(bitmap.count_ones("chunk").await.unwrap(), 64);
+        // 所有写入都已同步
+        assert!(bitmap.sync.dirty.lock().unwrap().1.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_filter_pool_by_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dir.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        let pool = FiltersPool::from(strategy);
+        let keys = (0..35).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys.clone()).await.unwrap();
+        pool.insert("user001", "key_single".into()).await.unwrap();
+        let result = pool.batch_contain("user001", keys).await.unwrap();
+        assert!(result.into_iter().all(|x| x));
+        assert!(pool.contain("user001", "key_single".into()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_drop_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = (0..25).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dir.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        let pool = FiltersPool::from(strategy);
+        pool.batch_insert("user001", keys.clone()).await.unwrap();
+        pool.batch_insert("user002", keys.clone()).await.unwrap();
+
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dir.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        assert_eq!(strategy.drop_group("user001").await.unwrap(), 3);
+        assert_eq!(
+            strategy.groups().await.unwrap(),
+            vec!["user002".to_string()]
+        );
+        let bits = std::fs::read_dir(dir.path().join("bitmap"))
+            .unwrap()
+            .count();
+        assert_eq!(bits, 3);
+        assert_eq!(strategy.drop_group("user001").await.unwrap(), 0);
+    }
+}
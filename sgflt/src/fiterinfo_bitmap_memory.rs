@@ -0,0 +1,292 @@
+use crate::{AppUsage, Bitmap, ChunkRegistration, ChunkSpec, FiltersInfo, KeyLayout};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use wd_tools::PFOk;
+
+// 进程内存实现的bitmap，不依赖任何运行时，适合测试和单机场景
+// clone之后共享同一份数据
+#[derive(Clone, Default)]
+pub struct BitmapMemory {
+    map: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl BitmapMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl Bitmap for BitmapMemory {
+    async fn set(&self, key: &str, offset: usize, value: bool) -> anyhow::Result<()> {
+        let mut map = self.map.lock().unwrap();
+        let buf = map.entry(key.to_string()).or_default();
+        let l = offset / 8;
+        if l >= buf.len() {
+            buf.resize(l + 1, 0);
+        }
+        if value {
+            buf[l] |= 0x80 >> (offset % 8);
+        } else {
+            buf[l] &= !(0x80 >> (offset % 8));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, offset: usize) -> anyhow::Result<bool> {
+        let map = self.map.lock().unwrap();
+        let bit = map
+            .get(key)
+            .and_then(|buf| buf.get(offset / 8))
+            .map(|b| b & (0x80 >> (offset % 8)) != 0)
+            .unwrap_or(false);
+        Ok(bit)
+    }
+
+    async fn get_bits(&self, key: &str, offsets: &[usize]) -> anyhow::Result<Vec<bool>> {
+        let map = self.map.lock().unwrap();
+        let buf = map.get(key).map(|x| x.as_slice()).unwrap_or(&[]);
+        offsets
+            .iter()
+            .map(|i| {
+                buf.get(i / 8)
+                    .map(|b| b & (0x80 >> (i % 8)) != 0)
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>()
+            .ok()
+    }
+
+    async fn mul_set(&self, key: &str, list: HashSet<usize>) -> anyhow::Result<()> {
+        let mut map = self.map.lock().unwrap();
+        let buf = map.entry(key.to_string()).or_default();
+        for i in list {
+            let l = i / 8;
+            if l >= buf.len() {
+                buf.resize(l + 1, 0);
+            }
+            buf[l] |= 0x80 >> (i % 8)
+        }
+        Ok(())
+    }
+
+    async fn mul_get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        let map = self.map.lock().unwrap();
+        map.get(key).cloned().unwrap_or_default().ok()
+    }
+
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        self.map.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn put_bytes(&self, key: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+        self.map.lock().unwrap().insert(key.to_string(), bytes);
+        Ok(())
+    }
+}
+
+// 进程内存实现的过滤器信息，clone之后共享同一份数据
+#[derive(Clone, Default)]
+pub struct FilterInfoMemory {
+    map: Arc<Mutex<HashMap<String, BTreeMap<String, usize>>>>,
+    // appid -> group -> bitmap字节数
+    usage: Arc<Mutex<HashMap<String, HashMap<String, u64>>>>,
+    // group -> chunk -> 构建参数
+    specs: Arc<Mutex<HashMap<String, HashMap<String, ChunkSpec>>>>,
+}
+
+impl FilterInfoMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl FiltersInfo for FilterInfoMemory {
+    async fn list(&self, group: &str) -> anyhow::Result<Vec<(String, usize)>> {
+        let map = self.map.lock().unwrap();
+        map.get(group)
+            .map(|x| x.iter().map(|(k, v)| (k.clone(), *v)).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .ok()
+    }
+
+    async fn count(&self, group: &str, key: &str) -> anyhow::Result<usize> {
+        let map = self.map.lock().unwrap();
+        map.get(group)
+            .and_then(|x| x.get(key))
+            .copied()
+            .unwrap_or(0)
+            .ok()
+    }
+
+    async fn add(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        let mut map = self.map.lock().unwrap();
+        *map.entry(group.to_string())
+            .or_default()
+            .entry(key.to_string())
+            .or_insert(0) += count;
+        Ok(())
+    }
+
+    async fn set_count(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        let mut map = self.map.lock().unwrap();
+        map.entry(group.to_string())
+            .or_default()
+            .insert(key.to_string(), count);
+        Ok(())
+    }
+
+    async fn register(
+        &self,
+        group: &str,
+        key: &str,
+        chunk: &ChunkRegistration,
+    ) -> anyhow::Result<()> {
+        let mut map = self.map.lock().unwrap();
+        self.specs
+            .lock()
+            .unwrap()
+            .entry(group.to_string())
+            .or_default()
+            .entry(key.to_string())
+            .or_insert(chunk.spec);
+        let chunks = map.entry(group.to_string()).or_default();
+        if chunks.contains_key(key) {
+            return Ok(());
+        }
+        chunks.insert(key.to_string(), 0);
+        *self
+            .usage
+            .lock()
+            .unwrap()
+            .entry(chunk.usage_key.clone())
+            .or_default()
+            .entry(group.to_string())
+            .or_insert(0) += chunk.bytes;
+        Ok(())
+    }
+
+    async fn specs(&self, group: &str) -> anyhow::Result<HashMap<String, ChunkSpec>> {
+        let specs = self.specs.lock().unwrap();
+        specs.get(group).cloned().unwrap_or_default().ok()
+    }
+
+    async fn usage(&self, usage_key: &str) -> anyhow::Result<AppUsage> {
+        let usage = self.usage.lock().unwrap();
+        let groups = usage.get(usage_key);
+        AppUsage {
+            groups: groups.map(|x| x.len()).unwrap_or(0),
+            bytes: groups.map(|x| x.values().sum()).unwrap_or(0),
+        }
+        .ok()
+    }
+
+    async fn remove(&self, usage_key: &str, group: &str) -> anyhow::Result<()> {
+        self.map.lock().unwrap().remove(group);
+        self.specs.lock().unwrap().remove(group);
+        if let Some(groups) = self.usage.lock().unwrap().get_mut(usage_key) {
+            groups.remove(group);
+        }
+        Ok(())
+    }
+
+    async fn groups(&self, layout: &dyn KeyLayout, appid: &str) -> anyhow::Result<Vec<String>> {
+        let map = self.map.lock().unwrap();
+        let mut list = map
+            .keys()
+            .filter_map(|k| layout.parse_group_key(k.as_str()))
+            .filter(|(a, _)| a == appid)
+            .map(|(_, g)| g)
+            .collect::<Vec<_>>();
+        list.sort();
+        list.ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        AppUsage, Bitmap, BitmapMemory, ChunkRegistration, ChunkSpec, DelimitedKeyLayout,
+        FilterInfoMemory, FilterKind, FiltersInfo,
+    };
+    use futures::executor::block_on;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_memory_backend() {
+        let bitmap = BitmapMemory::new();
+        block_on(bitmap.set("k", 9, true)).unwrap();
+        block_on(bitmap.mul_set("k", HashSet::from([0, 30]))).unwrap();
+        assert_eq!(
+            block_on(bitmap.mul_get("k")).unwrap(),
+            vec![0x80, 0x40, 0, 0x02]
+        );
+        assert_eq!(
+            block_on(bitmap.get_bits("k", &[0, 1, 9, 30, 100])).unwrap(),
+            vec![true, false, true, true, false]
+        );
+        block_on(bitmap.set("k", 9, false)).unwrap();
+        assert!(!block_on(bitmap.clone().get("k", 9)).unwrap());
+        assert_eq!(block_on(bitmap.count_ones("k")).unwrap(), 2);
+        block_on(bitmap.del("k")).unwrap();
+        assert!(block_on(bitmap.mul_get("k")).unwrap().is_empty());
+
+        let info = FilterInfoMemory::new();
+        let chunk = ChunkRegistration {
+            usage_key: "biz02".into(),
+            capacity: 10,
+            bytes: 18,
+            spec: ChunkSpec {
+                shards: 1,
+                filter: FilterKind::Blocked,
+                layout: 1,
+            },
+        };
+        block_on(info.register("SFP_biz02_user001", "c1", &chunk)).unwrap();
+        block_on(info.register("SFP_biz02_user001", "c1", &chunk)).unwrap();
+        block_on(info.register("SFP_biz02_user002", "c0", &chunk)).unwrap();
+        assert_eq!(
+            block_on(info.usage("biz02")).unwrap(),
+            AppUsage {
+                groups: 2,
+                bytes: 36
+            }
+        );
+        block_on(info.add("SFP_biz02_user001", "c0", 3)).unwrap();
+        block_on(info.add("SFP_biz03_user001", "c0", 1)).unwrap();
+        assert_eq!(
+            block_on(info.list("SFP_biz02_user001")).unwrap(),
+            vec![("c0".to_string(), 3), ("c1".to_string(), 0)]
+        );
+        let groups = block_on(info.groups(&DelimitedKeyLayout::default(), "biz02")).unwrap();
+        assert_eq!(groups, vec!["user001".to_string(), "user002".to_string()]);
+        let specs = block_on(info.specs("SFP_biz02_user001")).unwrap();
+        assert_eq!(
+            specs.get("c1"),
+            Some(&ChunkSpec {
+                shards: 1,
+                filter: FilterKind::Blocked,
+                layout: 1,
+            })
+        );
+        assert!(!specs.contains_key("c0"));
+        block_on(info.remove("biz02", "SFP_biz02_user001")).unwrap();
+        assert!(block_on(info.specs("SFP_biz02_user001"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(block_on(info.count("SFP_biz02_user001", "c0")).unwrap(), 0);
+        assert_eq!(
+            block_on(info.usage("biz02")).unwrap(),
+            AppUsage {
+                groups: 1,
+                bytes: 18
+            }
+        );
+    }
+}
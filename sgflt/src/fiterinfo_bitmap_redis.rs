@@ -1,28 +1,39 @@
-use crate::{Bitmap, FiltersInfo};
+use crate::metrics::observe_backend;
+use crate::{
+    AppUsage, Bitmap, ChunkRegistration, ChunkSpec, FiltersInfo, KeyLayout, RedisClient, RedisNode,
+};
 use redis::cluster::ClusterClient;
 use redis::{AsyncCommands, Client, IntoConnectionInfo};
 use std::collections::{HashMap, HashSet};
 use wd_tools::PFOk;
 
-#[derive(Clone)]
-#[allow(dead_code)]
-pub enum RedisClient {
-    CLUSTER(ClusterClient),
-    SINGLE(Client),
+const REDIS_BACKEND: &str = "redis";
+// appid的用量保存在一个hash中，每个group一个字段记录字节数，USAGE_BYTES记录总字节数
+const USAGE_PREFIX: &str = "sgflt:usage:";
+const USAGE_BYTES: &str = "__bytes";
+// 从用量中扣除group的字节数并删除group字段
+const REMOVE_USAGE_SCRIPT: &str = r#"
+local bytes = redis.call('HGET', KEYS[1], ARGV[1])
+if bytes then
+    redis.call('HINCRBY', KEYS[1], ARGV[2], -tonumber(bytes))
+    redis.call('HDEL', KEYS[1], ARGV[1])
+end
+if redis.call('HLEN', KEYS[1]) == 1 and redis.call('HEXISTS', KEYS[1], ARGV[2]) == 1 then
+    redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+const MUL_SET_BATCH: usize = 1024;
+
+// group中chunk的构建参数，每个chunk一个字段
+const SPEC_PREFIX: &str = "sgflt:spec:";
+
+fn usage_hash(usage_key: &str) -> String {
+    format!("{}{}", USAGE_PREFIX, usage_key)
 }
-impl TryFrom<&str> for RedisClient {
-    type Error = anyhow::Error;
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let client = Client::open(value)?;
-        Ok(RedisClient::SINGLE(client))
-    }
-}
-impl TryFrom<Vec<String>> for RedisClient {
-    type Error = anyhow::Error;
-    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
-        let cs = ClusterClient::new(value)?;
-        Ok(RedisClient::CLUSTER(cs))
-    }
+fn spec_key(group: &str) -> String {
+    format!("{}{}", SPEC_PREFIX, group)
 }
 
 pub struct BitmapRedis {
@@ -36,13 +47,13 @@ impl From<RedisClient> for BitmapRedis {
 impl BitmapRedis {
     #[allow(dead_code)]
     pub fn new_from_cluster(client: ClusterClient) -> Self {
-        let client = RedisClient::CLUSTER(client);
+        let client = RedisNode::CLUSTER(client).into();
         Self { client }
     }
     #[allow(dead_code)]
     pub fn redis_single_node(url: &str) -> anyhow::Result<Self> {
         let client = redis::Client::open(url)?;
-        let client = RedisClient::SINGLE(client);
+        let client = RedisNode::SINGLE(client).into();
         Ok(Self { client })
     }
     #[allow(dead_code)]
@@ -87,85 +98,102 @@ impl Bitmap for BitmapRedis {
     // }
 
     async fn set(&self, key: &str, offset: usize, value: bool) -> anyhow::Result<()> {
-        return match self.client {
-            RedisClient::CLUSTER(ref clu) => {
-                let mut conn = clu.get_async_connection().await?;
-                let _ = conn.setbit(key, offset, value).await?;
-                Ok(())
-            }
-            RedisClient::SINGLE(ref sin) => {
-                let mut conn = sin.get_async_connection().await?;
-                let _ = conn.setbit(key, offset, value).await?;
-                Ok(())
-            }
-        };
+        observe_backend(REDIS_BACKEND, "bitmap.set", key, async {
+            let mut conn = self.client.get_write_connection(key).await?;
+            let _: () = conn.setbit(key, offset, value).await?;
+            Ok(())
+        })
+        .await
     }
 
     async fn get(&self, key: &str, offset: usize) -> anyhow::Result<bool> {
-        return match self.client {
-            RedisClient::CLUSTER(ref clu) => {
-                let mut conn = clu.get_async_connection().await?;
-                let result: bool = conn.getbit(key, offset).await?;
-                Ok(result)
-            }
-            RedisClient::SINGLE(ref sin) => {
-                let mut conn = sin.get_async_connection().await?;
-                let result: bool = conn.getbit(key, offset).await?;
-                Ok(result)
+        observe_backend(REDIS_BACKEND, "bitmap.get", key, async {
+            let mut conn = self.client.get_read_connection(key).await?;
+            let result: bool = conn.getbit(key, offset).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    // 所有位通过pipeline一次往返读取
+    async fn get_bits(&self, key: &str, offsets: &[usize]) -> anyhow::Result<Vec<bool>> {
+        observe_backend(REDIS_BACKEND, "bitmap.get_bits", key, async {
+            let mut conn = self.client.get_read_connection(key).await?;
+            let mut pipe = redis::pipe();
+            for i in offsets.iter() {
+                pipe.getbit(key, *i);
             }
-        };
+            let result: Vec<bool> = pipe.query_async(&mut conn).await?;
+            Ok(result)
+        })
+        .await
     }
 
+    // 在服务端按位置位，不做读改写，并发写同一个key不会丢失bit
+    // 每个BITFIELD最多 MUL_SET_BATCH 个位置，所有命令在一个pipeline中发送
     async fn mul_set(&self, key: &str, list: HashSet<usize>) -> anyhow::Result<()> {
-        return match self.client {
-            RedisClient::CLUSTER(ref clu) => {
-                let mut conn = clu.get_async_connection().await?;
-                let result: Option<Vec<u8>> = conn.get(key).await?;
-                let mut buf = if let Some(s) = result { s } else { vec![] };
-                for i in list {
-                    let l = i / 8;
-                    if l >= buf.len() {
-                        let mut avec = vec![0u8; l - buf.len() + 1];
-                        buf.append(&mut avec);
-                    }
-                    buf[l] |= 0x80 >> (i % 8)
-                }
-                conn.set(key, buf).await?;
-                Ok(())
+        observe_backend(REDIS_BACKEND, "bitmap.mul_set", key, async {
+            if list.is_empty() {
+                return Ok(());
             }
-            RedisClient::SINGLE(ref sin) => {
-                let mut conn = sin.get_async_connection().await?;
-                let result: Option<Vec<u8>> = conn.get(key).await?;
-                let mut buf = if let Some(s) = result { s } else { vec![] };
-                for i in list {
-                    let l = i / 8;
-                    if l >= buf.len() {
-                        let mut avec = vec![0u8; l - buf.len() + 1];
-                        buf.append(&mut avec);
-                    }
-                    buf[l] |= 0x80 >> (i % 8)
+            let list = list.into_iter().collect::<Vec<_>>();
+            let mut pipe = redis::pipe();
+            for batch in list.chunks(MUL_SET_BATCH) {
+                let cmd = pipe.cmd("BITFIELD").arg(key);
+                for i in batch {
+                    cmd.arg("SET").arg("u1").arg(*i).arg(1);
                 }
-                conn.set(key, buf).await?;
-                Ok(())
+                cmd.ignore();
             }
-        };
+            let mut conn = self.client.get_write_connection(key).await?;
+            let _: () = pipe.query_async(&mut conn).await?;
+            Ok(())
+        })
+        .await
     }
 
     async fn mul_get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
-        return match self.client {
-            RedisClient::CLUSTER(ref clu) => {
-                let mut conn = clu.get_async_connection().await?;
-                let result: Option<Vec<u8>> = conn.get(key).await?;
-                let buf = if let Some(s) = result { s } else { vec![] };
-                Ok(buf)
-            }
-            RedisClient::SINGLE(ref sin) => {
-                let mut conn = sin.get_async_connection().await?;
-                let result: Option<Vec<u8>> = conn.get(key).await?;
-                let buf = if let Some(s) = result { s } else { vec![] };
-                Ok(buf)
-            }
-        };
+        observe_backend(REDIS_BACKEND, "bitmap.mul_get", key, async {
+            let mut conn = self.client.get_read_connection(key).await?;
+            let result: Option<Vec<u8>> = conn.get(key).await?;
+            let buf = result.unwrap_or_default();
+            Ok(buf)
+        })
+        .await
+    }
+
+    fn backend(&self) -> &'static str {
+        REDIS_BACKEND
+    }
+
+    async fn count_ones(&self, key: &str) -> anyhow::Result<usize> {
+        observe_backend(REDIS_BACKEND, "bitmap.count_ones", key, async {
+            let mut conn = self.client.get_read_connection(key).await?;
+            let count: usize = redis::cmd("BITCOUNT")
+                .arg(key)
+                .query_async(&mut conn)
+                .await?;
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        observe_backend(REDIS_BACKEND, "bitmap.del", key, async {
+            let mut conn = self.client.get_write_connection(key).await?;
+            let _: usize = conn.del(key).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn put_bytes(&self, key: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+        observe_backend(REDIS_BACKEND, "bitmap.put_bytes", key, async {
+            let mut conn = self.client.get_write_connection(key).await?;
+            let _: () = conn.set(key, bytes).await?;
+            Ok(())
+        })
+        .await
     }
 }
 
@@ -181,71 +209,159 @@ impl From<RedisClient> for FilterInfoRedis {
 impl FilterInfoRedis {
     #[allow(dead_code)]
     pub fn new_from_cluster(client: ClusterClient) -> Self {
-        let client = RedisClient::CLUSTER(client);
+        let client = RedisNode::CLUSTER(client).into();
         Self { client }
     }
     #[allow(dead_code)]
     pub fn redis_single_node(url: &str) -> anyhow::Result<Self> {
         let client = Client::open(url)?;
-        let client = RedisClient::SINGLE(client);
+        let client = RedisNode::SINGLE(client).into();
         Ok(Self { client })
     }
 }
 #[async_trait::async_trait]
 impl FiltersInfo for FilterInfoRedis {
     async fn list(&self, group: &str) -> anyhow::Result<Vec<(String, usize)>> {
-        match self.client {
-            RedisClient::CLUSTER(ref clu) => {
-                let mut client = clu.get_async_connection().await?;
-                let result: Option<HashMap<String, usize>> = client.hgetall(group).await?;
-                let map = result.unwrap_or(HashMap::new());
-                let mut list = vec![];
-                for (k, v) in map.into_iter() {
-                    list.push((k, v));
-                }
-                list.sort_by(|(a, _), (b, _)| a.cmp(b));
-                Ok(list)
-            }
-            RedisClient::SINGLE(ref sin) => {
-                let mut client = sin.get_async_connection().await?;
-                let result: Option<HashMap<String, usize>> = client.hgetall(group).await?;
-                let map = result.unwrap_or(HashMap::new());
-                let mut list = vec![];
-                for (k, v) in map.into_iter() {
-                    list.push((k, v));
-                }
-                list.sort_by(|(a, _), (b, _)| a.cmp(b));
-                Ok(list)
+        observe_backend(REDIS_BACKEND, "info.list", group, async {
+            let mut client = self.client.get_read_connection(group).await?;
+            let result: Option<HashMap<String, usize>> = client.hgetall(group).await?;
+            let map = result.unwrap_or_default();
+            let mut list = vec![];
+            for (k, v) in map.into_iter() {
+                list.push((k, v));
             }
-        }
+            list.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Ok(list)
+        })
+        .await
     }
 
     async fn count(&self, group: &str, key: &str) -> anyhow::Result<usize> {
-        match self.client {
-            RedisClient::CLUSTER(ref clu) => {
-                let mut client = clu.get_async_connection().await?;
-                let result: Option<usize> = client.hget(group, key).await?;
-                result.unwrap_or(0).ok()
-            }
-            RedisClient::SINGLE(ref sin) => {
-                let mut client = sin.get_async_connection().await?;
-                let result: Option<usize> = client.hget(group, key).await?;
-                result.unwrap_or(0).ok()
-            }
-        }
+        observe_backend(REDIS_BACKEND, "info.count", group, async {
+            let mut client = self.client.get_read_connection(group).await?;
+            let result: Option<usize> = client.hget(group, key).await?;
+            result.unwrap_or(0).ok()
+        })
+        .await
     }
 
     async fn add(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
-        match self.client {
-            RedisClient::CLUSTER(ref clu) => {
-                let mut client = clu.get_async_connection().await?;
-                let _: isize = client.hincr(group, key, count).await?;
+        observe_backend(REDIS_BACKEND, "info.add", group, async {
+            let mut client = self.client.get_write_connection(group).await?;
+            let _: isize = client.hincr(group, key, count).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_count(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        observe_backend(REDIS_BACKEND, "info.set_count", group, async {
+            let mut client = self.client.get_write_connection(group).await?;
+            let _: usize = client.hset(group, key, count).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    // HSETNX保证同一个chunk只计入一次用量
+    async fn register(
+        &self,
+        group: &str,
+        key: &str,
+        chunk: &ChunkRegistration,
+    ) -> anyhow::Result<()> {
+        observe_backend(REDIS_BACKEND, "info.register", group, async {
+            // 已存在但没有参数的chunk(例如迁移期间双写计数时创建)补上参数
+            let spec = spec_key(group);
+            let mut client = self.client.get_write_connection(spec.as_str()).await?;
+            let _: bool = client
+                .hset_nx(spec.as_str(), key, chunk.spec.to_string())
+                .await?;
+            let mut client = self.client.get_write_connection(group).await?;
+            let created: bool = client.hset_nx(group, key, 0).await?;
+            if !created {
+                return Ok(());
             }
-            RedisClient::SINGLE(ref sin) => {
-                let mut client = sin.get_async_connection().await?;
-                let _: isize = client.hincr(group, key, count).await?;
+            let usage = usage_hash(chunk.usage_key.as_str());
+            let mut client = self.client.get_write_connection(usage.as_str()).await?;
+            let _: () = redis::pipe()
+                .atomic()
+                .hincr(usage.as_str(), group, chunk.bytes)
+                .ignore()
+                .hincr(usage.as_str(), USAGE_BYTES, chunk.bytes)
+                .ignore()
+                .query_async(&mut client)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn specs(&self, group: &str) -> anyhow::Result<HashMap<String, ChunkSpec>> {
+        let spec = spec_key(group);
+        observe_backend(REDIS_BACKEND, "info.specs", group, async {
+            let mut client = self.client.get_read_connection(spec.as_str()).await?;
+            let map: HashMap<String, String> = client.hgetall(spec.as_str()).await?;
+            let mut specs = HashMap::with_capacity(map.len());
+            for (k, v) in map.into_iter() {
+                specs.insert(k, v.parse()?);
+            }
+            Ok(specs)
+        })
+        .await
+    }
+
+    async fn usage(&self, usage_key: &str) -> anyhow::Result<AppUsage> {
+        let usage = usage_hash(usage_key);
+        observe_backend(REDIS_BACKEND, "info.usage", usage.as_str(), async {
+            let mut client = self.client.get_read_connection(usage.as_str()).await?;
+            let (len, bytes): (usize, Option<u64>) = redis::pipe()
+                .hlen(usage.as_str())
+                .hget(usage.as_str(), USAGE_BYTES)
+                .query_async(&mut client)
+                .await?;
+            AppUsage {
+                groups: len.saturating_sub(bytes.is_some() as usize),
+                bytes: bytes.unwrap_or(0),
             }
-        }
-        Ok(())
+            .ok()
+        })
+        .await
+    }
+
+    async fn remove(&self, usage_key: &str, group: &str) -> anyhow::Result<()> {
+        observe_backend(REDIS_BACKEND, "info.remove", group, async {
+            let mut client = self.client.get_write_connection(group).await?;
+            let _: usize = client.del(group).await?;
+            let spec = spec_key(group);
+            let mut client = self.client.get_write_connection(spec.as_str()).await?;
+            let _: usize = client.del(spec.as_str()).await?;
+            let usage = usage_hash(usage_key);
+            let mut client = self.client.get_write_connection(usage.as_str()).await?;
+            let _: i64 = redis::Script::new(REMOVE_USAGE_SCRIPT)
+                .key(usage.as_str())
+                .arg(group)
+                .arg(USAGE_BYTES)
+                .invoke_async(&mut client)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn groups(&self, layout: &dyn KeyLayout, appid: &str) -> anyhow::Result<Vec<String>> {
+        // 集群模式下逐个主节点扫描
+        let pattern = layout.group_pattern(appid);
+        let keys = self.client.scan_match(pattern.as_str()).await?;
+        // chunk key同样会被匹配到，需要解析过滤
+        let mut list = keys
+            .into_iter()
+            .filter_map(|k| layout.parse_group_key(k.as_str()))
+            .filter(|(a, _)| a == appid)
+            .map(|(_, g)| g)
+            .collect::<Vec<_>>();
+        list.sort();
+        list.dedup();
+        Ok(list)
     }
 }
@@ -0,0 +1,298 @@
+use wd_tools::{PFErr, PFOk};
+
+pub const DEFAULT_KEY_PREFIX: &str = "SFP";
+pub const DEFAULT_KEY_DELIMITER: char = '_';
+
+// 通过chunk key解析出的信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkKey {
+    pub appid: String,
+    pub group: String,
+    pub timestamp: i64,
+    pub index: usize,
+}
+
+// 存储key的命名规则，group key用于FiltersInfo，chunk key用于Bitmap
+// 实现需要保证 parse_* 能够还原 *_key 生成的结果
+pub trait KeyLayout: Send + Sync {
+    fn group_key(&self, appid: &str, group: &str) -> String;
+    fn parse_group_key(&self, key: &str) -> Option<(String, String)>;
+
+    fn chunk_key(&self, group_key: &str, timestamp: i64, index: usize) -> String;
+    fn parse_chunk_key(&self, key: &str) -> Option<ChunkKey>;
+
+    // 匹配某个appid下所有group key的glob表达式，用于redis scan等
+    fn group_pattern(&self, appid: &str) -> String;
+
+    // appid用量计数的名字，不同前缀和命名空间下的同名appid互不影响
+    fn usage_key(&self, appid: &str) -> String;
+}
+
+// 默认的分隔符命名方式：{prefix}_[{namespace}_]{appid}_{group}[_{timestamp}_{index}]
+// 默认不转义，与引入KeyLayout之前的命名完全一致，名字中含分隔符的group无法解析
+// 开启转义后每一段中出现的分隔符和转义符会被百分号转义，任意appid和group都能够安全还原
+#[derive(Debug, Clone)]
+pub struct DelimitedKeyLayout {
+    prefix: String,
+    namespace: Option<String>,
+    delimiter: char,
+    escape: bool,
+}
+
+impl Default for DelimitedKeyLayout {
+    fn default() -> Self {
+        Self {
+            prefix: DEFAULT_KEY_PREFIX.to_string(),
+            namespace: None,
+            delimiter: DEFAULT_KEY_DELIMITER,
+            escape: false,
+        }
+    }
+}
+
+impl DelimitedKeyLayout {
+    pub fn new<P: Into<String>>(prefix: P) -> Self {
+        Self::default().set_prefix(prefix)
+    }
+    pub fn set_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+    pub fn set_namespace<N: Into<String>>(mut self, namespace: N) -> Self {
+        let namespace = namespace.into();
+        self.namespace = if namespace.is_empty() {
+            None
+        } else {
+            Some(namespace)
+        };
+        self
+    }
+    // 分隔符不能是转义符'%'和十六进制字符，否则转义后的key无法还原
+    pub fn set_delimiter(mut self, delimiter: char) -> anyhow::Result<Self> {
+        if delimiter == '%' || delimiter.is_ascii_hexdigit() {
+            return anyhow::anyhow!("invalid key delimiter: {:?}", delimiter).err();
+        }
+        self.delimiter = delimiter;
+        self.ok()
+    }
+
+    // 是否转义appid和group中的分隔符，默认关闭
+    // 开启后appid或group含有分隔符的存量数据会换成新的key，需要迁移后才能访问
+    pub fn set_escape(mut self, escape: bool) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    fn escape(&self, segment: &str) -> String {
+        if !self.escape {
+            return segment.to_string();
+        }
+        let mut buf = String::with_capacity(segment.len());
+        for c in segment.chars() {
+            if c == '%' || c == self.delimiter {
+                let mut bytes = [0u8; 4];
+                for b in c.encode_utf8(&mut bytes).bytes() {
+                    buf.push_str(format!("%{:02X}", b).as_str());
+                }
+            } else {
+                buf.push(c);
+            }
+        }
+        buf
+    }
+    fn unescape(&self, segment: &str) -> Option<String> {
+        if !self.escape {
+            return Some(segment.to_string());
+        }
+        let src = segment.as_bytes();
+        let mut bytes = Vec::with_capacity(src.len());
+        let mut i = 0;
+        while i < src.len() {
+            if src[i] == b'%' {
+                let hex = segment.get(i + 1..i + 3)?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            } else {
+                bytes.push(src[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8(bytes).ok()
+    }
+    // redis glob中的特殊字符需要使用反斜杠转义
+    fn escape_glob(segment: &str) -> String {
+        let mut buf = String::with_capacity(segment.len());
+        for c in segment.chars() {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                buf.push('\\');
+            }
+            buf.push(c);
+        }
+        buf
+    }
+
+    fn head(&self) -> Vec<String> {
+        let mut head = vec![self.escape(self.prefix.as_str())];
+        if let Some(ref ns) = self.namespace {
+            head.push(self.escape(ns.as_str()));
+        }
+        head
+    }
+    // 校验前缀和命名空间，返回剩余的分段
+    fn strip_head<'a>(&self, key: &'a str) -> Option<Vec<&'a str>> {
+        let list = key.split(self.delimiter).collect::<Vec<_>>();
+        let head = self.head();
+        if list.len() < head.len() {
+            return None;
+        }
+        for (i, h) in head.iter().enumerate() {
+            if list[i] != h.as_str() {
+                return None;
+            }
+        }
+        Some(list[head.len()..].to_vec())
+    }
+}
+
+impl KeyLayout for DelimitedKeyLayout {
+    fn group_key(&self, appid: &str, group: &str) -> String {
+        let mut list = self.head();
+        list.push(self.escape(appid));
+        list.push(self.escape(group));
+        list.join(self.delimiter.to_string().as_str())
+    }
+
+    fn parse_group_key(&self, key: &str) -> Option<(String, String)> {
+        let list = self.strip_head(key)?;
+        if list.len() != 2 {
+            return None;
+        }
+        Some((self.unescape(list[0])?, self.unescape(list[1])?))
+    }
+
+    fn chunk_key(&self, group_key: &str, timestamp: i64, index: usize) -> String {
+        format!(
+            "{}{}{}{}{}",
+            group_key, self.delimiter, timestamp, self.delimiter, index
+        )
+    }
+
+    fn parse_chunk_key(&self, key: &str) -> Option<ChunkKey> {
+        let list = self.strip_head(key)?;
+        if list.len() != 4 {
+            return None;
+        }
+        Some(ChunkKey {
+            appid: self.unescape(list[0])?,
+            group: self.unescape(list[1])?,
+            timestamp: list[2].parse().ok()?,
+            index: list[3].parse().ok()?,
+        })
+    }
+
+    fn group_pattern(&self, appid: &str) -> String {
+        let mut list = self
+            .head()
+            .into_iter()
+            .map(|x| Self::escape_glob(x.as_str()))
+            .collect::<Vec<_>>();
+        list.push(Self::escape_glob(self.escape(appid).as_str()));
+        list.push("*".to_string());
+        list.join(self.delimiter.to_string().as_str())
+    }
+
+    fn usage_key(&self, appid: &str) -> String {
+        let mut list = self.head();
+        list.push(self.escape(appid));
+        list.join(self.delimiter.to_string().as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_layout::{ChunkKey, DelimitedKeyLayout, KeyLayout};
+
+    #[test]
+    fn test_default_layout_compatible() {
+        let layout = DelimitedKeyLayout::default();
+        let key = layout.group_key("biz02", "user001");
+        assert_eq!(key, "SFP_biz02_user001");
+        let chunk = layout.chunk_key(key.as_str(), 1704798000, 0);
+        assert_eq!(chunk, "SFP_biz02_user001_1704798000_0");
+        assert_eq!(layout.usage_key("biz02"), "SFP_biz02");
+        let layout = layout.set_namespace("prod");
+        assert_eq!(layout.usage_key("biz02"), "SFP_prod_biz02");
+    }
+
+    #[test]
+    fn test_layout_round_trip() {
+        let layout = DelimitedKeyLayout::new("X_Y")
+            .set_namespace("prod")
+            .set_escape(true);
+        for (appid, group) in [
+            ("biz_02", "user_001"),
+            ("biz%5F", "_"),
+            ("", "__%%"),
+            ("应用", "组*?[]"),
+        ] {
+            let key = layout.group_key(appid, group);
+            assert_eq!(
+                layout.parse_group_key(key.as_str()),
+                Some((appid.to_string(), group.to_string()))
+            );
+            let chunk = layout.chunk_key(key.as_str(), 1704798000, 3);
+            assert_eq!(layout.parse_group_key(chunk.as_str()), None);
+            assert_eq!(
+                layout.parse_chunk_key(chunk.as_str()),
+                Some(ChunkKey {
+                    appid: appid.to_string(),
+                    group: group.to_string(),
+                    timestamp: 1704798000,
+                    index: 3,
+                })
+            );
+        }
+        let other = DelimitedKeyLayout::default().set_escape(true);
+        let key = other.group_key("biz02", "user001");
+        assert_eq!(layout.parse_group_key(key.as_str()), None);
+    }
+
+    #[test]
+    fn test_unescaped_layout() {
+        let layout = DelimitedKeyLayout::default();
+        let key = layout.group_key("biz_02", "user001");
+        assert_eq!(key, format!("SFP_{}_{}", "biz_02", "user001"));
+        let chunk = layout.chunk_key(key.as_str(), 1704798000, 1);
+        assert_eq!(chunk, "SFP_biz_02_user001_1704798000_1");
+        assert_eq!(layout.group_pattern("biz_02"), "SFP_biz_02_*");
+
+        let key = layout.group_key("biz02", "user001");
+        assert_eq!(
+            layout.parse_group_key(key.as_str()),
+            Some(("biz02".to_string(), "user001".to_string()))
+        );
+        let chunk = layout.chunk_key(key.as_str(), 1704798000, 1);
+        assert_eq!(layout.parse_chunk_key(chunk.as_str()).unwrap().index, 1);
+        assert_eq!(
+            layout.parse_group_key("SFP_biz%5F02_user001").unwrap().0,
+            "biz%5F02"
+        );
+    }
+
+    #[test]
+    fn test_group_pattern() {
+        let layout = DelimitedKeyLayout::default()
+            .set_namespace("ns")
+            .set_escape(true);
+        assert_eq!(layout.group_pattern("biz_*"), "SFP_ns_biz%5F\\*_*");
+    }
+
+    #[test]
+    fn test_set_delimiter() {
+        let layout = DelimitedKeyLayout::default().set_delimiter(':').unwrap();
+        assert_eq!(layout.group_key("biz02", "user001"), "SFP:biz02:user001");
+        for c in ['%', '0', '9', 'a', 'F'] {
+            assert!(DelimitedKeyLayout::default().set_delimiter(c).is_err());
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::future::Future;
+use std::time::Instant;
+use tracing::Instrument;
+
+// 监控指标，注册在prometheus默认registry中，未开启metrics特性时所有方法为空实现
+#[cfg(feature = "metrics")]
+mod imp {
+    use prometheus::{
+        register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec,
+    };
+    use std::sync::LazyLock;
+
+    const LATENCY_BUCKETS: &[f64] = &[
+        0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+    ];
+
+    pub static POOL_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "sgflt_pool_requests_total",
+            "FiltersPool calls",
+            &["appid", "operation", "backend", "status"]
+        )
+        .unwrap()
+    });
+    pub static POOL_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+        register_histogram_vec!(
+            "sgflt_pool_duration_seconds",
+            "FiltersPool call latency",
+            &["appid", "operation", "backend"],
+            LATENCY_BUCKETS.to_vec()
+        )
+        .unwrap()
+    });
+    pub static POOL_KEYS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "sgflt_pool_keys_total",
+            "keys checked by contain, by result (hit/miss)",
+            &["appid", "backend", "result"]
+        )
+        .unwrap()
+    });
+    pub static GROUP_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "sgflt_group_events_total",
+            "FilterGroup events: extend, chunk_full_retry, try_max_exhausted",
+            &["appid", "backend", "event"]
+        )
+        .unwrap()
+    });
+    pub static BACKEND_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "sgflt_backend_requests_total",
+            "Bitmap/FiltersInfo backend calls",
+            &["appid", "backend", "operation", "status"]
+        )
+        .unwrap()
+    });
+    pub static BACKEND_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+        register_histogram_vec!(
+            "sgflt_backend_duration_seconds",
+            "Bitmap/FiltersInfo backend call latency",
+            &["appid", "backend", "operation"],
+            LATENCY_BUCKETS.to_vec()
+        )
+        .unwrap()
+    });
+}
+
+// 后端不感知appid，由observe_pool在调用期间设置，FiltersPool之外的后端调用appid为空
+tokio::task_local! {
+    static CURRENT_APPID: String;
+}
+
+fn status<T>(result: &anyhow::Result<T>) -> &'static str {
+    if result.is_ok() {
+        "ok"
+    } else {
+        "error"
+    }
+}
+
+// 记录一次FiltersPool调用，每次调用对应一个tracing span
+pub(crate) async fn observe_pool<T, F: Future<Output = anyhow::Result<T>>>(
+    appid: &str,
+    backend: &str,
+    operation: &str,
+    group: &str,
+    keys: usize,
+    fut: F,
+) -> anyhow::Result<T> {
+    let span = tracing::info_span!("pool", appid, backend, operation, group, keys);
+    let start = Instant::now();
+    let result = CURRENT_APPID
+        .scope(appid.to_string(), fut.instrument(span))
+        .await;
+    #[cfg(feature = "metrics")]
+    {
+        imp::POOL_LATENCY
+            .with_label_values(&[appid, operation, backend])
+            .observe(start.elapsed().as_secs_f64());
+        imp::POOL_REQUESTS
+            .with_label_values(&[appid, operation, backend, status(&result)])
+            .inc();
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (appid, backend, operation, start, status(&result));
+    result
+}
+
+// 记录一次后端调用，每次调用对应一个tracing span
+pub(crate) async fn observe_backend<T, F: Future<Output = anyhow::Result<T>>>(
+    backend: &str,
+    operation: &str,
+    key: &str,
+    fut: F,
+) -> anyhow::Result<T> {
+    let span = tracing::debug_span!("backend", backend, operation, key);
+    let start = Instant::now();
+    let result = fut.instrument(span).await;
+    #[cfg(feature = "metrics")]
+    {
+        let appid = CURRENT_APPID.try_with(|x| x.clone()).unwrap_or_default();
+        imp::BACKEND_LATENCY
+            .with_label_values(&[appid.as_str(), backend, operation])
+            .observe(start.elapsed().as_secs_f64());
+        imp::BACKEND_REQUESTS
+            .with_label_values(&[appid.as_str(), backend, operation, status(&result)])
+            .inc();
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (backend, operation, start, status(&result));
+    result
+}
+
+// 命中率 = hit / (hit + miss)
+pub(crate) fn record_contain(appid: &str, backend: &str, result: &[bool]) {
+    #[cfg(feature = "metrics")]
+    {
+        let hit = result.iter().filter(|x| **x).count() as u64;
+        let miss = result.len() as u64 - hit;
+        imp::POOL_KEYS
+            .with_label_values(&[appid, backend, "hit"])
+            .inc_by(hit);
+        imp::POOL_KEYS
+            .with_label_values(&[appid, backend, "miss"])
+            .inc_by(miss);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (appid, backend, result);
+}
+
+pub(crate) fn record_group_event(appid: &str, backend: &str, event: &str) {
+    #[cfg(feature = "metrics")]
+    imp::GROUP_EVENTS
+        .with_label_values(&[appid, backend, event])
+        .inc();
+    #[cfg(not(feature = "metrics"))]
+    let _ = (appid, backend, event);
+}
+
+// prometheus文本格式输出默认registry中的全部指标
+#[cfg(feature = "metrics")]
+pub fn metrics_text() -> anyhow::Result<String> {
+    use prometheus::{Encoder, TextEncoder};
+    let mut buf = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod test {
+    use crate::metrics::{metrics_text, observe_backend, observe_pool, record_contain};
+
+    #[tokio::test]
+    async fn test_metrics_text() {
+        observe_backend("test", "bitmap.get", "k", async { Ok(()) })
+            .await
+            .unwrap();
+        // pool调用期间的后端调用带上appid
+        observe_pool("biz_metrics", "test", "contain", "g", 1, async {
+            observe_backend("test", "bitmap.get", "k", async { Ok(()) }).await
+        })
+        .await
+        .unwrap();
+        record_contain("biz_metrics", "test", &[true, false, false]);
+        let text = metrics_text().unwrap();
+        assert!(text.contains(
+            r#"sgflt_backend_requests_total{appid="",backend="test",operation="bitmap.get",status="ok"} 1"#
+        ));
+        assert!(text.contains(
+            r#"sgflt_backend_requests_total{appid="biz_metrics",backend="test",operation="bitmap.get",status="ok"} 1"#
+        ));
+        assert!(text.contains(
+            r#"sgflt_pool_keys_total{appid="biz_metrics",backend="test",result="miss"} 2"#
+        ));
+    }
+}
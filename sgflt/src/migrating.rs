@@ -0,0 +1,459 @@
+use crate::snapshot::bitmap_offsets;
+use crate::{
+    shard_keys, AppUsage, Bitmap, ChunkRegistration, ChunkSpec, FilterKind, FiltersInfo, KeyLayout,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 切换标记保存在新后端的FiltersInfo中，key为 {MIGRATION_STATE_KEY}:{appid}
+pub const MIGRATION_STATE_KEY: &str = "sgflt:migration";
+const SWITCHED_FIELD: &str = "switched";
+const DEFAULT_STATE_REFRESH: Duration = Duration::from_secs(1);
+
+// 在两个后端之间在线迁移：写入同时落到新旧两个后端，读取在切换之前走旧后端
+// 通过 backfill 把旧后端的数据补齐到新后端，完成后 switch_over 写入切换标记
+// 其他进程的Migration按refresh间隔读取标记，看到之后切换读取，切换后不再回退
+#[derive(Clone)]
+pub struct Migration {
+    old_info: Arc<dyn FiltersInfo>,
+    old_bitmap: Arc<dyn Bitmap>,
+    new_info: Arc<dyn FiltersInfo>,
+    new_bitmap: Arc<dyn Bitmap>,
+    switched: Arc<AtomicBool>,
+    state_key: String,
+    refresh: Duration,
+    checked_at: Arc<Mutex<Option<Instant>>>,
+    // 旧后端中没有登记构建参数的chunk使用的参数
+    fallback: ChunkSpec,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BackfillReport {
+    pub groups: usize,
+    pub chunks: usize,
+    pub bits: usize,
+    pub count: usize,
+}
+
+impl Migration {
+    pub fn new(
+        old_info: Arc<dyn FiltersInfo>,
+        old_bitmap: Arc<dyn Bitmap>,
+        new_info: Arc<dyn FiltersInfo>,
+        new_bitmap: Arc<dyn Bitmap>,
+    ) -> Self {
+        Self {
+            old_info,
+            old_bitmap,
+            new_info,
+            new_bitmap,
+            switched: Arc::new(AtomicBool::new(false)),
+            state_key: MIGRATION_STATE_KEY.to_string(),
+            refresh: DEFAULT_STATE_REFRESH,
+            checked_at: Arc::new(Mutex::new(None)),
+            fallback: ChunkSpec::default(),
+        }
+    }
+    // 切换标记按appid区分，同一个新后端上可以有多个appid在迁移
+    pub fn set_appid(mut self, appid: &str) -> Self {
+        self.state_key = format!("{}:{}", MIGRATION_STATE_KEY, appid);
+        self
+    }
+    // 读取切换标记的间隔
+    pub fn set_state_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    // 强制以切换后的状态启动，不读取标记
+    pub fn set_switched(self, switched: bool) -> Self {
+        self.switched.store(switched, Ordering::SeqCst);
+        self
+    }
+    // 旧后端中没有登记构建参数的chunk按这个分片数复制，与 BloomExpandStrategy::set_shards 一致
+    pub fn set_shards(mut self, shards: usize) -> Self {
+        self.fallback.shards = shards.max(1);
+        self
+    }
+    // 旧后端中没有登记构建参数的chunk在新后端登记的过滤器类型，与 BloomExpandStrategy::set_filter 一致
+    pub fn set_filter(mut self, filter: FilterKind) -> Self {
+        self.fallback.filter = filter;
+        self
+    }
+    // 本进程当前的切换状态，不访问后端
+    pub fn is_switched(&self) -> bool {
+        self.switched.load(Ordering::SeqCst)
+    }
+    // 读取后端中的切换标记
+    pub async fn load_state(&self) -> anyhow::Result<bool> {
+        let count = self
+            .new_info
+            .count(self.state_key.as_str(), SWITCHED_FIELD)
+            .await?;
+        Ok(count > 0)
+    }
+    // 写入切换标记，所有进程在refresh间隔内切换读取
+    pub async fn switch_over(&self) -> anyhow::Result<()> {
+        if !self.load_state().await? {
+            self.new_info
+                .add(self.state_key.as_str(), SWITCHED_FIELD, 1)
+                .await?;
+        }
+        self.switched.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+    // 读路径使用，未切换时按间隔刷新标记，读取失败时保持当前状态
+    async fn switched(&self) -> bool {
+        if self.is_switched() {
+            return true;
+        }
+        let due = {
+            let mut checked = self.checked_at.lock().unwrap();
+            match *checked {
+                Some(t) if t.elapsed() < self.refresh => false,
+                _ => {
+                    *checked = Some(Instant::now());
+                    true
+                }
+            }
+        };
+        if due {
+            match self.load_state().await {
+                Ok(true) => self.switched.store(true, Ordering::SeqCst),
+                Ok(false) => {}
+                Err(e) => wd_log::log_field("error", e)
+                    .field("key", self.state_key.as_str())
+                    .warn("Migration: load switch state failed"),
+            }
+        }
+        self.is_switched()
+    }
+
+    pub fn bitmap(&self) -> MigratingBitmap {
+        MigratingBitmap {
+            migration: self.clone(),
+        }
+    }
+    pub fn filter_info(&self) -> MigratingFiltersInfo {
+        MigratingFiltersInfo {
+            migration: self.clone(),
+        }
+    }
+
+    // 补齐appid下所有group
+    pub async fn backfill(
+        &self,
+        layout: &dyn KeyLayout,
+        appid: &str,
+    ) -> anyhow::Result<BackfillReport> {
+        let groups = self.old_info.groups(layout, appid).await?;
+        self.backfill_groups(layout, appid, groups.as_slice()).await
+    }
+    pub async fn backfill_groups(
+        &self,
+        layout: &dyn KeyLayout,
+        appid: &str,
+        groups: &[String],
+    ) -> anyhow::Result<BackfillReport> {
+        let mut report = BackfillReport::default();
+        let usage_key = layout.usage_key(appid);
+        for group in groups.iter() {
+            let group_key = layout.group_key(appid, group.as_str());
+            self.backfill_group_key(usage_key.as_str(), group_key.as_str(), &mut report)
+                .await?;
+            report.groups += 1;
+        }
+        Ok(report)
+    }
+
+    async fn backfill_group_key(
+        &self,
+        usage_key: &str,
+        group_key: &str,
+        report: &mut BackfillReport,
+    ) -> anyhow::Result<()> {
+        let chunks = self.old_info.list(group_key).await?;
+        let specs = self.old_info.specs(group_key).await?;
+        // 迁移期间双写计数会在新后端创建chunk记录，但不会登记参数，这样的chunk也需要登记
+        let registered = self.new_info.specs(group_key).await?;
+        for (code, old_count) in chunks.into_iter() {
+            // bitmap按位或合并，重复执行不会产生影响
+            let spec = specs.get(&code).copied().unwrap_or(self.fallback);
+            let mut size = 0;
+            for key in shard_keys(code.as_str(), spec.shards) {
+                let bytes = self.old_bitmap.mul_get(key.as_str()).await?;
+                size += bytes.len() as u64;
+                let offsets = bitmap_offsets(bytes.as_slice());
+                report.bits += offsets.len();
+                if !offsets.is_empty() {
+                    self.new_bitmap.mul_set(key.as_str(), offsets).await?;
+                }
+            }
+            // 迁移之前创建的chunk在新后端登记，用量按旧后端中bitmap的实际字节数计算
+            if !registered.contains_key(&code) {
+                let chunk = ChunkRegistration {
+                    usage_key: usage_key.to_string(),
+                    capacity: 0,
+                    bytes: size,
+                    spec,
+                };
+                self.new_info
+                    .register(group_key, code.as_str(), &chunk)
+                    .await?;
+            }
+            // 迁移期间的写入已经双写，只补齐差值
+            let new_count = self.new_info.count(group_key, code.as_str()).await?;
+            if old_count > new_count {
+                self.new_info
+                    .add(group_key, code.as_str(), old_count - new_count)
+                    .await?;
+                report.count += old_count - new_count;
+            }
+            report.chunks += 1;
+        }
+        Ok(())
+    }
+}
+
+pub struct MigratingBitmap {
+    migration: Migration,
+}
+
+#[async_trait::async_trait]
+impl Bitmap for MigratingBitmap {
+    async fn set(&self, key: &str, offset: usize, value: bool) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_bitmap.set(key, offset, value).await?;
+        m.new_bitmap.set(key, offset, value).await
+    }
+
+    async fn get(&self, key: &str, offset: usize) -> anyhow::Result<bool> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_bitmap.get(key, offset).await
+        } else {
+            m.old_bitmap.get(key, offset).await
+        }
+    }
+
+    async fn get_bits(&self, key: &str, offsets: &[usize]) -> anyhow::Result<Vec<bool>> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_bitmap.get_bits(key, offsets).await
+        } else {
+            m.old_bitmap.get_bits(key, offsets).await
+        }
+    }
+
+    async fn mul_set(&self, key: &str, list: HashSet<usize>) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_bitmap.mul_set(key, list.clone()).await?;
+        m.new_bitmap.mul_set(key, list).await
+    }
+
+    async fn mul_get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_bitmap.mul_get(key).await
+        } else {
+            m.old_bitmap.mul_get(key).await
+        }
+    }
+
+    fn backend(&self) -> &'static str {
+        "migrating"
+    }
+
+    async fn count_ones(&self, key: &str) -> anyhow::Result<usize> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_bitmap.count_ones(key).await
+        } else {
+            m.old_bitmap.count_ones(key).await
+        }
+    }
+
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_bitmap.del(key).await?;
+        m.new_bitmap.del(key).await
+    }
+
+    async fn put_bytes(&self, key: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_bitmap.put_bytes(key, bytes.clone()).await?;
+        m.new_bitmap.put_bytes(key, bytes).await
+    }
+}
+
+pub struct MigratingFiltersInfo {
+    migration: Migration,
+}
+
+#[async_trait::async_trait]
+impl FiltersInfo for MigratingFiltersInfo {
+    async fn list(&self, group: &str) -> anyhow::Result<Vec<(String, usize)>> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_info.list(group).await
+        } else {
+            m.old_info.list(group).await
+        }
+    }
+
+    async fn count(&self, group: &str, key: &str) -> anyhow::Result<usize> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_info.count(group, key).await
+        } else {
+            m.old_info.count(group, key).await
+        }
+    }
+
+    async fn add(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_info.add(group, key, count).await?;
+        m.new_info.add(group, key, count).await
+    }
+
+    async fn set_count(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_info.set_count(group, key, count).await?;
+        m.new_info.set_count(group, key, count).await
+    }
+
+    async fn register(
+        &self,
+        group: &str,
+        key: &str,
+        chunk: &ChunkRegistration,
+    ) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_info.register(group, key, chunk).await?;
+        m.new_info.register(group, key, chunk).await
+    }
+
+    async fn specs(&self, group: &str) -> anyhow::Result<HashMap<String, ChunkSpec>> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_info.specs(group).await
+        } else {
+            m.old_info.specs(group).await
+        }
+    }
+
+    async fn usage(&self, usage_key: &str) -> anyhow::Result<AppUsage> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_info.usage(usage_key).await
+        } else {
+            m.old_info.usage(usage_key).await
+        }
+    }
+
+    async fn remove(&self, usage_key: &str, group: &str) -> anyhow::Result<()> {
+        let m = &self.migration;
+        m.old_info.remove(usage_key, group).await?;
+        m.new_info.remove(usage_key, group).await
+    }
+
+    async fn groups(&self, layout: &dyn KeyLayout, appid: &str) -> anyhow::Result<Vec<String>> {
+        let m = &self.migration;
+        if m.switched().await {
+            m.new_info.groups(layout, appid).await
+        } else {
+            m.old_info.groups(layout, appid).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        BitmapFile, BloomExpandStrategy, DelimitedKeyLayout, FilterInfoFile, FiltersPool,
+        Migration, Strategy,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_migration() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        let keys = (0..30).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        let (before, after) = keys.split_at(15);
+
+        // 迁移前写入旧后端
+        let strategy = BloomExpandStrategy::build_from_file("biz02", old.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        let pool = FiltersPool::from(strategy);
+        pool.batch_insert("user001", before.to_vec()).await.unwrap();
+
+        let migration = Migration::new(
+            Arc::new(FilterInfoFile::new(old.path()).unwrap()),
+            Arc::new(BitmapFile::new(old.path()).unwrap()),
+            Arc::new(FilterInfoFile::new(new.path()).unwrap()),
+            Arc::new(BitmapFile::new(new.path()).unwrap()),
+        )
+        .set_appid("biz02");
+        let strategy = BloomExpandStrategy::new(
+            "biz02".into(),
+            migration.filter_info(),
+            Strategy::Fixed(10),
+            migration.bitmap(),
+            0.001,
+            3600,
+        );
+        let pool = FiltersPool::from(strategy);
+        // 迁移期间双写
+        pool.batch_insert("user001", after.to_vec()).await.unwrap();
+
+        let report = migration
+            .backfill(&DelimitedKeyLayout::default(), "biz02")
+            .await
+            .unwrap();
+        assert_eq!(report.groups, 1);
+        assert_eq!(report.chunks, 3);
+        // 另一个进程的Migration通过后端中的标记切换
+        let other = Migration::new(
+            Arc::new(FilterInfoFile::new(old.path()).unwrap()),
+            Arc::new(BitmapFile::new(old.path()).unwrap()),
+            Arc::new(FilterInfoFile::new(new.path()).unwrap()),
+            Arc::new(BitmapFile::new(new.path()).unwrap()),
+        )
+        .set_appid("biz02")
+        .set_state_refresh(Duration::ZERO);
+        assert!(!other.switched().await);
+        migration.switch_over().await.unwrap();
+        assert!(migration.is_switched());
+        assert!(other.switched().await);
+        assert!(!Migration::new(
+            Arc::new(FilterInfoFile::new(old.path()).unwrap()),
+            Arc::new(BitmapFile::new(old.path()).unwrap()),
+            Arc::new(FilterInfoFile::new(new.path()).unwrap()),
+            Arc::new(BitmapFile::new(new.path()).unwrap()),
+        )
+        .set_appid("biz03")
+        .load_state()
+        .await
+        .unwrap());
+
+        let result = pool.batch_contain("user001", keys.clone()).await.unwrap();
+        assert!(result.into_iter().all(|x| x));
+
+        // 新后端独立可用，且计数完整
+        let strategy = BloomExpandStrategy::build_from_file("biz02", new.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        let pool = FiltersPool::from(strategy);
+        let result = pool.batch_contain("user001", keys).await.unwrap();
+        assert!(result.into_iter().all(|x| x));
+        let info = FilterInfoFile::new(new.path()).unwrap();
+        let list = crate::FiltersInfo::list(&info, "SFP_biz02_user001")
+            .await
+            .unwrap();
+        assert_eq!(list.iter().map(|x| x.1).sum::<usize>(), 30);
+    }
+}
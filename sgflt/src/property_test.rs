@@ -0,0 +1,299 @@
+// 基于proptest的性质测试，只使用进程内后端
+// 1. 不漏判：插入成功的key在扩容、重新加载、并发批量插入之后都必须命中
+// 2. 误判率：未插入的key的命中率不超过各chunk目标误判率之和的合理上界
+use crate::{
+    BitmapMemory, BloomExpandStrategy, DefaultPoolImpl, FilterInfoMemory, FilterKind, FiltersPool,
+    ProbeOrder,
+};
+use proptest::prelude::*;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+const APPID: &str = "prop";
+const GROUP: &str = "user001";
+const KEY_SPACE: u32 = 2000;
+
+#[derive(Debug, Clone)]
+enum Expand {
+    Fixed(usize),
+    // 最后追加一个足够大的梯度，避免梯度用尽导致插入失败
+    Ladder(Vec<usize>),
+    // 以base为起点按下标翻倍
+    Function(usize),
+}
+
+impl Expand {
+    fn strategy(&self) -> crate::Strategy {
+        match self {
+            Expand::Fixed(n) => crate::Strategy::Fixed(*n),
+            Expand::Ladder(list) => {
+                let mut list = list.clone();
+                list.push(100_000);
+                crate::Strategy::Ladder(list)
+            }
+            Expand::Function(base) => {
+                let base = *base;
+                crate::Strategy::Function(Box::new(move |i| base << i.min(8)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Setting {
+    expand: Expand,
+    filter: FilterKind,
+    shards: usize,
+    probe_order: ProbeOrder,
+    fetch_window: usize,
+    fp_rate: f64,
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(u32),
+    BatchInsert(Vec<u32>),
+    Contain(u32),
+    BatchContain(Vec<u32>),
+    // 使用同一份存储重新创建策略和pool，相当于进程重启
+    Reload,
+    // 多个任务同时对同一个group批量插入
+    ConcurrentInsert(Vec<Vec<u32>>),
+}
+
+// chunk容量在 [min, max) 之间
+fn expand(min: usize, max: usize) -> impl Strategy<Value = Expand> {
+    prop_oneof![
+        (min..max).prop_map(Expand::Fixed),
+        prop::collection::vec(min..max, 1..5).prop_map(Expand::Ladder),
+        (min..max / 2).prop_map(Expand::Function),
+    ]
+}
+
+fn setting(min: usize, max: usize) -> impl Strategy<Value = Setting> {
+    (
+        expand(min, max),
+        prop_oneof![Just(FilterKind::Basic), Just(FilterKind::Blocked)],
+        prop_oneof![Just(1usize), Just(3), Just(4)],
+        prop_oneof![Just(ProbeOrder::OldestFirst), Just(ProbeOrder::NewestFirst)],
+        1usize..4,
+        prop_oneof![Just(0.001), Just(0.01), Just(0.05)],
+    )
+        .prop_map(
+            |(expand, filter, shards, probe_order, fetch_window, fp_rate)| Setting {
+                expand,
+                filter,
+                // 分块过滤器不支持分片
+                shards: if filter == FilterKind::Blocked {
+                    1
+                } else {
+                    shards
+                },
+                probe_order,
+                fetch_window,
+                fp_rate,
+            },
+        )
+}
+
+fn keys(max: usize) -> impl Strategy<Value = Vec<u32>> {
+    prop::collection::vec(0..KEY_SPACE, 1..max)
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (0..KEY_SPACE).prop_map(Op::Insert),
+        4 => keys(80).prop_map(Op::BatchInsert),
+        3 => (0..KEY_SPACE).prop_map(Op::Contain),
+        3 => keys(80).prop_map(Op::BatchContain),
+        1 => Just(Op::Reload),
+        2 => prop::collection::vec(keys(60), 2..5).prop_map(Op::ConcurrentInsert),
+    ]
+}
+
+fn key(i: u32) -> String {
+    format!("key_{}", i)
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+// 存储在多次reload之间共享
+struct Harness {
+    setting: Setting,
+    info: FilterInfoMemory,
+    bitmap: BitmapMemory,
+    pool: Arc<FiltersPool>,
+}
+
+impl Harness {
+    fn new(setting: Setting) -> Self {
+        let info = FilterInfoMemory::new();
+        let bitmap = BitmapMemory::new();
+        let pool = Self::pool(&setting, &info, &bitmap);
+        Self {
+            setting,
+            info,
+            bitmap,
+            pool,
+        }
+    }
+    fn pool(setting: &Setting, info: &FilterInfoMemory, bitmap: &BitmapMemory) -> Arc<FiltersPool> {
+        let strategy = BloomExpandStrategy::new(
+            APPID.to_string(),
+            info.clone(),
+            setting.expand.strategy(),
+            bitmap.clone(),
+            setting.fp_rate,
+            60 * 60,
+        )
+        .set_filter(setting.filter)
+        .set_shards(setting.shards);
+        let pool = DefaultPoolImpl::new(strategy)
+            .set_probe_order(setting.probe_order)
+            .set_fetch_window(setting.fetch_window);
+        Arc::new(FiltersPool::new(pool))
+    }
+    fn reload(&mut self) {
+        self.pool = Self::pool(&self.setting, &self.info, &self.bitmap);
+    }
+
+    async fn check_all(&self, inserted: &HashSet<u32>) -> Result<(), TestCaseError> {
+        let list = inserted.iter().copied().collect::<Vec<_>>();
+        let result = self
+            .pool
+            .batch_contain(GROUP, list.iter().copied().map(key).collect())
+            .await
+            .map_err(|e| TestCaseError::fail(e.to_string()))?;
+        for (i, hit) in list.iter().zip(result) {
+            prop_assert!(hit, "false negative on batch_contain: {}", key(*i));
+        }
+        Ok(())
+    }
+}
+
+async fn run(setting: Setting, ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let mut h = Harness::new(setting);
+    let mut inserted = HashSet::new();
+    let fail = |e: anyhow::Error| TestCaseError::fail(e.to_string());
+    for op in ops {
+        match op {
+            Op::Insert(i) => {
+                h.pool.insert(GROUP, key(i)).await.map_err(fail)?;
+                inserted.insert(i);
+            }
+            Op::BatchInsert(list) => {
+                let keys = list.iter().copied().map(key).collect();
+                h.pool.batch_insert(GROUP, keys).await.map_err(fail)?;
+                inserted.extend(list);
+            }
+            Op::Contain(i) => {
+                let hit = h.pool.contain(GROUP, key(i)).await.map_err(fail)?;
+                prop_assert!(
+                    hit || !inserted.contains(&i),
+                    "false negative on contain: {}",
+                    key(i)
+                );
+            }
+            Op::BatchContain(list) => {
+                let keys = list.iter().copied().map(key).collect();
+                let result = h.pool.batch_contain(GROUP, keys).await.map_err(fail)?;
+                for (i, hit) in list.iter().zip(result) {
+                    prop_assert!(
+                        hit || !inserted.contains(i),
+                        "false negative on batch_contain: {}",
+                        key(*i)
+                    );
+                }
+            }
+            Op::Reload => {
+                h.reload();
+                h.check_all(&inserted).await?;
+            }
+            Op::ConcurrentInsert(batches) => {
+                let tasks = batches
+                    .iter()
+                    .map(|list| {
+                        let pool = h.pool.clone();
+                        let keys = list.iter().copied().map(key).collect();
+                        tokio::spawn(async move { pool.batch_insert(GROUP, keys).await })
+                    })
+                    .collect::<Vec<_>>();
+                for task in tasks {
+                    task.await.unwrap().map_err(fail)?;
+                }
+                inserted.extend(batches.into_iter().flatten());
+            }
+        }
+    }
+    h.check_all(&inserted).await?;
+    h.reload();
+    h.check_all(&inserted).await
+}
+
+// 顺序插入时每个chunk不超过容量，组的误判率上界为 1-Π(1-p)，近似为 chunks*p
+// 允许1.5倍再加上抽样误差，覆盖m取整和很小的chunk的偏差
+async fn fp_rate(setting: Setting, count: usize, batch: usize) -> Result<(), TestCaseError> {
+    let h = Harness::new(setting.clone());
+    let fail = |e: anyhow::Error| TestCaseError::fail(e.to_string());
+    let inserted = (0..count).map(|i| format!("in_{}", i)).collect::<Vec<_>>();
+    for list in inserted.chunks(batch) {
+        h.pool
+            .batch_insert(GROUP, list.to_vec())
+            .await
+            .map_err(fail)?;
+    }
+    let result = h
+        .pool
+        .batch_contain(GROUP, inserted.clone())
+        .await
+        .map_err(fail)?;
+    prop_assert!(result.into_iter().all(|x| x));
+
+    let probes = 20_000;
+    let absent = (0..probes).map(|i| format!("out_{}", i)).collect();
+    let hits = h
+        .pool
+        .batch_contain(GROUP, absent)
+        .await
+        .map_err(fail)?
+        .into_iter()
+        .filter(|x| *x)
+        .count();
+    let stats = h.pool.stats(GROUP).await.map_err(fail)?;
+    let expected = 1.0 - (1.0 - setting.fp_rate).powi(stats.chunks.len() as i32);
+    let bound = 1.5 * expected + 4.0 * (expected / probes as f64).sqrt();
+    let observed = hits as f64 / probes as f64;
+    prop_assert!(
+        observed <= bound,
+        "fp rate {} > bound {} (chunks {})",
+        observed,
+        bound,
+        stats.chunks.len()
+    );
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn prop_no_false_negative(setting in setting(4, 64), ops in prop::collection::vec(op(), 1..30)) {
+        runtime().block_on(run(setting, ops))?;
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    // chunk容量与其他性质相同从4开始，m很小和分片中key分布不均的情况都要满足误判率
+    #[test]
+    fn prop_fp_rate(setting in setting(4, 1000), count in 10usize..1000, batch in 10usize..300) {
+        runtime().block_on(fp_rate(setting, count, batch))?;
+    }
+}
@@ -0,0 +1,320 @@
+use crate::SgfitErr;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+// 超出的配额类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaKind {
+    Groups,
+    ChunksPerGroup,
+    BitmapBytes,
+    OpsPerSec,
+}
+
+impl QuotaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaKind::Groups => "max_groups",
+            QuotaKind::ChunksPerGroup => "max_chunks_per_group",
+            QuotaKind::BitmapBytes => "max_bitmap_bytes",
+            QuotaKind::OpsPerSec => "ops_per_sec",
+        }
+    }
+}
+
+impl Display for QuotaKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// appid的配额，None表示不限制
+// 组数和bitmap字节数在创建chunk时按后端中的数据统计，ops按key数计算
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub max_groups: Option<usize>,
+    pub max_chunks_per_group: Option<usize>,
+    pub max_bitmap_bytes: Option<u64>,
+    pub ops_per_sec: Option<f64>,
+    // 令牌桶容量，默认等于ops_per_sec
+    pub burst: Option<f64>,
+}
+
+impl QuotaConfig {
+    // other中设置的字段覆盖当前值
+    pub fn merge(&self, other: &QuotaConfig) -> QuotaConfig {
+        QuotaConfig {
+            max_groups: other.max_groups.or(self.max_groups),
+            max_chunks_per_group: other.max_chunks_per_group.or(self.max_chunks_per_group),
+            max_bitmap_bytes: other.max_bitmap_bytes.or(self.max_bitmap_bytes),
+            ops_per_sec: other.ops_per_sec.or(self.ops_per_sec),
+            burst: other.burst.or(self.burst),
+        }
+    }
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_groups == Some(0)
+            || self.max_chunks_per_group == Some(0)
+            || self.max_bitmap_bytes == Some(0)
+        {
+            return Err(anyhow::anyhow!("quota limits must be > 0"));
+        }
+        if matches!(self.ops_per_sec, Some(r) if r.is_nan() || r <= 0.0) {
+            return Err(anyhow::anyhow!("ops_per_sec must be > 0"));
+        }
+        if matches!(self.burst, Some(b) if b.is_nan() || b < 1.0) {
+            return Err(anyhow::anyhow!("burst must be >= 1"));
+        }
+        Ok(())
+    }
+}
+
+// 令牌桶，桶满时允许一次取出超过容量的令牌，之后需要等欠下的令牌补齐
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+    pub fn try_acquire(&self, n: f64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let tokens =
+            (state.0 + now.duration_since(state.1).as_secs_f64() * self.rate).min(self.burst);
+        *state = (tokens, now);
+        if tokens < n.min(self.burst) {
+            return false;
+        }
+        state.0 -= n;
+        true
+    }
+}
+
+// 配额和限流状态，每个strategy持有一份，reload时原地更新
+#[derive(Default)]
+pub struct Quota {
+    config: RwLock<QuotaConfig>,
+    bucket: RwLock<Option<TokenBucket>>,
+}
+
+impl Quota {
+    pub fn new(config: QuotaConfig) -> Self {
+        Self {
+            bucket: RwLock::new(Self::bucket(&config)),
+            config: RwLock::new(config),
+        }
+    }
+    fn bucket(config: &QuotaConfig) -> Option<TokenBucket> {
+        config
+            .ops_per_sec
+            .map(|rate| TokenBucket::new(rate, config.burst.unwrap_or(rate).max(1.0)))
+    }
+    pub fn config(&self) -> QuotaConfig {
+        self.config.read().unwrap().clone()
+    }
+    // 替换配额，限流参数不变时保留令牌桶中的令牌
+    pub fn update(&self, config: QuotaConfig) {
+        let mut current = self.config.write().unwrap();
+        if current.ops_per_sec != config.ops_per_sec || current.burst != config.burst {
+            *self.bucket.write().unwrap() = Self::bucket(&config);
+        }
+        *current = config;
+    }
+    // n为本次操作的key数
+    pub fn acquire(&self, n: usize) -> anyhow::Result<()> {
+        match *self.bucket.read().unwrap() {
+            Some(ref bucket) if !bucket.try_acquire(n as f64) => {
+                Err(SgfitErr::new_quota_exceeded(QuotaKind::OpsPerSec, bucket.rate as u64).into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        BloomExpandStrategy, DelimitedKeyLayout, FiltersPool, Quota, QuotaConfig, QuotaKind,
+        SgfitErr, TokenBucket,
+    };
+    use std::time::Duration;
+
+    fn quota_kind(e: anyhow::Error) -> Option<QuotaKind> {
+        match e.downcast_ref::<SgfitErr>() {
+            Some(SgfitErr::QuotaExceeded(kind, _)) => Some(*kind),
+            _ => None,
+        }
+    }
+
+    fn pool(quota: QuotaConfig) -> FiltersPool {
+        let strategy = BloomExpandStrategy::build_from_memory("biz02")
+            .set_strategy_fixed(10)
+            .set_quota(quota);
+        FiltersPool::from(strategy)
+    }
+
+    fn keys(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("key_{}", i)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_quota_expand_chunk() {
+        let pool_chunks = pool(QuotaConfig {
+            max_chunks_per_group: Some(2),
+            ..Default::default()
+        });
+        pool_chunks.batch_insert("user001", keys(20)).await.unwrap();
+        let err = pool_chunks
+            .insert("user001", "more".into())
+            .await
+            .unwrap_err();
+        assert_eq!(quota_kind(err), Some(QuotaKind::ChunksPerGroup));
+
+        let pool_groups = pool(QuotaConfig {
+            max_groups: Some(1),
+            ..Default::default()
+        });
+        pool_groups.batch_insert("user001", keys(15)).await.unwrap();
+        let err = pool_groups.insert("user002", "a".into()).await.unwrap_err();
+        assert_eq!(quota_kind(err), Some(QuotaKind::Groups));
+        // 读取不存在的group不会创建chunk
+        assert!(!pool_groups.contain("user003", "a".into()).await.unwrap());
+
+        // fixed 10, fp 0.001 每个chunk 18字节
+        let pool_bytes = pool(QuotaConfig {
+            max_bitmap_bytes: Some(40),
+            ..Default::default()
+        });
+        pool_bytes.batch_insert("user001", keys(20)).await.unwrap();
+        let err = pool_bytes.insert("user002", "a".into()).await.unwrap_err();
+        assert_eq!(quota_kind(err), Some(QuotaKind::BitmapBytes));
+    }
+
+    #[tokio::test]
+    async fn test_quota_usage() {
+        // 配额按FiltersInfo中的用量计数检查，不遍历group
+        let (strategy, faults) = crate::mock_strategy("biz02");
+        faults.fail_always("info.groups");
+        let strategy = strategy.set_strategy_fixed(10).set_quota(QuotaConfig {
+            max_groups: Some(2),
+            max_bitmap_bytes: Some(40),
+            ..Default::default()
+        });
+        let admin = BloomExpandStrategy::from_backend(
+            "biz02".into(),
+            strategy.filter_info(),
+            strategy.bitmap(),
+        );
+        let pool = FiltersPool::from(strategy);
+        pool.insert("user001", "a".into()).await.unwrap();
+        pool.insert("user002", "a".into()).await.unwrap();
+        let err = pool.insert("user003", "a".into()).await.unwrap_err();
+        assert_eq!(quota_kind(err), Some(QuotaKind::Groups));
+        assert_eq!(faults.calls("info.groups"), 0);
+
+        // 删除group后用量随之减少
+        admin.drop_group("user001").await.unwrap();
+        assert_eq!(admin.bitmap_bytes().await.unwrap(), 18);
+        pool.insert("user003", "a".into()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_contain_keeps_usage() {
+        // 查询不存在的group不登记chunk，也不占用配额
+        let strategy = BloomExpandStrategy::build_from_memory("biz02").set_strategy_fixed(10);
+        let admin = BloomExpandStrategy::from_backend(
+            "biz02".into(),
+            strategy.filter_info(),
+            strategy.bitmap(),
+        );
+        let pool = FiltersPool::from(strategy);
+        pool.insert("user001", "a".into()).await.unwrap();
+        let usage = admin.usage().await.unwrap();
+        assert_eq!(usage.groups, 1);
+
+        assert!(!pool.contain("never_written", "a".into()).await.unwrap());
+        let res = pool.batch_contain("never_written", keys(3)).await.unwrap();
+        assert_eq!(res, vec![false; 3]);
+        assert_eq!(admin.usage().await.unwrap(), usage);
+        assert_eq!(admin.groups().await.unwrap(), vec!["user001".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_quota_namespace() {
+        // 同一个后端中不同命名空间下的同名appid分别计数
+        let base = BloomExpandStrategy::build_from_memory("biz02");
+        let strategy = |ns: &str| {
+            BloomExpandStrategy::from_backend("biz02".into(), base.filter_info(), base.bitmap())
+                .set_key_layout(DelimitedKeyLayout::default().set_namespace(ns))
+                .set_strategy_fixed(10)
+                .set_quota(QuotaConfig {
+                    max_groups: Some(1),
+                    ..Default::default()
+                })
+        };
+        let (prod, test) = (strategy("prod"), strategy("test"));
+        assert_ne!(prod.usage_key(), test.usage_key());
+        let prod = FiltersPool::from(prod);
+        let test = FiltersPool::from(test);
+        prod.insert("user001", "a".into()).await.unwrap();
+        test.insert("user001", "a".into()).await.unwrap();
+        let err = test.insert("user002", "a".into()).await.unwrap_err();
+        assert_eq!(quota_kind(err), Some(QuotaKind::Groups));
+        assert_eq!(base.usage().await.unwrap().groups, 0);
+    }
+
+    #[tokio::test]
+    async fn test_quota_rate_limit() {
+        let pool = pool(QuotaConfig {
+            ops_per_sec: Some(10.0),
+            ..Default::default()
+        });
+        pool.batch_insert("user001", keys(10)).await.unwrap();
+        let err = pool.contain("user001", "key_1".into()).await.unwrap_err();
+        assert_eq!(quota_kind(err), Some(QuotaKind::OpsPerSec));
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(pool.contain("user001", "key_1".into()).await.unwrap());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(100.0, 10.0);
+        assert!(bucket.try_acquire(6.0));
+        assert!(!bucket.try_acquire(6.0));
+        assert!(bucket.try_acquire(4.0));
+        std::thread::sleep(Duration::from_millis(120));
+        // 桶满时允许超过容量，之后欠账
+        assert!(bucket.try_acquire(20.0));
+        assert!(!bucket.try_acquire(1.0));
+    }
+
+    #[test]
+    fn test_quota_acquire() {
+        Quota::default().acquire(1_000_000).unwrap();
+        let quota = Quota::new(QuotaConfig {
+            ops_per_sec: Some(5.0),
+            ..Default::default()
+        });
+        quota.acquire(5).unwrap();
+        let err = quota.acquire(1).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SgfitErr>(),
+            Some(SgfitErr::QuotaExceeded(QuotaKind::OpsPerSec, 5))
+        ));
+        let invalid = QuotaConfig {
+            burst: Some(0.5),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,867 @@
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::cluster::{ClusterClient, ClusterClientBuilder};
+use redis::cluster_async::ClusterConnection;
+use redis::cluster_routing::{Route, RoutingInfo, SingleNodeRoutingInfo, SlotAddr};
+use redis::sentinel::SentinelServerType;
+use redis::{
+    AsyncCommands, Client, ClientTlsConfig, Cmd, ConnectionAddr, ConnectionInfo, ErrorKind,
+    FromRedisValue, IntoConnectionInfo, Pipeline, RedisConnectionInfo, RedisError, RedisFuture,
+    TlsCertificates, TlsMode, Value,
+};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+// redis的部署方式
+#[derive(Clone, Debug)]
+pub enum RedisMode {
+    Single(String),
+    Cluster(Vec<String>),
+    // 通过哨兵发现主节点，service_name为哨兵中配置的master名称
+    Sentinel {
+        nodes: Vec<String>,
+        service_name: String,
+    },
+}
+
+impl Default for RedisMode {
+    fn default() -> Self {
+        RedisMode::Single("redis://127.0.0.1/".into())
+    }
+}
+
+// tls证书配置，均为PEM格式的文件路径；url需要使用 rediss:// 协议
+#[derive(Clone, Debug, Default)]
+pub struct RedisTlsConfig {
+    pub root_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    // 不校验服务端证书
+    pub insecure: bool,
+}
+
+impl RedisTlsConfig {
+    fn certificates(&self) -> anyhow::Result<Option<TlsCertificates>> {
+        let root_cert = match self.root_cert {
+            Some(ref path) => Some(std::fs::read(path)?),
+            None => None,
+        };
+        let client_tls = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some(ClientTlsConfig {
+                client_cert: std::fs::read(cert)?,
+                client_key: std::fs::read(key)?,
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "RedisTlsConfig: client_cert and client_key must be set together"
+                ))
+            }
+        };
+        if root_cert.is_none() && client_tls.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(TlsCertificates {
+            client_tls,
+            root_cert,
+        }))
+    }
+    fn mode(&self) -> TlsMode {
+        if self.insecure {
+            TlsMode::Insecure
+        } else {
+            TlsMode::Secure
+        }
+    }
+}
+
+// 开启副本读之后的一致性要求
+#[derive(Clone, Copy, Debug)]
+pub enum ReadConsistency {
+    // 本进程在窗口期内写过的key从主节点读，其余从副本读
+    ReadYourWrites(Duration),
+    // 所有读都走副本，最终一致
+    Eventual,
+}
+
+impl Default for ReadConsistency {
+    fn default() -> Self {
+        ReadConsistency::ReadYourWrites(Duration::from_secs(1))
+    }
+}
+
+// 结构化的redis配置，url中的用户名密码和db会被这里的配置覆盖
+#[derive(Clone, Debug, Default)]
+pub struct RedisConfig {
+    pub mode: RedisMode,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub db: i64,
+    pub tls: Option<RedisTlsConfig>,
+    pub connect_timeout: Option<Duration>,
+    pub response_timeout: Option<Duration>,
+    pub read_from_replicas: bool,
+    pub read_consistency: ReadConsistency,
+}
+
+impl RedisConfig {
+    pub fn single<U: Into<String>>(url: U) -> Self {
+        Self {
+            mode: RedisMode::Single(url.into()),
+            ..Default::default()
+        }
+    }
+    pub fn cluster<U: Into<String>>(nodes: impl IntoIterator<Item = U>) -> Self {
+        Self {
+            mode: RedisMode::Cluster(nodes.into_iter().map(|x| x.into()).collect()),
+            ..Default::default()
+        }
+    }
+    pub fn sentinel<U: Into<String>, S: Into<String>>(
+        nodes: impl IntoIterator<Item = U>,
+        service_name: S,
+    ) -> Self {
+        Self {
+            mode: RedisMode::Sentinel {
+                nodes: nodes.into_iter().map(|x| x.into()).collect(),
+                service_name: service_name.into(),
+            },
+            ..Default::default()
+        }
+    }
+    pub fn set_username<U: Into<String>>(mut self, username: U) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+    pub fn set_password<P: Into<String>>(mut self, password: P) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+    pub fn set_db(mut self, db: i64) -> Self {
+        self.db = db;
+        self
+    }
+    pub fn set_tls(mut self, tls: RedisTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+    pub fn set_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+    pub fn set_response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = Some(timeout);
+        self
+    }
+    pub fn set_read_from_replicas(mut self, enable: bool) -> Self {
+        self.read_from_replicas = enable;
+        self
+    }
+    pub fn set_read_consistency(mut self, consistency: ReadConsistency) -> Self {
+        self.read_consistency = consistency;
+        self
+    }
+
+    // 将配置中的认证信息和db覆盖到url上
+    fn connection_info(&self, url: &str) -> anyhow::Result<ConnectionInfo> {
+        let mut info = url.into_connection_info()?;
+        self.apply_redis_info(&mut info.redis);
+        Ok(info)
+    }
+    fn apply_redis_info(&self, info: &mut RedisConnectionInfo) {
+        if self.username.is_some() {
+            info.username = self.username.clone();
+        }
+        if self.password.is_some() {
+            info.password = self.password.clone();
+        }
+        if self.db != 0 {
+            info.db = self.db;
+        }
+    }
+
+    fn build_single(&self, url: &str) -> anyhow::Result<Client> {
+        let info = self.connection_info(url)?;
+        let certs = match self.tls {
+            Some(ref tls) => tls.certificates()?,
+            None => None,
+        };
+        let client = match certs {
+            Some(certs) => {
+                if !matches!(info.addr, ConnectionAddr::TcpTls { .. }) {
+                    return Err(anyhow::anyhow!(
+                        "RedisConfig: tls requires a rediss:// url, got[{}]",
+                        url
+                    ));
+                }
+                Client::build_with_tls(info, certs)?
+            }
+            None => Client::open(info)?,
+        };
+        Ok(client)
+    }
+    fn build_cluster(&self, nodes: &[String], replica: bool) -> anyhow::Result<ClusterClient> {
+        if self.db != 0 {
+            return Err(anyhow::anyhow!(
+                "RedisConfig: cluster mode only supports db 0"
+            ));
+        }
+        let mut infos = Vec::with_capacity(nodes.len());
+        for i in nodes.iter() {
+            infos.push(self.connection_info(i.as_str())?);
+        }
+        let mut builder = ClusterClientBuilder::new(infos);
+        if let Some(ref username) = self.username {
+            builder = builder.username(username.clone());
+        }
+        if let Some(ref password) = self.password {
+            builder = builder.password(password.clone());
+        }
+        if let Some(ref tls) = self.tls {
+            builder = builder.tls(tls.mode());
+            if let Some(certs) = tls.certificates()? {
+                builder = builder.certs(certs);
+            }
+        }
+        if replica {
+            builder = builder.read_from_replicas();
+        }
+        Ok(builder.build()?)
+    }
+    fn build_sentinel(
+        &self,
+        nodes: &[String],
+        service_name: &str,
+        server_type: SentinelServerType,
+    ) -> anyhow::Result<Arc<SentinelNode>> {
+        let certs = match self.tls {
+            Some(ref tls) => tls.certificates()?,
+            None => None,
+        };
+        // 哨兵节点使用url中的认证信息，证书与数据节点共用
+        let mut sentinels = Vec::with_capacity(nodes.len());
+        for url in nodes.iter() {
+            let info = url.as_str().into_connection_info()?;
+            let client = match (&certs, &info.addr) {
+                (Some(certs), ConnectionAddr::TcpTls { .. }) => {
+                    Client::build_with_tls(info, certs.clone())?
+                }
+                _ => Client::open(info)?,
+            };
+            sentinels.push(client);
+        }
+        if sentinels.is_empty() {
+            return Err(anyhow::anyhow!(
+                "RedisConfig: at least one sentinel is required"
+            ));
+        }
+        // 数据节点使用配置中的认证信息和db
+        let mut redis_info = RedisConnectionInfo::default();
+        self.apply_redis_info(&mut redis_info);
+        Ok(Arc::new(SentinelNode {
+            sentinels,
+            service_name: service_name.to_string(),
+            server_type,
+            redis_info,
+            tls: self.tls.as_ref().map(|x| x.insecure),
+            certs,
+            cached: RwLock::new(None),
+            next_replica: AtomicUsize::new(0),
+        }))
+    }
+}
+
+// 哨兵模式的数据节点
+// 缓存哨兵发现的节点地址，连接失败或者节点变为只读时重新向哨兵查询
+pub struct SentinelNode {
+    sentinels: Vec<Client>,
+    service_name: String,
+    server_type: SentinelServerType,
+    redis_info: RedisConnectionInfo,
+    // None表示不使用tls，Some(insecure)
+    tls: Option<bool>,
+    certs: Option<TlsCertificates>,
+    cached: RwLock<Option<Client>>,
+    next_replica: AtomicUsize,
+}
+
+impl SentinelNode {
+    fn cached(&self) -> Option<Client> {
+        self.cached.read().unwrap().clone()
+    }
+    fn invalidate(&self) {
+        *self.cached.write().unwrap() = None;
+    }
+    // 依次询问哨兵，返回第一个成功的结果
+    async fn query_sentinels<T: FromRedisValue>(
+        &self,
+        cmd: &Cmd,
+        timeout: Option<Duration>,
+    ) -> redis::RedisResult<T> {
+        let mut last_err = None;
+        for client in self.sentinels.iter() {
+            let result = async {
+                let mut conn =
+                    with_timeout(timeout, client.get_multiplexed_async_connection()).await?;
+                with_timeout(timeout, cmd.query_async(&mut conn)).await
+            }
+            .await;
+            match result {
+                Ok(t) => return Ok(t),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+    async fn discover(&self, timeout: Option<Duration>) -> redis::RedisResult<(String, u16)> {
+        match self.server_type {
+            SentinelServerType::Master => {
+                let mut cmd = redis::cmd("SENTINEL");
+                cmd.arg("get-master-addr-by-name").arg(&self.service_name);
+                let addr: Option<(String, u16)> = self.query_sentinels(&cmd, timeout).await?;
+                addr.ok_or_else(|| {
+                    RedisError::from((
+                        ErrorKind::MasterNameNotFoundBySentinel,
+                        "sentinel: master not found",
+                        self.service_name.clone(),
+                    ))
+                })
+            }
+            SentinelServerType::Replica => {
+                let mut cmd = redis::cmd("SENTINEL");
+                cmd.arg("SLAVES").arg(&self.service_name);
+                let list: Vec<HashMap<String, String>> =
+                    self.query_sentinels(&cmd, timeout).await?;
+                let list = list
+                    .into_iter()
+                    .filter(|x| {
+                        let flags = x.get("flags").map(|s| s.as_str()).unwrap_or_default();
+                        !flags.contains("s_down")
+                            && !flags.contains("o_down")
+                            && !flags.contains("disconnected")
+                            && x.get("master-link-status").map(|s| s.as_str()) == Some("ok")
+                    })
+                    .filter_map(|x| Some((x.get("ip")?.clone(), x.get("port")?.parse().ok()?)))
+                    .collect::<Vec<(String, u16)>>();
+                if list.is_empty() {
+                    return Err(RedisError::from((
+                        ErrorKind::NoValidReplicasFoundBySentinel,
+                        "sentinel: no valid replica",
+                        self.service_name.clone(),
+                    )));
+                }
+                // 轮询副本
+                let i = self.next_replica.fetch_add(1, Ordering::Relaxed);
+                Ok(list[i % list.len()].clone())
+            }
+        }
+    }
+    fn build_client(&self, host: String, port: u16) -> redis::RedisResult<Client> {
+        let addr = match self.tls {
+            Some(insecure) => ConnectionAddr::TcpTls {
+                host,
+                port,
+                insecure,
+                tls_params: None,
+            },
+            None => ConnectionAddr::Tcp(host, port),
+        };
+        let info = ConnectionInfo {
+            addr,
+            redis: self.redis_info.clone(),
+        };
+        match (self.tls, &self.certs) {
+            (Some(_), Some(certs)) => Client::build_with_tls(info, certs.clone()),
+            _ => Client::open(info),
+        }
+    }
+    async fn connect(
+        &self,
+        timeout: Option<Duration>,
+    ) -> redis::RedisResult<MultiplexedConnection> {
+        if let Some(client) = self.cached() {
+            match with_timeout(timeout, client.get_multiplexed_async_connection()).await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => {
+                    wd_log::log_field("error", e)
+                        .warn("SentinelNode: cached node unavailable, ask sentinels again");
+                    self.invalidate();
+                }
+            }
+        }
+        let (host, port) = self.discover(timeout).await?;
+        let client = self.build_client(host, port)?;
+        let conn = with_timeout(timeout, client.get_multiplexed_async_connection()).await?;
+        *self.cached.write().unwrap() = Some(client);
+        Ok(conn)
+    }
+}
+
+#[derive(Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RedisNode {
+    CLUSTER(ClusterClient),
+    SINGLE(Client),
+    SENTINEL(Arc<SentinelNode>),
+}
+
+// 记录本进程写过的key，用于read-your-writes
+// order按写入时间排序，过期的记录从队头淘汰，重复写入的key在队列中会有多条记录
+struct WrittenKeys {
+    window: Duration,
+    keys: std::sync::Mutex<WrittenState>,
+}
+
+#[derive(Default)]
+struct WrittenState {
+    last: HashMap<String, Instant>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl WrittenKeys {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            keys: std::sync::Mutex::new(WrittenState::default()),
+        }
+    }
+    fn record(&self, key: &str) {
+        let now = Instant::now();
+        let mut state = self.keys.lock().unwrap();
+        while let Some((t, _)) = state.order.front() {
+            if now.duration_since(*t) < self.window {
+                break;
+            }
+            let (t, k) = state.order.pop_front().unwrap();
+            // 之后又写过的key保留
+            if state.last.get(&k) == Some(&t) {
+                state.last.remove(&k);
+            }
+        }
+        state.last.insert(key.to_string(), now);
+        state.order.push_back((now, key.to_string()));
+    }
+    fn recent(&self, key: &str) -> bool {
+        let state = self.keys.lock().unwrap();
+        match state.last.get(key) {
+            Some(t) => t.elapsed() < self.window,
+            None => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RedisClient {
+    node: RedisNode,
+    // 只读副本，为空时读写都走node
+    replica: Option<RedisNode>,
+    written: Option<Arc<WrittenKeys>>,
+    connect_timeout: Option<Duration>,
+    response_timeout: Option<Duration>,
+}
+
+impl From<RedisNode> for RedisClient {
+    fn from(node: RedisNode) -> Self {
+        Self {
+            node,
+            replica: None,
+            written: None,
+            connect_timeout: None,
+            response_timeout: None,
+        }
+    }
+}
+impl TryFrom<&str> for RedisClient {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let client = Client::open(value)?;
+        Ok(RedisNode::SINGLE(client).into())
+    }
+}
+impl TryFrom<Vec<String>> for RedisClient {
+    type Error = anyhow::Error;
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let cs = ClusterClient::new(value)?;
+        Ok(RedisNode::CLUSTER(cs).into())
+    }
+}
+impl TryFrom<RedisConfig> for RedisClient {
+    type Error = anyhow::Error;
+    fn try_from(cfg: RedisConfig) -> Result<Self, Self::Error> {
+        let (node, replica) = match cfg.mode {
+            RedisMode::Single(ref url) => {
+                (RedisNode::SINGLE(cfg.build_single(url.as_str())?), None)
+            }
+            RedisMode::Cluster(ref nodes) => {
+                let replica = if cfg.read_from_replicas {
+                    Some(RedisNode::CLUSTER(cfg.build_cluster(nodes, true)?))
+                } else {
+                    None
+                };
+                (
+                    RedisNode::CLUSTER(cfg.build_cluster(nodes, false)?),
+                    replica,
+                )
+            }
+            RedisMode::Sentinel {
+                ref nodes,
+                ref service_name,
+            } => {
+                let node = cfg.build_sentinel(nodes, service_name, SentinelServerType::Master)?;
+                let replica = if cfg.read_from_replicas {
+                    let replica =
+                        cfg.build_sentinel(nodes, service_name, SentinelServerType::Replica)?;
+                    Some(RedisNode::SENTINEL(replica))
+                } else {
+                    None
+                };
+                (RedisNode::SENTINEL(node), replica)
+            }
+        };
+        let written = match (&replica, cfg.read_consistency) {
+            (Some(_), ReadConsistency::ReadYourWrites(window)) => {
+                Some(Arc::new(WrittenKeys::new(window)))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            node,
+            replica,
+            written,
+            connect_timeout: cfg.connect_timeout,
+            response_timeout: cfg.response_timeout,
+        })
+    }
+}
+
+impl RedisClient {
+    pub fn node(&self) -> &RedisNode {
+        &self.node
+    }
+    pub fn is_cluster(&self) -> bool {
+        matches!(self.node, RedisNode::CLUSTER(_))
+    }
+
+    // 获取一个主节点连接，连接超时和响应超时都在这里生效
+    pub async fn get_async_connection(&self) -> anyhow::Result<RedisConnection> {
+        self.connect(&self.node).await
+    }
+    // 写连接，会记录写过的key
+    pub async fn get_write_connection(&self, key: &str) -> anyhow::Result<RedisConnection> {
+        if let Some(ref written) = self.written {
+            written.record(key);
+        }
+        self.connect(&self.node).await
+    }
+    // 读连接，根据一致性配置选择副本或者主节点，副本不可用时回退到主节点
+    pub async fn get_read_connection(&self, key: &str) -> anyhow::Result<RedisConnection> {
+        let replica = match self.replica {
+            Some(ref r) => r,
+            None => return self.connect(&self.node).await,
+        };
+        if let Some(ref written) = self.written {
+            if written.recent(key) {
+                return self.connect(&self.node).await;
+            }
+        }
+        match self.connect(replica).await {
+            Ok(conn) => Ok(conn),
+            Err(e) => {
+                wd_log::log_field("error", e)
+                    .warn("RedisClient: replica unavailable, read from primary");
+                self.connect(&self.node).await
+            }
+        }
+    }
+
+    // 扫描匹配pattern的所有key，集群模式下逐个主节点扫描
+    pub async fn scan_match(&self, pattern: &str) -> anyhow::Result<Vec<String>> {
+        let clu = match self.node {
+            RedisNode::CLUSTER(ref clu) => clu,
+            _ => {
+                let mut conn = self.get_read_connection(pattern).await?;
+                let mut iter = conn.scan_match::<_, String>(pattern).await?;
+                let mut keys = vec![];
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                return Ok(keys);
+            }
+        };
+        let mut conn = with_timeout(self.connect_timeout, clu.get_async_connection()).await?;
+        let mut keys = vec![];
+        for slot in cluster_master_slots(&mut conn).await? {
+            let route = RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(Route::new(
+                slot,
+                SlotAddr::Master,
+            )));
+            let mut cursor = 0u64;
+            loop {
+                let mut cmd = redis::cmd("SCAN");
+                cmd.arg(cursor)
+                    .arg("MATCH")
+                    .arg(pattern)
+                    .arg("COUNT")
+                    .arg(1000);
+                let value = with_timeout(
+                    self.response_timeout,
+                    conn.route_command(&cmd, route.clone()),
+                )
+                .await?;
+                let (next, list): (u64, Vec<String>) = FromRedisValue::from_redis_value(&value)?;
+                keys.extend(list);
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn connect(&self, node: &RedisNode) -> anyhow::Result<RedisConnection> {
+        let inner = match node {
+            RedisNode::CLUSTER(ref clu) => Connection::Cluster(
+                with_timeout(self.connect_timeout, clu.get_async_connection()).await?,
+            ),
+            RedisNode::SINGLE(ref sin) => Connection::Single(
+                with_timeout(self.connect_timeout, sin.get_async_connection()).await?,
+            ),
+            RedisNode::SENTINEL(ref sen) => {
+                Connection::Sentinel(sen.connect(self.connect_timeout).await?, sen.clone())
+            }
+        };
+        Ok(RedisConnection {
+            inner,
+            timeout: self.response_timeout,
+        })
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    fut: impl Future<Output = redis::RedisResult<T>>,
+) -> redis::RedisResult<T> {
+    match timeout {
+        Some(t) => match tokio::time::timeout(t, fut).await {
+            Ok(r) => r,
+            Err(_) => Err(RedisError::from((
+                ErrorKind::IoError,
+                "redis operation timeout",
+            ))),
+        },
+        None => fut.await,
+    }
+}
+
+// 通过 CLUSTER SLOTS 为每个主节点找到一个它负责的slot，用于把命令路由到该节点
+async fn cluster_master_slots(conn: &mut ClusterConnection) -> anyhow::Result<Vec<u16>> {
+    let value = conn
+        .route_command(
+            &redis::cmd("CLUSTER").arg("SLOTS").clone(),
+            RoutingInfo::SingleNode(SingleNodeRoutingInfo::Random),
+        )
+        .await?;
+    let ranges: Vec<Vec<Value>> = FromRedisValue::from_redis_value(&value)?;
+    let mut masters: HashMap<(String, u16), u16> = HashMap::new();
+    for range in ranges.iter() {
+        if range.len() < 3 {
+            continue;
+        }
+        let start: u16 = FromRedisValue::from_redis_value(&range[0])?;
+        let node: Vec<Value> = FromRedisValue::from_redis_value(&range[2])?;
+        if node.len() < 2 {
+            continue;
+        }
+        let host: String = FromRedisValue::from_redis_value(&node[0])?;
+        let port: u16 = FromRedisValue::from_redis_value(&node[1])?;
+        let slot = masters.entry((host, port)).or_insert(start);
+        *slot = (*slot).min(start);
+    }
+    let mut slots = masters.into_values().collect::<Vec<_>>();
+    slots.sort();
+    Ok(slots)
+}
+
+enum Connection {
+    Cluster(ClusterConnection),
+    Single(redis::aio::Connection),
+    // 命令出现连接错误或者只读错误时，丢弃缓存的节点地址
+    Sentinel(MultiplexedConnection, Arc<SentinelNode>),
+}
+
+async fn check_sentinel<T>(
+    node: Option<Arc<SentinelNode>>,
+    fut: impl Future<Output = redis::RedisResult<T>>,
+) -> redis::RedisResult<T> {
+    let result = fut.await;
+    if let (Some(node), Err(ref e)) = (node, &result) {
+        if e.is_io_error() || e.is_connection_dropped() || e.kind() == ErrorKind::ReadOnly {
+            node.invalidate();
+        }
+    }
+    result
+}
+
+// 对不同部署方式的连接做统一封装，可以直接使用 AsyncCommands
+pub struct RedisConnection {
+    inner: Connection,
+    timeout: Option<Duration>,
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let timeout = self.timeout;
+        let (fut, node) = match self.inner {
+            Connection::Cluster(ref mut c) => (c.req_packed_command(cmd), None),
+            Connection::Single(ref mut c) => (c.req_packed_command(cmd), None),
+            Connection::Sentinel(ref mut c, ref n) => (c.req_packed_command(cmd), Some(n.clone())),
+        };
+        Box::pin(check_sentinel(node, with_timeout(timeout, fut)))
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let timeout = self.timeout;
+        let (fut, node) = match self.inner {
+            Connection::Cluster(ref mut c) => (c.req_packed_commands(cmd, offset, count), None),
+            Connection::Single(ref mut c) => (c.req_packed_commands(cmd, offset, count), None),
+            Connection::Sentinel(ref mut c, ref n) => {
+                (c.req_packed_commands(cmd, offset, count), Some(n.clone()))
+            }
+        };
+        Box::pin(check_sentinel(node, with_timeout(timeout, fut)))
+    }
+
+    fn get_db(&self) -> i64 {
+        match self.inner {
+            Connection::Cluster(ref c) => c.get_db(),
+            Connection::Single(ref c) => c.get_db(),
+            Connection::Sentinel(ref c, _) => c.get_db(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WrittenKeys;
+    use crate::{ReadConsistency, RedisClient, RedisConfig, RedisNode, RedisTlsConfig};
+    use std::time::Duration;
+
+    // 自签名的测试证书，只用于构建客户端
+    const TEST_CA: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIBgjCCASegAwIBAgIUDS6x7JxsFkKbg4m+OuGdXFXKXwkwCgYIKoZIzj0EAwIw\n\
+FTETMBEGA1UEAwwKc2dmbHQtdGVzdDAgFw0yNjEwMTkwMzUxMTVaGA8yMTI2MDky\n\
+NTAzNTExNVowFTETMBEGA1UEAwwKc2dmbHQtdGVzdDBZMBMGByqGSM49AgEGCCqG\n\
+SM49AwEHA0IABBF+wYboTKoDrx0qeRm0AaE5WMwiGTtNeGlZ6KgCcbSml1HeT4Wo\n\
+JnPmYC0EzF5AOOfS8dcqDbzHUlQ9aOyTi6ejUzBRMB0GA1UdDgQWBBSJypctqO8y\n\
+c/ttNXiPpYB+OB47EDAfBgNVHSMEGDAWgBSJypctqO8yc/ttNXiPpYB+OB47EDAP\n\
+BgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0kAMEYCIQDWPIts/5joPZBcM4B2\n\
+vwNvaFj1pWPucQFqv/pqNFaNGgIhANfxIXXEX/yYfEkYZHtiNE8xy3RksThKDKgZ\n\
+hs4z45RV\n\
+-----END CERTIFICATE-----\n";
+
+    #[test]
+    fn test_redis_config_build() {
+        let cfg = RedisConfig::single("redis://127.0.0.1:6379/")
+            .set_username("sgflt")
+            .set_password("secret")
+            .set_db(3)
+            .set_connect_timeout(Duration::from_millis(200));
+        let client = RedisClient::try_from(cfg).unwrap();
+        match client.node() {
+            RedisNode::SINGLE(c) => {
+                let info = &c.get_connection_info().redis;
+                assert_eq!(info.db, 3);
+                assert_eq!(info.username.as_deref(), Some("sgflt"));
+                assert_eq!(info.password.as_deref(), Some("secret"));
+            }
+            _ => panic!("expect single node"),
+        }
+
+        let cfg = RedisConfig::cluster(["redis://127.0.0.1:7000/"]).set_db(1);
+        assert!(RedisClient::try_from(cfg).is_err());
+
+        let cfg = RedisConfig::sentinel(["redis://127.0.0.1:26379/"], "mymaster")
+            .set_tls(RedisTlsConfig::default());
+        let client = RedisClient::try_from(cfg).unwrap();
+        assert!(matches!(client.node(), RedisNode::SENTINEL(_)));
+
+        // 哨兵模式支持自定义证书，哨兵和数据节点共用
+        let dir = tempfile::tempdir().unwrap();
+        let ca = dir.path().join("ca.pem");
+        std::fs::write(&ca, TEST_CA).unwrap();
+        let cfg = RedisConfig::sentinel(["rediss://127.0.0.1:26379/"], "mymaster")
+            .set_password("secret")
+            .set_tls(RedisTlsConfig {
+                root_cert: Some(ca.to_string_lossy().to_string()),
+                ..Default::default()
+            });
+        let client = RedisClient::try_from(cfg).unwrap();
+        match client.node() {
+            RedisNode::SENTINEL(node) => {
+                assert!(node.certs.is_some());
+                assert_eq!(node.tls, Some(false));
+                assert_eq!(node.redis_info.password.as_deref(), Some("secret"));
+                let c = node.build_client("10.0.0.1".into(), 6379).unwrap();
+                assert!(matches!(
+                    c.get_connection_info().addr,
+                    redis::ConnectionAddr::TcpTls { ref host, port: 6379, .. } if host == "10.0.0.1"
+                ));
+                assert_eq!(
+                    c.get_connection_info().redis.password.as_deref(),
+                    Some("secret")
+                );
+            }
+            _ => panic!("expect sentinel node"),
+        }
+
+        let cfg = RedisConfig::single("redis://127.0.0.1:6379/").set_tls(RedisTlsConfig {
+            client_cert: Some("client.crt".into()),
+            ..Default::default()
+        });
+        assert!(RedisClient::try_from(cfg).is_err());
+    }
+
+    #[test]
+    fn test_read_write_routing() {
+        let cfg = RedisConfig::single("redis://127.0.0.1:6379/").set_read_from_replicas(true);
+        let client = RedisClient::try_from(cfg).unwrap();
+        assert!(client.replica.is_none());
+        assert!(client.written.is_none());
+
+        let cfg = RedisConfig::cluster(["redis://127.0.0.1:7000/"]).set_read_from_replicas(true);
+        let client = RedisClient::try_from(cfg).unwrap();
+        assert!(matches!(client.replica, Some(RedisNode::CLUSTER(_))));
+        let written = client.written.unwrap();
+        written.record("SFP_biz02_user001");
+        assert!(written.recent("SFP_biz02_user001"));
+        assert!(!written.recent("SFP_biz02_user002"));
+
+        // 过期的记录在下次写入时淘汰，之后重复写入的key不受影响
+        let written = WrittenKeys::new(Duration::from_millis(50));
+        written.record("a");
+        written.record("b");
+        std::thread::sleep(Duration::from_millis(60));
+        written.record("b");
+        written.record("c");
+        assert!(!written.recent("a"));
+        assert!(written.recent("b"));
+        let state = written.keys.lock().unwrap();
+        assert_eq!(state.last.len(), 2);
+        assert_eq!(state.order.len(), 2);
+
+        let cfg = RedisConfig::sentinel(["redis://127.0.0.1:26379/"], "mymaster")
+            .set_read_from_replicas(true)
+            .set_read_consistency(ReadConsistency::Eventual);
+        let client = RedisClient::try_from(cfg).unwrap();
+        assert!(matches!(client.replica, Some(RedisNode::SENTINEL(_))));
+        assert!(client.written.is_none());
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{BloomExpandStrategy, ChunkRegistration, ChunkSpec, FilterExpandStrategy, FilterKind};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+pub const SNAPSHOT_FORMAT: &str = "sgflt-snapshot";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// 快照文件为json lines格式，第一行为header，后续每行一个chunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub format: String,
+    pub version: u32,
+    pub appid: String,
+    pub created_at: i64,
+}
+
+// bitmap为chunk的原始字节(分片时按分片顺序拼接)，base64编码
+// hash种子来自group key和chunk code，所以恢复时key必须保持不变
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    pub group: String,
+    pub group_key: String,
+    pub code: String,
+    pub index: usize,
+    pub timestamp: i64,
+    pub capacity: usize,
+    pub m: usize,
+    pub k: u32,
+    pub fp_rate: f64,
+    pub count: usize,
+    // 旧版本快照没有分片数、过滤器类型和下标计算方式，按最初的未分片basic过滤器导入
+    #[serde(default = "default_shards")]
+    pub shards: usize,
+    #[serde(default)]
+    pub filter: FilterKind,
+    #[serde(default)]
+    pub layout: u32,
+    pub bitmap: String,
+}
+
+fn default_shards() -> usize {
+    1
+}
+
+impl SnapshotChunk {
+    pub fn bitmap_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(STANDARD.decode(self.bitmap.as_bytes())?)
+    }
+}
+
+pub(crate) fn bitmap_offsets(bytes: &[u8]) -> HashSet<usize> {
+    let mut set = HashSet::new();
+    for (i, b) in bytes.iter().enumerate() {
+        for j in 0..8 {
+            if b & (0x80 >> j) != 0 {
+                set.insert(i * 8 + j);
+            }
+        }
+    }
+    set
+}
+
+impl BloomExpandStrategy {
+    // 导出一个group，返回导出的chunk数
+    pub async fn export_group<W: Write>(&self, group: &str, w: &mut W) -> anyhow::Result<usize> {
+        self.export_groups(&[group.to_string()], w).await
+    }
+    // 导出整个appid
+    pub async fn export_app<W: Write>(&self, w: &mut W) -> anyhow::Result<usize> {
+        let groups = self.groups().await?;
+        self.export_groups(groups.as_slice(), w).await
+    }
+    pub async fn export_groups<W: Write>(
+        &self,
+        groups: &[String],
+        w: &mut W,
+    ) -> anyhow::Result<usize> {
+        let header = SnapshotHeader {
+            format: SNAPSHOT_FORMAT.to_string(),
+            version: SNAPSHOT_VERSION,
+            appid: self.appid().to_string(),
+            created_at: wd_tools::time::utc_timestamp(),
+        };
+        writeln!(w, "{}", serde_json::to_string(&header)?)?;
+        let info = self.filter_info();
+        let layout = self.key_layout();
+        let mut total = 0;
+        for group in groups.iter() {
+            let group_key = layout.group_key(self.appid(), group.as_str());
+            let chunks = self.load_filter_group(group.as_str()).await?;
+            let specs = info.specs(group_key.as_str()).await?;
+            for (i, chunk) in chunks.iter().enumerate() {
+                let meta = chunk.meta();
+                let (index, timestamp) = self.chunk_position(meta.code.as_str(), i);
+                let count = info.count(group_key.as_str(), meta.code.as_str()).await?;
+                let spec = self.stored_spec(&specs, meta.code.as_str());
+                // 分片chunk的bitmap按分片顺序拼接
+                let bytes = chunk.fetch_bitmap().await?;
+                let record = SnapshotChunk {
+                    group: group.clone(),
+                    group_key: group_key.clone(),
+                    code: meta.code,
+                    index,
+                    timestamp,
+                    capacity: meta.capacity,
+                    m: meta.m,
+                    k: meta.k,
+                    fp_rate: meta.fp_rate,
+                    count,
+                    shards: spec.shards,
+                    filter: spec.filter,
+                    layout: spec.layout,
+                    bitmap: STANDARD.encode(bytes),
+                };
+                writeln!(w, "{}", serde_json::to_string(&record)?)?;
+                total += 1;
+            }
+        }
+        w.flush()?;
+        Ok(total)
+    }
+
+    // 导入快照，覆盖目标chunk的bitmap和计数，重复导入结果不变；返回导入的chunk数
+    // 快照的appid、key命名方式和chunk参数必须和当前strategy一致
+    pub async fn import<R: BufRead>(&self, r: R) -> anyhow::Result<usize> {
+        let mut lines = r.lines();
+        let header = match lines.next() {
+            Some(line) => serde_json::from_str::<SnapshotHeader>(line?.as_str())?,
+            None => return Err(anyhow::anyhow!("snapshot: empty input")),
+        };
+        if header.format != SNAPSHOT_FORMAT || header.version != SNAPSHOT_VERSION {
+            return Err(anyhow::anyhow!(
+                "snapshot: unsupported format[{}] version[{}]",
+                header.format,
+                header.version
+            ));
+        }
+        if header.appid != self.appid() {
+            return Err(anyhow::anyhow!(
+                "snapshot: appid[{}] does not match strategy appid[{}]",
+                header.appid,
+                self.appid()
+            ));
+        }
+        let info = self.filter_info();
+        let layout = self.key_layout();
+        let mut total = 0;
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str::<SnapshotChunk>(line.as_str())?;
+            let group_key = layout.group_key(self.appid(), record.group.as_str());
+            if group_key != record.group_key {
+                return Err(anyhow::anyhow!(
+                    "snapshot: group key[{}] does not match key layout[{}]",
+                    record.group_key,
+                    group_key
+                ));
+            }
+            let spec = ChunkSpec {
+                shards: record.shards.max(1),
+                filter: record.filter,
+                layout: record.layout,
+            };
+            let chunk =
+                self.build_chunk(group_key.as_str(), record.code.clone(), record.index, spec)?;
+            let expect = chunk.meta();
+            if expect.m != record.m || expect.k != record.k {
+                return Err(anyhow::anyhow!(
+                    "snapshot: chunk[{}] m[{}] k[{}] does not match strategy m[{}] k[{}]",
+                    record.code,
+                    record.m,
+                    record.k,
+                    expect.m,
+                    expect.k
+                ));
+            }
+            // 分片chunk的字节按分片拆分写入
+            let registration = ChunkRegistration {
+                usage_key: self.usage_key(),
+                capacity: record.capacity,
+                bytes: expect.m.div_ceil(8) as u64,
+                spec,
+            };
+            info.register(group_key.as_str(), record.code.as_str(), &registration)
+                .await?;
+            chunk.store_bitmap(record.bitmap_bytes()?).await?;
+            info.set_count(group_key.as_str(), record.code.as_str(), record.count)
+                .await?;
+            total += 1;
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::snapshot::bitmap_offsets;
+    use crate::{BloomExpandStrategy, FiltersPool};
+    use std::collections::HashSet;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_bitmap_offsets() {
+        let set = bitmap_offsets(&[0b1000_0001, 0, 0b0100_0000]);
+        assert_eq!(set, HashSet::from([0, 7, 17]));
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let keys = (0..25).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+
+        let strategy = BloomExpandStrategy::build_from_file("biz02", src.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        let pool = FiltersPool::from(strategy);
+        pool.batch_insert("user001", keys.clone()).await.unwrap();
+        pool.batch_insert("user002", keys.clone()).await.unwrap();
+
+        let strategy = BloomExpandStrategy::build_from_file("biz02", src.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        let mut buf = vec![];
+        let total = strategy.export_app(&mut buf).await.unwrap();
+        assert_eq!(total, 6);
+
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dst.path())
+            .unwrap()
+            .set_strategy_fixed(10);
+        let total = strategy
+            .import(BufReader::new(buf.as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(total, 6);
+        let pool = FiltersPool::from(strategy);
+        for group in ["user001", "user002"] {
+            let result = pool.batch_contain(group, keys.clone()).await.unwrap();
+            assert!(result.into_iter().all(|x| x));
+        }
+
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dst.path())
+            .unwrap()
+            .set_strategy_fixed(20);
+        assert!(strategy
+            .import(BufReader::new(buf.as_slice()))
+            .await
+            .is_err());
+        let strategy = BloomExpandStrategy::build_from_file("biz03", dst.path()).unwrap();
+        assert!(strategy
+            .import(BufReader::new(buf.as_slice()))
+            .await
+            .is_err());
+    }
+}
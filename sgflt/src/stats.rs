@@ -0,0 +1,123 @@
+use crate::ChunkMeta;
+use serde::{Deserialize, Serialize};
+
+// chunk的运行状态，fp_rate为按照实际填充率估算的误判率，target_fp_rate为创建时的目标误判率
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkStats {
+    pub code: String,
+    pub count: usize,
+    pub capacity: usize,
+    pub m: usize,
+    pub k: u32,
+    pub bits: usize,
+    pub fill_ratio: f64,
+    pub estimated_items: f64,
+    pub fp_rate: f64,
+    pub target_fp_rate: f64,
+}
+
+impl ChunkStats {
+    pub fn new(meta: ChunkMeta, count: usize, bits: usize) -> Self {
+        let m = meta.m.max(1) as f64;
+        let fill_ratio = (bits as f64 / m).min(1.0);
+        // 误判率 = 填充率^k
+        let fp_rate = fill_ratio.powi(meta.k as i32);
+        // 基数估计 n = -(m/k) * ln(1 - X/m)，bit全满时无法估计
+        let estimated_items = if fill_ratio >= 1.0 || meta.k == 0 {
+            f64::INFINITY
+        } else {
+            -(m / meta.k as f64) * (1.0 - fill_ratio).ln()
+        };
+        Self {
+            code: meta.code,
+            count,
+            capacity: meta.capacity,
+            m: meta.m,
+            k: meta.k,
+            bits,
+            fill_ratio,
+            estimated_items,
+            fp_rate,
+            target_fp_rate: meta.fp_rate,
+        }
+    }
+}
+
+// group的运行状态，查询会依次检查每个chunk，所以group的误判率为 1 - ∏(1 - p_i)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupStats {
+    pub group: String,
+    pub chunks: Vec<ChunkStats>,
+    pub count: usize,
+    pub capacity: usize,
+    pub fp_rate: f64,
+}
+
+impl GroupStats {
+    pub fn new(group: String, chunks: Vec<ChunkStats>) -> Self {
+        let count = chunks.iter().map(|x| x.count).sum();
+        let capacity = chunks.iter().map(|x| x.capacity).sum();
+        let pass = chunks.iter().map(|x| 1.0 - x.fp_rate).product::<f64>();
+        Self {
+            group,
+            chunks,
+            count,
+            capacity,
+            fp_rate: 1.0 - pass,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BloomExpandStrategy, ChunkMeta, ChunkStats, FiltersPool, GroupStats};
+
+    #[test]
+    fn test_chunk_stats() {
+        let meta = ChunkMeta {
+            code: "c0".into(),
+            capacity: 100,
+            m: 1000,
+            k: 2,
+            fp_rate: 0.01,
+        };
+        let stats = ChunkStats::new(meta.clone(), 10, 500);
+        assert_eq!(stats.fill_ratio, 0.5);
+        assert_eq!(stats.fp_rate, 0.25);
+        assert!((stats.estimated_items - 500.0 * 2f64.ln()).abs() < 1e-9);
+        let stats = ChunkStats::new(meta, 0, 0);
+        assert_eq!(stats.fp_rate, 0.0);
+        assert_eq!(stats.estimated_items, 0.0);
+
+        let group = GroupStats::new("g".into(), vec![stats.clone(), stats]);
+        assert_eq!(group.capacity, 200);
+        assert_eq!(group.fp_rate, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_pool_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = BloomExpandStrategy::build_from_file("biz02", dir.path())
+            .unwrap()
+            .set_strategy_fixed(100)
+            .set_fp_rate(0.01);
+        let pool = FiltersPool::from(strategy);
+        let stats = pool.stats("user001").await.unwrap();
+        assert!(stats.chunks.is_empty());
+        assert_eq!(stats.fp_rate, 0.0);
+
+        let keys = (0..150).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys).await.unwrap();
+        let stats = pool.stats("user001").await.unwrap();
+        assert_eq!(stats.chunks.len(), 2);
+        assert_eq!(stats.count, 150);
+        assert_eq!(stats.capacity, 200);
+        let full = &stats.chunks[0];
+        assert_eq!(full.count, 100);
+        assert!(full.bits > 0 && full.bits <= full.m);
+        // 满载时的误判率接近目标误判率
+        assert!(full.fp_rate < full.target_fp_rate * 2.0);
+        assert!((full.estimated_items - 100.0).abs() < 15.0);
+        assert!(stats.fp_rate >= full.fp_rate);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{Bitmap, FiltersInfo, FiltersPool, Quota, ResolvedConfig, StrategyConfig};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+// 单个租户(appid)最终生效的配置和它独立的过滤器池
+pub struct Tenant {
+    config: ResolvedConfig,
+    pool: Arc<FiltersPool>,
+    info: Arc<dyn FiltersInfo>,
+    bitmap: Arc<dyn Bitmap>,
+    quota: Arc<Quota>,
+}
+
+impl Tenant {
+    pub fn new(config: ResolvedConfig) -> anyhow::Result<Self> {
+        let strategy = config.build()?;
+        let info = strategy.filter_info();
+        let bitmap = strategy.bitmap();
+        let quota = strategy.shared_quota();
+        let pool = Arc::new(FiltersPool::from(strategy));
+        Ok(Self {
+            config,
+            pool,
+            info,
+            bitmap,
+            quota,
+        })
+    }
+    pub fn appid(&self) -> &str {
+        self.config.appid.as_str()
+    }
+    pub fn config(&self) -> &ResolvedConfig {
+        &self.config
+    }
+    pub fn pool(&self) -> &FiltersPool {
+        &self.pool
+    }
+
+    // 按新配置得到租户，配额状态始终沿用，令牌桶不会重置
+    // 只有配额变化时沿用原来的pool，配额由reload成功后原地更新
+    // 其他字段变化时重建strategy和pool，后端不变则沿用原来的后端，memory://中的数据不会丢失
+    fn update(&self, config: ResolvedConfig) -> anyhow::Result<Self> {
+        let mut quota_only = self.config.clone();
+        quota_only.quota = config.quota.clone();
+        if quota_only == config {
+            return Ok(Self {
+                config,
+                pool: self.pool.clone(),
+                info: self.info.clone(),
+                bitmap: self.bitmap.clone(),
+                quota: self.quota.clone(),
+            });
+        }
+        let strategy = if self.config.same_backend(&config) {
+            config.build_on(self.info.clone(), self.bitmap.clone())?
+        } else {
+            config.build()?
+        };
+        let strategy = strategy.set_shared_quota(self.quota.clone());
+        Ok(Self {
+            info: strategy.filter_info(),
+            bitmap: strategy.bitmap(),
+            quota: self.quota.clone(),
+            pool: Arc::new(FiltersPool::from(strategy)),
+            config,
+        })
+    }
+}
+
+// 一次reload的变化，按appid排序
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+// appid到租户的映射，StrategyConfig.apps中的每个appid对应一个租户
+// 支持运行时整体替换配置，新增业务线不需要重启服务
+#[derive(Default)]
+pub struct TenantRegistry {
+    config: RwLock<StrategyConfig>,
+    tenants: RwLock<HashMap<String, Arc<Tenant>>>,
+    // 串行执行reload，避免并发reload互相覆盖
+    reload_lock: Mutex<()>,
+}
+
+impl TenantRegistry {
+    pub fn new(config: StrategyConfig) -> anyhow::Result<Self> {
+        let registry = Self::default();
+        registry.reload(config)?;
+        Ok(registry)
+    }
+
+    pub fn get(&self, appid: &str) -> Option<Arc<Tenant>> {
+        self.tenants.read().unwrap().get(appid).cloned()
+    }
+    pub fn appids(&self) -> Vec<String> {
+        let mut list = self
+            .tenants
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        list.sort();
+        list
+    }
+    pub fn config(&self) -> StrategyConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    // 先校验并构建所有租户，全部成功才替换，失败时保留旧配置
+    // 配置没有变化的租户沿用原来的实例，变化的租户见Tenant::update
+    pub fn reload(&self, config: StrategyConfig) -> anyhow::Result<ReloadReport> {
+        let _guard = self.reload_lock.lock().unwrap();
+        config.validate()?;
+        let current = self.tenants.read().unwrap().clone();
+        let mut tenants = HashMap::new();
+        let mut report = ReloadReport::default();
+        for appid in config.appids() {
+            let resolved = config.resolve(appid.as_str())?;
+            let tenant = match current.get(appid.as_str()) {
+                Some(t) if t.config == resolved => t.clone(),
+                Some(t) => {
+                    report.updated.push(appid.clone());
+                    Arc::new(t.update(resolved)?)
+                }
+                None => {
+                    report.added.push(appid.clone());
+                    Arc::new(Tenant::new(resolved)?)
+                }
+            };
+            tenants.insert(appid, tenant);
+        }
+        report.removed = current
+            .keys()
+            .filter(|k| !tenants.contains_key(k.as_str()))
+            .cloned()
+            .collect();
+        report.removed.sort();
+        for appid in report.updated.iter() {
+            let tenant = &tenants[appid];
+            tenant.quota.update(tenant.config.quota.clone());
+        }
+
+        *self.tenants.write().unwrap() = tenants;
+        *self.config.write().unwrap() = config;
+        Ok(report)
+    }
+
+    // 每隔interval调用一次load，配置发生变化时reload
+    // 加载或校验失败只记录日志，继续使用旧配置
+    pub fn watch<F>(self: Arc<Self>, interval: Duration, load: F) -> tokio::task::JoinHandle<()>
+    where
+        F: Fn() -> anyhow::Result<StrategyConfig> + Send + 'static,
+    {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let result = load().and_then(|config| {
+                    if config == self.config() {
+                        Ok(ReloadReport::default())
+                    } else {
+                        self.reload(config)
+                    }
+                });
+                match result {
+                    Ok(report) if report.is_empty() => {}
+                    Ok(report) => wd_log::log_field("added", format!("{:?}", report.added))
+                        .field("updated", format!("{:?}", report.updated))
+                        .field("removed", format!("{:?}", report.removed))
+                        .info("TenantRegistry: config reloaded"),
+                    Err(e) => wd_log::log_field("error", e).warn("TenantRegistry: reload failed"),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{StrategyConfig, TenantRegistry};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn config(s: &str) -> StrategyConfig {
+        StrategyConfig::from_toml(s).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tenant_registry() {
+        let registry = TenantRegistry::new(config(
+            "backend = \"memory://\"\n[apps.biz02]\n[apps.biz03]\nfp_rate = 0.01",
+        ))
+        .unwrap();
+        assert_eq!(registry.appids(), vec!["biz02", "biz03"]);
+        assert!(registry.get("biz04").is_none());
+
+        let biz02 = registry.get("biz02").unwrap();
+        biz02.pool().insert("user001", "a".into()).await.unwrap();
+        assert!(!registry
+            .get("biz03")
+            .unwrap()
+            .pool()
+            .contain("user001", "a".into())
+            .await
+            .unwrap());
+
+        // biz02未变化沿用原来的实例，biz03更新，biz04新增
+        let report = registry
+            .reload(config(
+                "backend = \"memory://\"\n[apps.biz02]\n[apps.biz03]\n[apps.biz04]",
+            ))
+            .unwrap();
+        assert_eq!(report.added, vec!["biz04"]);
+        assert_eq!(report.updated, vec!["biz03"]);
+        assert!(report.removed.is_empty());
+        assert!(Arc::ptr_eq(&biz02, &registry.get("biz02").unwrap()));
+        assert_eq!(registry.get("biz03").unwrap().config().fp_rate, 0.001);
+
+        // 校验失败不影响当前配置
+        let invalid = config("backend = \"memory://\"\nfp_rate = 2.0\n[apps.biz05]");
+        assert!(registry.reload(invalid).is_err());
+        assert_eq!(registry.appids(), vec!["biz02", "biz03", "biz04"]);
+
+        let report = registry
+            .reload(config("backend = \"memory://\"\n[apps.biz04]"))
+            .unwrap();
+        assert_eq!(report.removed, vec!["biz02", "biz03"]);
+        assert_eq!(registry.appids(), vec!["biz04"]);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_reload_keeps_state() {
+        let registry = TenantRegistry::new(config(
+            "backend = \"memory://\"\n[apps.biz02]\nquota = { ops_per_sec = 5.0 }",
+        ))
+        .unwrap();
+        let biz02 = registry.get("biz02").unwrap();
+        let keys = (0..5).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        biz02.pool().batch_insert("user001", keys).await.unwrap();
+
+        // 只修改配额：沿用原来的pool，令牌桶不重置
+        let report = registry
+            .reload(config(
+                "backend = \"memory://\"\n[apps.biz02]\nquota = { ops_per_sec = 5.0, max_groups = 10 }",
+            ))
+            .unwrap();
+        assert_eq!(report.updated, vec!["biz02"]);
+        let updated = registry.get("biz02").unwrap();
+        assert!(std::ptr::eq(biz02.pool(), updated.pool()));
+        assert_eq!(updated.config().quota.max_groups, Some(10));
+        assert!(updated.pool().contain("user001", "a".into()).await.is_err());
+
+        // 修改retention并取消限流：重建pool，memory://中的数据保留
+        // 阶梯扩容不能设置retention，改为与第一级相同大小的固定扩容
+        registry
+            .reload(config(
+                "backend = \"memory://\"\nretention = 86400\nstrategy = { kind = \"fixed\", size = 100 }\n[apps.biz02]",
+            ))
+            .unwrap();
+        let rebuilt = registry.get("biz02").unwrap();
+        assert!(!std::ptr::eq(biz02.pool(), rebuilt.pool()));
+        assert!(rebuilt
+            .pool()
+            .contain("user001", "key_1".into())
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_watch() {
+        let registry =
+            Arc::new(TenantRegistry::new(config("[apps.biz02]\nbackend = \"memory://\"")).unwrap());
+        let source = Arc::new(Mutex::new(registry.config()));
+        let loader = source.clone();
+        let handle = registry.clone().watch(Duration::from_millis(10), move || {
+            Ok(loader.lock().unwrap().clone())
+        });
+        *source.lock().unwrap() = config("backend = \"memory://\"\n[apps.biz02]\n[apps.biz03]");
+        for _ in 0..100 {
+            if registry.get("biz03").is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(registry.appids(), vec!["biz02", "biz03"]);
+        handle.abort();
+    }
+}
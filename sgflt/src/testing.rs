@@ -0,0 +1,527 @@
+// 测试工具：本地启动的redis-server(单节点和集群)，以及可编排延迟和故障的mock后端
+// crate内测试直接可用，其他crate通过 feature test-support 使用
+use crate::{
+    AppUsage, Bitmap, BitmapMemory, BloomExpandStrategy, ChunkRegistration, ChunkSpec,
+    FilterInfoMemory, FiltersInfo, KeyLayout, Strategy,
+};
+use std::collections::{HashMap, HashSet};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wd_tools::PFErr;
+
+// redis-server可执行文件路径，默认从PATH中查找
+pub const REDIS_SERVER_ENV: &str = "SGFLT_REDIS_SERVER";
+// 设置为1或true时无法启动redis-server视为测试失败，CI中保证依赖redis的测试真正执行
+pub const REQUIRE_REDIS_ENV: &str = "SGFLT_REQUIRE_REDIS";
+const CLUSTER_SLOTS: usize = 16384;
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 本地启动的redis-server，监听127.0.0.1的随机端口，不持久化，drop时关闭
+pub struct RedisServer {
+    port: u16,
+    child: Child,
+    _dir: tempfile::TempDir,
+}
+
+impl RedisServer {
+    pub fn start() -> anyhow::Result<Self> {
+        Self::spawn(&[])
+    }
+    // 找不到redis-server或启动失败时返回None，用于跳过依赖redis的测试
+    // 设置了 SGFLT_REQUIRE_REDIS 时直接panic
+    pub fn try_start() -> Option<Self> {
+        skip_or_fail(Self::start())
+    }
+
+    fn spawn(args: &[&str]) -> anyhow::Result<Self> {
+        let bin = std::env::var(REDIS_SERVER_ENV).unwrap_or_else(|_| "redis-server".into());
+        let dir = tempfile::tempdir()?;
+        let port = free_port()?;
+        let child = Command::new(bin.as_str())
+            .arg("--port")
+            .arg(port.to_string())
+            .args(["--bind", "127.0.0.1", "--save", "", "--appendonly", "no"])
+            .arg("--dir")
+            .arg(dir.path())
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("start {} failed: {}", bin, e))?;
+        let server = Self {
+            port,
+            child,
+            _dir: dir,
+        };
+        server.wait_ready()?;
+        Ok(server)
+    }
+    fn wait_ready(&self) -> anyhow::Result<()> {
+        let start = Instant::now();
+        loop {
+            let ping = self
+                .connection()
+                .and_then(|mut c| Ok(redis::cmd("PING").query::<String>(&mut c)?));
+            match ping {
+                Ok(_) => return Ok(()),
+                Err(e) if start.elapsed() > START_TIMEOUT => {
+                    return anyhow::anyhow!("redis-server on port {} not ready: {}", self.port, e)
+                        .err()
+                }
+                Err(_) => std::thread::sleep(Duration::from_millis(20)),
+            }
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    pub fn url(&self) -> String {
+        format!("redis://127.0.0.1:{}/", self.port)
+    }
+    pub fn connection(&self) -> anyhow::Result<redis::Connection> {
+        Ok(redis::Client::open(self.url())?.get_connection()?)
+    }
+}
+
+impl Drop for RedisServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// 只有主节点的本地redis集群，槽位平均分配
+pub struct RedisCluster {
+    nodes: Vec<RedisServer>,
+}
+
+impl RedisCluster {
+    pub fn start(masters: usize) -> anyhow::Result<Self> {
+        let masters = masters.max(1);
+        let mut nodes = Vec::with_capacity(masters);
+        for _ in 0..masters {
+            nodes.push(RedisServer::spawn(&[
+                "--cluster-enabled",
+                "yes",
+                "--cluster-node-timeout",
+                "2000",
+            ])?);
+        }
+        let per = CLUSTER_SLOTS.div_ceil(masters);
+        for (i, node) in nodes.iter().enumerate() {
+            let slots = (i * per..((i + 1) * per).min(CLUSTER_SLOTS)).collect::<Vec<_>>();
+            let mut con = node.connection()?;
+            redis::cmd("CLUSTER")
+                .arg("ADDSLOTS")
+                .arg(slots)
+                .query::<()>(&mut con)?;
+        }
+        let mut con = nodes[0].connection()?;
+        for node in nodes.iter().skip(1) {
+            redis::cmd("CLUSTER")
+                .arg("MEET")
+                .arg("127.0.0.1")
+                .arg(node.port())
+                .query::<()>(&mut con)?;
+        }
+        let cluster = Self { nodes };
+        cluster.wait_ready()?;
+        Ok(cluster)
+    }
+    pub fn try_start(masters: usize) -> Option<Self> {
+        skip_or_fail(Self::start(masters))
+    }
+    // 所有节点都认为集群可用，且看到全部节点
+    fn wait_ready(&self) -> anyhow::Result<()> {
+        let start = Instant::now();
+        loop {
+            let mut ready = true;
+            for node in self.nodes.iter() {
+                let mut con = node.connection()?;
+                let info = redis::cmd("CLUSTER")
+                    .arg("INFO")
+                    .query::<String>(&mut con)?;
+                let known = format!("cluster_known_nodes:{}", self.nodes.len());
+                ready &= info.contains("cluster_state:ok") && info.contains(known.as_str());
+            }
+            if ready {
+                return Ok(());
+            }
+            if start.elapsed() > START_TIMEOUT {
+                return anyhow::anyhow!("redis cluster not ready").err();
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    pub fn nodes(&self) -> &[RedisServer] {
+        self.nodes.as_slice()
+    }
+    pub fn node_urls(&self) -> Vec<String> {
+        self.nodes.iter().map(|n| n.url()).collect()
+    }
+    // 与 BloomExpandStrategy::build_from_url 一致的集群url
+    pub fn url(&self) -> String {
+        let hosts = self
+            .nodes
+            .iter()
+            .map(|n| format!("127.0.0.1:{}", n.port()))
+            .collect::<Vec<_>>();
+        format!("redis+cluster://{}", hosts.join(","))
+    }
+}
+
+pub fn redis_required() -> bool {
+    matches!(
+        std::env::var(REQUIRE_REDIS_ENV).as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+fn skip_or_fail<T>(result: anyhow::Result<T>) -> Option<T> {
+    match result {
+        Ok(s) => Some(s),
+        Err(e) if redis_required() => panic!(
+            "{} is set but redis is unavailable: {}",
+            REQUIRE_REDIS_ENV, e
+        ),
+        Err(_) => None,
+    }
+}
+
+// 集群总线端口为 port+10000，需要同时可用
+fn free_port() -> anyhow::Result<u16> {
+    for _ in 0..100 {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        if port < 55535 && std::net::TcpListener::bind(("127.0.0.1", port + 10000)).is_ok() {
+            return Ok(port);
+        }
+    }
+    anyhow::anyhow!("no free port").err()
+}
+
+#[derive(Default)]
+struct FaultState {
+    latency: HashMap<String, Duration>,
+    // 剩余的失败次数，usize::MAX表示一直失败
+    failures: HashMap<String, usize>,
+    calls: HashMap<String, usize>,
+    // 正在执行和历史最大的并发调用数
+    in_flight: HashMap<String, usize>,
+    peak: HashMap<String, usize>,
+}
+
+// mock后端的故障编排，clone之后共享
+// op为 bitmap.set bitmap.get bitmap.get_bits bitmap.mul_set bitmap.mul_get bitmap.count_ones bitmap.del
+// bitmap.put_bytes info.list info.count info.add info.set_count info.register info.specs info.usage info.groups
+// info.remove
+// "*" 匹配所有op
+#[derive(Clone, Default)]
+pub struct Faults {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl Faults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_latency(&self, op: &str, latency: Duration) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state.latency.insert(op.to_string(), latency);
+        self
+    }
+    // 接下来的times次调用返回错误
+    pub fn fail(&self, op: &str, times: usize) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state.failures.insert(op.to_string(), times);
+        self
+    }
+    pub fn fail_always(&self, op: &str) -> &Self {
+        self.fail(op, usize::MAX)
+    }
+    // 清除延迟和故障，保留调用计数
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.latency.clear();
+        state.failures.clear();
+    }
+    pub fn calls(&self, op: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        match op {
+            "*" => state.calls.values().sum(),
+            _ => state.calls.get(op).copied().unwrap_or(0),
+        }
+    }
+    // 延迟期间同时在途的最大调用数，用于验证并发度
+    pub fn peak(&self, op: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.peak.get(op).copied().unwrap_or(0)
+    }
+    pub fn reset_calls(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.calls.clear();
+        state.peak.clear();
+    }
+
+    async fn enter(&self, op: &str) -> anyhow::Result<()> {
+        let (latency, fail) = {
+            let mut state = self.state.lock().unwrap();
+            *state.calls.entry(op.to_string()).or_insert(0) += 1;
+            let n = state.in_flight.entry(op.to_string()).or_insert(0);
+            *n += 1;
+            let n = *n;
+            let peak = state.peak.entry(op.to_string()).or_insert(0);
+            *peak = n.max(*peak);
+            let latency = state
+                .latency
+                .get(op)
+                .or_else(|| state.latency.get("*"))
+                .copied();
+            let key = [op, "*"]
+                .into_iter()
+                .find(|k| state.failures.get(*k).is_some_and(|n| *n > 0));
+            let fail = match key {
+                Some(k) => {
+                    let n = state.failures.get_mut(k).unwrap();
+                    if *n != usize::MAX {
+                        *n -= 1;
+                    }
+                    true
+                }
+                None => false,
+            };
+            (latency, fail)
+        };
+        if let Some(d) = latency {
+            tokio::time::sleep(d).await;
+        }
+        if let Some(n) = self.state.lock().unwrap().in_flight.get_mut(op) {
+            *n -= 1;
+        }
+        if fail {
+            return anyhow::anyhow!("mock: injected fault on {}", op).err();
+        }
+        Ok(())
+    }
+}
+
+// 基于BitmapMemory，每次调用先经过Faults
+#[derive(Clone, Default)]
+pub struct MockBitmap {
+    inner: BitmapMemory,
+    faults: Faults,
+}
+
+impl MockBitmap {
+    pub fn new(faults: Faults) -> Self {
+        Self {
+            inner: BitmapMemory::new(),
+            faults,
+        }
+    }
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+}
+
+#[async_trait::async_trait]
+impl Bitmap for MockBitmap {
+    async fn set(&self, key: &str, offset: usize, value: bool) -> anyhow::Result<()> {
+        self.faults.enter("bitmap.set").await?;
+        self.inner.set(key, offset, value).await
+    }
+    async fn get(&self, key: &str, offset: usize) -> anyhow::Result<bool> {
+        self.faults.enter("bitmap.get").await?;
+        self.inner.get(key, offset).await
+    }
+    async fn mul_set(&self, key: &str, list: HashSet<usize>) -> anyhow::Result<()> {
+        self.faults.enter("bitmap.mul_set").await?;
+        self.inner.mul_set(key, list).await
+    }
+    async fn mul_get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        self.faults.enter("bitmap.mul_get").await?;
+        self.inner.mul_get(key).await
+    }
+    async fn get_bits(&self, key: &str, offsets: &[usize]) -> anyhow::Result<Vec<bool>> {
+        self.faults.enter("bitmap.get_bits").await?;
+        self.inner.get_bits(key, offsets).await
+    }
+    async fn count_ones(&self, key: &str) -> anyhow::Result<usize> {
+        self.faults.enter("bitmap.count_ones").await?;
+        self.inner.count_ones(key).await
+    }
+    fn backend(&self) -> &'static str {
+        "mock"
+    }
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        self.faults.enter("bitmap.del").await?;
+        self.inner.del(key).await
+    }
+    async fn put_bytes(&self, key: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+        self.faults.enter("bitmap.put_bytes").await?;
+        self.inner.put_bytes(key, bytes).await
+    }
+}
+
+// 基于FilterInfoMemory，每次调用先经过Faults
+#[derive(Clone, Default)]
+pub struct MockFiltersInfo {
+    inner: FilterInfoMemory,
+    faults: Faults,
+}
+
+impl MockFiltersInfo {
+    pub fn new(faults: Faults) -> Self {
+        Self {
+            inner: FilterInfoMemory::new(),
+            faults,
+        }
+    }
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+}
+
+#[async_trait::async_trait]
+impl FiltersInfo for MockFiltersInfo {
+    async fn list(&self, group: &str) -> anyhow::Result<Vec<(String, usize)>> {
+        self.faults.enter("info.list").await?;
+        self.inner.list(group).await
+    }
+    async fn count(&self, group: &str, key: &str) -> anyhow::Result<usize> {
+        self.faults.enter("info.count").await?;
+        self.inner.count(group, key).await
+    }
+    async fn add(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        self.faults.enter("info.add").await?;
+        self.inner.add(group, key, count).await
+    }
+    async fn set_count(&self, group: &str, key: &str, count: usize) -> anyhow::Result<()> {
+        self.faults.enter("info.set_count").await?;
+        self.inner.set_count(group, key, count).await
+    }
+    async fn register(
+        &self,
+        group: &str,
+        key: &str,
+        chunk: &ChunkRegistration,
+    ) -> anyhow::Result<()> {
+        self.faults.enter("info.register").await?;
+        self.inner.register(group, key, chunk).await
+    }
+    async fn specs(&self, group: &str) -> anyhow::Result<HashMap<String, ChunkSpec>> {
+        self.faults.enter("info.specs").await?;
+        self.inner.specs(group).await
+    }
+    async fn usage(&self, usage_key: &str) -> anyhow::Result<AppUsage> {
+        self.faults.enter("info.usage").await?;
+        self.inner.usage(usage_key).await
+    }
+    async fn groups(&self, layout: &dyn KeyLayout, appid: &str) -> anyhow::Result<Vec<String>> {
+        self.faults.enter("info.groups").await?;
+        self.inner.groups(layout, appid).await
+    }
+    async fn remove(&self, usage_key: &str, group: &str) -> anyhow::Result<()> {
+        self.faults.enter("info.remove").await?;
+        self.inner.remove(usage_key, group).await
+    }
+}
+
+// 共享同一个Faults的mock后端
+pub fn mock_backend() -> (MockFiltersInfo, MockBitmap, Faults) {
+    let faults = Faults::new();
+    (
+        MockFiltersInfo::new(faults.clone()),
+        MockBitmap::new(faults.clone()),
+        faults,
+    )
+}
+
+// 使用mock后端的策略，参数与 BloomExpandStrategy::build_from_memory 一致
+pub fn mock_strategy<A: Into<String>>(appid: A) -> (BloomExpandStrategy, Faults) {
+    let (info, bitmap, faults) = mock_backend();
+    let strategy = BloomExpandStrategy::new(
+        appid.into(),
+        info,
+        Strategy::Ladder(vec![100, 1000, 5000]),
+        bitmap,
+        0.001,
+        60 * 60,
+    );
+    (strategy, faults)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::{mock_backend, RedisCluster, RedisServer};
+    use crate::{Bitmap, FiltersInfo};
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_mock_backend() {
+        let (info, bitmap, faults) = mock_backend();
+        bitmap.mul_set("k", HashSet::from([1, 9])).await.unwrap();
+        assert_eq!(
+            bitmap.get_bits("k", &[1, 2, 9]).await.unwrap(),
+            vec![true, false, true]
+        );
+
+        faults.fail("bitmap.get_bits", 2);
+        assert!(bitmap.get_bits("k", &[1]).await.is_err());
+        assert!(bitmap.get_bits("k", &[1]).await.is_err());
+        assert!(bitmap.get_bits("k", &[1]).await.is_ok());
+        assert_eq!(faults.calls("bitmap.get_bits"), 4);
+
+        faults.fail_always("*");
+        assert!(info.add("g", "c0", 1).await.is_err());
+        assert!(bitmap.mul_get("k").await.is_err());
+        faults.clear();
+        info.add("g", "c0", 1).await.unwrap();
+        assert_eq!(info.count("g", "c0").await.unwrap(), 1);
+
+        faults.set_latency("info.list", Duration::from_millis(50));
+        let start = Instant::now();
+        info.list("g").await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(faults.calls("*"), 10);
+    }
+
+    #[tokio::test]
+    async fn test_redis_server() {
+        let Some(server) = RedisServer::try_start() else {
+            return;
+        };
+        let bitmap = crate::BitmapRedis::redis_single_node(server.url().as_str()).unwrap();
+        bitmap.mul_set("k", HashSet::from([0, 9])).await.unwrap();
+        assert_eq!(bitmap.mul_get("k").await.unwrap(), vec![0x80, 0x40]);
+    }
+
+    #[tokio::test]
+    async fn test_redis_cluster() {
+        let Some(cluster) = RedisCluster::try_start(3) else {
+            return;
+        };
+        assert_eq!(cluster.nodes().len(), 3);
+        let strategy =
+            crate::BloomExpandStrategy::build_from_url("biz02", cluster.url().as_str()).unwrap();
+        let pool = crate::FiltersPool::from(strategy.set_strategy_fixed(10));
+        let keys = (0..25).map(|i| format!("key_{}", i)).collect::<Vec<_>>();
+        pool.batch_insert("user001", keys.clone()).await.unwrap();
+        let result = pool.batch_contain("user001", keys).await.unwrap();
+        assert!(result.into_iter().all(|x| x));
+
+        // group key分布在不同主节点上，需要逐个节点扫描
+        let groups = (0..20).map(|i| format!("user{:03}", i)).collect::<Vec<_>>();
+        for group in groups.iter() {
+            pool.insert(group.as_str(), "a".into()).await.unwrap();
+        }
+        let strategy =
+            crate::BloomExpandStrategy::build_from_url("biz02", cluster.url().as_str()).unwrap();
+        assert_eq!(strategy.groups().await.unwrap(), groups);
+    }
+}
@@ -1,20 +1,22 @@
+use crate::key_layout::{DelimitedKeyLayout, KeyLayout, DEFAULT_KEY_PREFIX};
 use std::collections::hash_map::{DefaultHasher, RandomState};
-use std::hash::BuildHasher;
+use std::hash::{BuildHasher, Hasher};
 use std::mem;
 use wd_tools::MD5;
 
-pub const FILTER_PREFIX: &'static str = "SFP";
+pub const FILTER_PREFIX: &str = DEFAULT_KEY_PREFIX;
 
+// 使用默认的 DelimitedKeyLayout 组装group key
 pub fn assembly_prefix(appid: &str, group: &str) -> String {
-    format!("{}_{}_{}", FILTER_PREFIX, appid, group)
+    DelimitedKeyLayout::default().group_key(appid, group)
 }
+// 使用默认的 DelimitedKeyLayout 解析group key或者chunk key，返回(appid,group)
 pub fn analyze_prefix(key: &str) -> Option<(String, String)> {
-    let mut list = key.split("_").collect::<Vec<_>>();
-    if list.len() < 3 || list[0] != FILTER_PREFIX {
-        None
-    } else {
-        Some((list.remove(1).to_string(), list.remove(1).to_string()))
+    let layout = DelimitedKeyLayout::default();
+    if let Some(s) = layout.parse_group_key(key) {
+        return Some(s);
     }
+    layout.parse_chunk_key(key).map(|x| (x.appid, x.group))
 }
 #[allow(dead_code)]
 struct MyRandomState {
@@ -39,11 +41,16 @@ pub fn generate_hasher(seed: &str) -> DefaultHasher {
     let builder: RandomState = unsafe { std::ptr::read(bytes.as_ptr() as *const _) };
     builder.build_hasher()
 }
+// key按字节hash，写入方式与str的Hash实现相同，utf8的key与按&str传入时结果一致
+pub fn hash_key<H: Hasher>(hasher: &mut H, key: &[u8]) {
+    hasher.write(key);
+    hasher.write_u8(0xff);
+}
 
 #[cfg(test)]
 mod test {
-    use crate::util::generate_hasher;
-    use std::hash::Hasher;
+    use crate::util::{analyze_prefix, assembly_prefix, generate_hasher, hash_key};
+    use std::hash::{Hash, Hasher};
 
     #[test]
     fn test_generate_hasher() {
@@ -52,4 +59,27 @@ mod test {
         hasher.write("123".as_bytes());
         let _result = hasher.finish();
     }
+
+    #[test]
+    fn test_hash_key() {
+        for key in ["", "key_1", "中文"] {
+            let mut a = generate_hasher("123");
+            let mut b = generate_hasher("123");
+            key.hash(&mut a);
+            hash_key(&mut b, key.as_bytes());
+            assert_eq!(a.finish(), b.finish());
+        }
+    }
+
+    #[test]
+    fn test_analyze_prefix() {
+        let key = assembly_prefix("biz02", "user001");
+        let result = analyze_prefix(key.as_str());
+        assert_eq!(result, Some(("biz02".into(), "user001".into())));
+        let result = analyze_prefix(format!("{}_1704798000_0", key).as_str());
+        assert_eq!(result, Some(("biz02".into(), "user001".into())));
+        assert_eq!(analyze_prefix("SFP_biz02"), None);
+        // 默认不转义，名字中含分隔符的key无法解析
+        assert_eq!(analyze_prefix("SFP_biz_02_user_001"), None);
+    }
 }